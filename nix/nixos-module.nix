@@ -49,22 +49,75 @@ in
         '';
       };
 
-      gh = lib.mkOption {
-        type = lib.types.package;
-        default = pkgs.gh;
-        example = pkgs.gh;
+      github-token = lib.mkOption {
+        type = lib.types.str;
+        default = "";
         description = ''
-          gh equivalent executable to use for project creation.
+          GitHub Access Token for creating repos (github source host).
         '';
       };
 
-      github-token = lib.mkOption {
-        type = lib.types.str;
+      admins = lib.mkOption {
+        type = lib.types.listOf lib.types.str;
+        default = [ "eth:519ce4c129a981b2cbb4c3990b1391da24e8ebf3" ];
+        example = [ "eth:519ce4c129a981b2cbb4c3990b1391da24e8ebf3" ];
         description = ''
-          GitHub Access Token for creating repos.
+          Users allowed to use the admin api.
         '';
       };
 
+      sourceHost = {
+        backend = lib.mkOption {
+          type = lib.types.enum [
+            "github"
+            "gitea"
+            "local"
+          ];
+          default = "github";
+          example = "gitea";
+          description = ''
+            Where project repositories are hosted.
+          '';
+        };
+
+        owner = lib.mkOption {
+          type = lib.types.str;
+          default = "miniapp-factory";
+          example = "my-organization";
+          description = ''
+            User or organization owning all project repositories.
+          '';
+        };
+
+        template = lib.mkOption {
+          type = lib.types.str;
+          default = "OpenxAI-Network/miniapp-factory-template";
+          example = "my-organization/miniapp-template";
+          description = ''
+            Repository (owner/name) of the default template new projects are created from.
+          '';
+        };
+
+        gitea = {
+          url = lib.mkOption {
+            type = lib.types.str;
+            default = "";
+            example = "https://codeberg.org";
+            description = ''
+              Base url of the Gitea or Forgejo instance (gitea source host).
+            '';
+          };
+
+          token = lib.mkOption {
+            type = lib.types.str;
+            default = "";
+            description = ''
+              Gitea or Forgejo Access Token for creating repos (gitea source host).
+            '';
+          };
+        };
+      };
+
       database = lib.mkOption {
         type = lib.types.str;
         default = "postgres:miniapp-factory?host=/run/postgresql";
@@ -149,6 +202,339 @@ in
           The api key to use for hyperstack deployments.
         '';
       };
+
+      workerProvider = {
+        backend = lib.mkOption {
+          type = lib.types.enum [
+            "hyperstack"
+            "static"
+            "mock"
+          ];
+          default = "hyperstack";
+          example = "static";
+          description = ''
+            Where new worker servers are deployed when the queue grows. Static workers can not be deployed automatically.
+          '';
+        };
+
+        hyperstack = {
+          environment = lib.mkOption {
+            type = lib.types.str;
+            default = "default-NORWAY-1";
+            example = "CANADA-1";
+            description = ''
+              Hyperstack environment worker servers are deployed in.
+            '';
+          };
+
+          flavor = lib.mkOption {
+            type = lib.types.str;
+            default = "n3-RTX-A4000x1";
+            example = "n3-A100x1";
+            description = ''
+              Hyperstack flavor (hardware) of deployed worker servers.
+            '';
+          };
+        };
+
+        flavorPrices = lib.mkOption {
+          type = lib.types.attrsOf lib.types.int;
+          default = {
+            "n3-RTX-A4000x1" = 15;
+          };
+          example = {
+            "n3-RTX-A4000x1" = 15;
+            "static" = 5;
+          };
+          description = ''
+            Price in USD cents per hour of each worker flavor (static for registered workers), used for cost reporting.
+          '';
+        };
+
+        mock = {
+          url = lib.mkOption {
+            type = lib.types.str;
+            default = "http://127.0.0.1:34391";
+            example = "http://127.0.0.1:34391";
+            description = ''
+              Xnode manager all mock worker servers connect to (mock provider).
+            '';
+          };
+        };
+      };
+
+      watchdog = {
+        codingTimeout = lib.mkOption {
+          type = lib.types.int;
+          default = 3600;
+          example = 7200;
+          description = ''
+            Seconds a deployment is allowed to spend coding before it is requeued.
+          '';
+        };
+
+        imagegenTimeout = lib.mkOption {
+          type = lib.types.int;
+          default = 1800;
+          example = 3600;
+          description = ''
+            Seconds a deployment is allowed to spend generating images before it is requeued.
+          '';
+        };
+
+        imagegenQueueTimeout = lib.mkOption {
+          type = lib.types.int;
+          default = 7200;
+          example = 14400;
+          description = ''
+            Seconds after coding finished a deployment is allowed to wait for an imagegen worker (including imagegen retries) before it is marked as failed.
+          '';
+        };
+
+        rolloutTimeout = lib.mkOption {
+          type = lib.types.int;
+          default = 1800;
+          example = 3600;
+          description = ''
+            Seconds the mini app host is allowed to take to roll out a deployment before it is marked as failed.
+          '';
+        };
+
+        setupTimeout = lib.mkOption {
+          type = lib.types.int;
+          default = 3600;
+          example = 7200;
+          description = ''
+            Seconds a dynamic worker server is allowed to take to finish setup before it is undeployed.
+          '';
+        };
+
+        unreachableGrace = lib.mkOption {
+          type = lib.types.int;
+          default = 300;
+          example = 600;
+          description = ''
+            Seconds a worker server is allowed to be unreachable before it is reclaimed.
+          '';
+        };
+
+        maxRetries = lib.mkOption {
+          type = lib.types.int;
+          default = 2;
+          example = 5;
+          description = ''
+            Maximum amount of times a stuck deployment is requeued before it is marked as failed.
+          '';
+        };
+      };
+
+      scaling = {
+        minWorkers = lib.mkOption {
+          type = lib.types.int;
+          default = 0;
+          example = 1;
+          description = ''
+            Worker servers (including static ones) that are kept, even when there is nothing queued.
+          '';
+        };
+
+        maxWorkers = lib.mkOption {
+          type = lib.types.int;
+          default = 10;
+          example = 25;
+          description = ''
+            Maximum amount of worker servers.
+          '';
+        };
+
+        queuePerWorker = lib.mkOption {
+          type = lib.types.int;
+          default = 3;
+          example = 1;
+          description = ''
+            Queued deployments a single worker server is expected to handle, more queued deployments deploy extra worker servers.
+          '';
+        };
+
+        idleCooldown = lib.mkOption {
+          type = lib.types.int;
+          default = 900;
+          example = 3600;
+          description = ''
+            Seconds a deployed worker server has to be idle before it is undeployed.
+          '';
+        };
+
+        warmStandby = lib.mkOption {
+          type = lib.types.int;
+          default = 0;
+          example = 1;
+          description = ''
+            Idle worker servers kept ready on top of the ones needed for the queue.
+          '';
+        };
+
+        maxPaidWorkers = lib.mkOption {
+          type = lib.types.int;
+          default = 10;
+          example = 5;
+          description = ''
+            Maximum amount of deployed (paid) worker servers at the same time.
+          '';
+        };
+      };
+
+      pricing = {
+        changePrice = lib.mkOption {
+          type = lib.types.int;
+          default = 1000000;
+          example = 5000000;
+          description = ''
+            Credits charged for each change request.
+          '';
+        };
+
+        expressPrice = lib.mkOption {
+          type = lib.types.int;
+          default = 2000000;
+          example = 5000000;
+          description = ''
+            Extra credits charged for an express change request, which is scheduled before standard ones.
+          '';
+        };
+
+        freeChanges = lib.mkOption {
+          type = lib.types.int;
+          default = 3;
+          example = 0;
+          description = ''
+            Amount of change requests per project that are free of charge.
+          '';
+        };
+
+        dailyFreeChanges = lib.mkOption {
+          type = lib.types.int;
+          default = 0;
+          example = 1;
+          description = ''
+            Amount of change requests per account per day that are free of charge.
+          '';
+        };
+      };
+
+      queue = {
+        workerParallelism = lib.mkOption {
+          type = lib.types.int;
+          default = 16;
+          example = 64;
+          description = ''
+            Maximum amount of worker servers that are set up, assigned or checked at the same time.
+          '';
+        };
+
+        sessionLifetime = lib.mkOption {
+          type = lib.types.int;
+          default = 1800;
+          example = 600;
+          description = ''
+            Seconds a login session with the xnode manager of a worker server is reused before logging in again.
+          '';
+        };
+
+        maxAccountDeployments = lib.mkOption {
+          type = lib.types.int;
+          default = 2;
+          example = 1;
+          description = ''
+            Maximum amount of deployments of a single account that are worked on at the same time.
+          '';
+        };
+      };
+
+      transcripts = {
+        maxBytes = lib.mkOption {
+          type = lib.types.int;
+          default = 1048576;
+          example = 262144;
+          description = ''
+            Maximum size of a stored coder chat history or imagegen log, only the end is kept of larger outputs.
+          '';
+        };
+
+        retention = lib.mkOption {
+          type = lib.types.int;
+          default = 2592000;
+          example = 604800;
+          description = ''
+            Seconds stored coder chat histories and imagegen logs are kept.
+          '';
+        };
+      };
+
+      migrationAttempts = lib.mkOption {
+        type = lib.types.int;
+        default = 5;
+        example = 10;
+        description = ''
+          Amount of times a step of moving a project between mini app hosts is attempted before the migration is marked as failed, failed migrations can be resumed through the admin api.
+        '';
+      };
+
+      provisioningBudget = lib.mkOption {
+        type = lib.types.int;
+        default = 600;
+        example = 1800;
+        description = ''
+          Seconds a project creation step is retried (with backoff) after it first failed, before the project is rolled back and refunded.
+        '';
+      };
+
+      miniappHost = {
+        url = lib.mkOption {
+          type = lib.types.str;
+          default = "https://miniapp-host.xnode-manager.openxai.org";
+          example = "https://miniapp-host.example.com";
+          description = ''
+            Xnode manager of the initial mini app host, only used when the host registry is empty.
+          '';
+        };
+
+        domain = lib.mkOption {
+          type = lib.types.str;
+          default = "miniapp-host.xnode-manager.openxai.org";
+          example = "miniapp-host.example.com";
+          description = ''
+            Domain to log in to the xnode manager of the initial mini app host with.
+          '';
+        };
+
+        capacity = lib.mkOption {
+          type = lib.types.int;
+          default = 10000;
+          example = 2000;
+          description = ''
+            Maximum amount of projects placed on the initial mini app host, other hosts are registered through the admin api.
+          '';
+        };
+
+        exposeTimeout = lib.mkOption {
+          type = lib.types.int;
+          default = 3600;
+          example = 7200;
+          description = ''
+            Seconds a project creation or migration step is allowed to wait for a mini app host to expose the project before the step fails.
+          '';
+        };
+      };
+
+      instanceId = lib.mkOption {
+        type = lib.types.nullOr lib.types.str;
+        default = null;
+        example = "factory-1";
+        description = ''
+          Name of this instance when multiple factory instances share a database, a random one is generated by default.
+        '';
+      };
     };
   };
 
@@ -163,14 +549,24 @@ in
       wantedBy = [ "multi-user.target" ];
       description = "AI-powered application to allow creation of Farcaster mini apps with natural language.";
       after = [ "network.target" ];
+      path = [ pkgs.git ];
       environment = {
         HOSTNAME = cfg.hostname;
         PORT = toString cfg.port;
         RUST_LOG = cfg.verbosity;
         DATADIR = cfg.dataDir;
         GH_TOKEN = cfg.github-token;
-        GH = "${cfg.gh}/bin/";
+        ADMINS = lib.concatStringsSep "," cfg.admins;
+        SOURCEHOST = cfg.sourceHost.backend;
+        SOURCEOWNER = cfg.sourceHost.owner;
+        SOURCETEMPLATE = cfg.sourceHost.template;
+        GITEAURL = cfg.sourceHost.gitea.url;
+        GITEATOKEN = cfg.sourceHost.gitea.token;
         DATABASE = cfg.database;
+        MINIAPPHOSTURL = cfg.miniappHost.url;
+        MINIAPPHOSTDOMAIN = cfg.miniappHost.domain;
+        MINIAPPHOSTCAPACITY = toString cfg.miniappHost.capacity;
+        EXPOSETIMEOUT = toString cfg.miniappHost.exposeTimeout;
         NFTMINTERKEY = cfg.nftminterkey;
         HTTPRPC = cfg.rpc.http;
         WSRPC = cfg.rpc.ws;
@@ -178,7 +574,39 @@ in
         OPENX = cfg.contracts.openx;
         NFT = cfg.contracts.nft;
         HYPERSTACKAPIKEY = cfg.hyperstackapikey;
-      };
+        WORKERPROVIDER = cfg.workerProvider.backend;
+        HYPERSTACKENVIRONMENT = cfg.workerProvider.hyperstack.environment;
+        HYPERSTACKFLAVOR = cfg.workerProvider.hyperstack.flavor;
+        FLAVORPRICES = lib.concatStringsSep "," (
+          lib.mapAttrsToList (flavor: price: "${flavor}=${toString price}") cfg.workerProvider.flavorPrices
+        );
+        MOCKWORKERURL = cfg.workerProvider.mock.url;
+        CODINGTIMEOUT = toString cfg.watchdog.codingTimeout;
+        IMAGEGENTIMEOUT = toString cfg.watchdog.imagegenTimeout;
+        IMAGEGENQUEUETIMEOUT = toString cfg.watchdog.imagegenQueueTimeout;
+        ROLLOUTTIMEOUT = toString cfg.watchdog.rolloutTimeout;
+        SETUPTIMEOUT = toString cfg.watchdog.setupTimeout;
+        UNREACHABLEGRACE = toString cfg.watchdog.unreachableGrace;
+        MAXRETRIES = toString cfg.watchdog.maxRetries;
+        MINWORKERS = toString cfg.scaling.minWorkers;
+        MAXWORKERS = toString cfg.scaling.maxWorkers;
+        QUEUEPERWORKER = toString cfg.scaling.queuePerWorker;
+        IDLECOOLDOWN = toString cfg.scaling.idleCooldown;
+        WARMSTANDBY = toString cfg.scaling.warmStandby;
+        MAXPAIDWORKERS = toString cfg.scaling.maxPaidWorkers;
+        CHANGEPRICE = toString cfg.pricing.changePrice;
+        EXPRESSPRICE = toString cfg.pricing.expressPrice;
+        FREECHANGES = toString cfg.pricing.freeChanges;
+        DAILYFREECHANGES = toString cfg.pricing.dailyFreeChanges;
+        MAXACCOUNTDEPLOYMENTS = toString cfg.queue.maxAccountDeployments;
+        WORKERPARALLELISM = toString cfg.queue.workerParallelism;
+        SESSIONLIFETIME = toString cfg.queue.sessionLifetime;
+        PROVISIONINGBUDGET = toString cfg.provisioningBudget;
+        MIGRATIONATTEMPTS = toString cfg.migrationAttempts;
+        LOGMAXBYTES = toString cfg.transcripts.maxBytes;
+        LOGRETENTION = toString cfg.transcripts.retention;
+      }
+      // lib.optionalAttrs (cfg.instanceId != null) { INSTANCEID = cfg.instanceId; };
       serviceConfig = {
         ExecStart = "${lib.getExe miniapp-factory}";
         User = "miniapp-factory";
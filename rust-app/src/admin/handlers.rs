@@ -0,0 +1,717 @@
+use std::collections::HashMap;
+
+use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
+use hex::ToHex;
+use sqlx::types::Json;
+
+use crate::{
+    admin::models::{
+        CostRange, Leaders, MigrationResume, MiniappHostLoad, MiniappHostRegistration,
+        MiniappHostUpdate, ProjectMigration, Scaling, TemplateAddition, TemplateUpdate,
+        WorkerDrain, WorkerRegistration, WorkerRemoval,
+    },
+    database::{
+        Database,
+        host_migrations::{DatabaseHostMigration, MigrationStatus},
+        leaders::DatabaseLeader,
+        miniapp_hosts::DatabaseMiniappHost,
+        projects::{DatabaseProject, network_of},
+        scaling_decisions::DatabaseScalingDecision,
+        templates::DatabaseTemplate,
+        worker_servers::{DatabaseWorkerServer, WorkerProviderKind, WorkerRole},
+    },
+    utils::{
+        auth::{get_session, is_admin},
+        autoscaler::ScalingPolicy,
+        costs::{daily_costs, deployment_costs, project_costs},
+        env::flavorprices,
+        error::ResponseError,
+        leader::instance,
+        sessions::forget_worker,
+        time::get_time_i64,
+        wallet::get_signer,
+    },
+    worker_provider::{
+        WorkerProvider, WorkerStatus,
+        static_pool::{StaticHandle, StaticPool},
+    },
+};
+
+fn admin_user(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+        .filter(|user| is_admin(user))
+}
+
+/// Defaults to the last 30 days
+fn cost_range(range: &CostRange) -> (i64, i64) {
+    let until = range.until.unwrap_or_else(get_time_i64);
+    let since = range.since.unwrap_or(until - 30 * 24 * 60 * 60);
+    (since, until)
+}
+
+#[get("/templates")]
+async fn templates(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseTemplate::get_all(&database).await {
+        Ok(templates) => HttpResponse::Ok().json(templates),
+        Err(e) => {
+            log::error!("Could not get templates from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/template/add")]
+async fn template_add(
+    database: web::Data<Database>,
+    data: web::Json<TemplateAddition>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let data = data.into_inner();
+    let mut template = DatabaseTemplate {
+        id: 0,
+        name: data.name,
+        description: data.description,
+        repository: data.repository,
+        module: data.module,
+        options: Json::from(data.options),
+        enabled: data.enabled,
+    };
+    if let Err(e) = template.insert(&database).await {
+        log::error!("Could not insert {template:?} into the database: {e}");
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Could not add template {name}.",
+            name = template.name
+        )));
+    }
+
+    HttpResponse::Ok().json(template.id)
+}
+
+#[post("/template/update")]
+async fn template_update(
+    database: web::Data<Database>,
+    data: web::Json<TemplateUpdate>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseTemplate::get_by_id(&database, data.id).await {
+        Ok(template) => {
+            if template.is_none() {
+                return HttpResponse::NotFound().finish();
+            }
+        }
+        Err(e) => {
+            log::error!(
+                "Could not get template {id} from the database: {e}",
+                id = data.id
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let data = data.into_inner();
+    let template = DatabaseTemplate {
+        id: data.id,
+        name: data.name,
+        description: data.description,
+        repository: data.repository,
+        module: data.module,
+        options: Json::from(data.options),
+        enabled: data.enabled,
+    };
+    if let Err(e) = template.update(&database).await {
+        log::error!("Could not update {template:?} in the database: {e}");
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Could not update template {id}.",
+            id = template.id
+        )));
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[get("/workers")]
+async fn workers(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseWorkerServer::get_all(&database).await {
+        Ok(workers) => HttpResponse::Ok().json(workers),
+        Err(e) => {
+            log::error!("Could not get worker servers from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/worker/register")]
+async fn worker_register(
+    database: web::Data<Database>,
+    data: web::Json<WorkerRegistration>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let data = data.into_inner();
+    let handle = match serde_json::to_value(StaticHandle {
+        url: data.url.trim_end_matches('/').to_string(),
+        domain: data.domain,
+    }) {
+        Ok(handle) => handle,
+        Err(e) => {
+            log::error!("Could not serialize static worker handle: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match StaticPool.status(&handle).await {
+        Ok(WorkerStatus::Running) => {}
+        Ok(_) => {
+            let addr: String = get_signer().public().address().encode_hex();
+            return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                "Could not log in to xnode manager {url}, make sure it is owned by eth:{addr}.",
+                url = data.url
+            )));
+        }
+        Err(e) => {
+            log::error!("Could not get status of static worker {handle}: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let flavor = StaticPool.flavor();
+    let mut server = DatabaseWorkerServer {
+        id: 0,
+        provider: WorkerProviderKind::Static,
+        handle: Json::from(handle),
+        coder_deployment: None,
+        imagegen_deployment: None,
+        setup_finished: false,
+        assignment: None,
+        dynamic: false,
+        unreachable_since: None,
+        draining: false,
+        idle_since: Some(get_time_i64()),
+        hourly_price: flavorprices().get(&flavor).copied().unwrap_or_default(),
+        flavor,
+        deleted_at: None,
+        role: data.role.unwrap_or(WorkerRole::Both),
+        assigned_at: None,
+        claim_backoff_until: None,
+    };
+    if let Err(e) = server.insert(&database).await {
+        log::error!("Could not insert {server:?} into the database: {e}");
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(server.id)
+}
+
+#[post("/worker/drain")]
+async fn worker_drain(
+    database: web::Data<Database>,
+    data: web::Json<WorkerDrain>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let mut server = match DatabaseWorkerServer::get_by_id(&database, data.id).await {
+        Ok(server) => match server {
+            Some(server) => server,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get worker server {id} from the database: {e}",
+                id = data.id
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if let Err(e) = server.update_draining(&database, data.draining).await {
+        log::error!(
+            "Could not set draining of worker server {id} to {draining}: {e}",
+            id = server.id,
+            draining = data.draining
+        );
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[post("/worker/remove")]
+async fn worker_remove(
+    database: web::Data<Database>,
+    data: web::Json<WorkerRemoval>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let mut server = match DatabaseWorkerServer::get_by_id(&database, data.id).await {
+        Ok(server) => match server {
+            Some(server) => server,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get worker server {id} from the database: {e}",
+                id = data.id
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if server.dynamic {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Worker server {id} is managed by the {provider} provider.",
+            id = server.id,
+            provider = server.provider
+        )));
+    }
+
+    if let Some(assignment) = server.assignment {
+        return HttpResponse::Conflict().json(ResponseError::new(format!(
+            "Worker server {id} is working on deployment {assignment}, drain it first.",
+            id = server.id
+        )));
+    }
+
+    forget_worker(server.id);
+    if let Err(e) = server.delete(&database).await {
+        log::error!(
+            "Could not remove worker server {id} from the database: {e}",
+            id = server.id
+        );
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[get("/miniapp_hosts")]
+async fn miniapp_hosts(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let hosts = match DatabaseMiniappHost::get_all(&database).await {
+        Ok(hosts) => hosts,
+        Err(e) => {
+            log::error!("Could not get mini app hosts from the database: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let projects: HashMap<i32, i64> =
+        match DatabaseProject::get_count_by_miniapp_host(&database).await {
+            Ok(projects) => projects.into_iter().collect(),
+            Err(e) => {
+                log::error!("Could not get project count per mini app host from the database: {e}");
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+
+    HttpResponse::Ok().json(
+        hosts
+            .into_iter()
+            .map(|host| MiniappHostLoad {
+                projects: projects.get(&host.id).copied().unwrap_or_default(),
+                host,
+            })
+            .collect::<Vec<MiniappHostLoad>>(),
+    )
+}
+
+#[post("/miniapp_host/register")]
+async fn miniapp_host_register(
+    database: web::Data<Database>,
+    data: web::Json<MiniappHostRegistration>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let data = data.into_inner();
+    let url = data.url.trim_end_matches('/').to_string();
+    if get_session(&url, &data.domain).await.is_err() {
+        let addr: String = get_signer().public().address().encode_hex();
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Could not log in to xnode manager {url}, make sure it is owned by eth:{addr}."
+        )));
+    }
+
+    let mut host = DatabaseMiniappHost {
+        id: 0,
+        url,
+        domain: data.domain,
+        capacity: data.capacity,
+        draining: false,
+        created_at: get_time_i64(),
+        exposed: None,
+        rebuild_exposed: None,
+        rebuild_request: None,
+        synced_at: None,
+        drifted_at: None,
+        rebuild_failures: 0,
+        rebuild_failed_at: None,
+    };
+    if let Err(e) = host.insert(&database).await {
+        log::error!("Could not insert {host:?} into the database: {e}");
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(host.id)
+}
+
+#[post("/miniapp_host/update")]
+async fn miniapp_host_update(
+    database: web::Data<Database>,
+    data: web::Json<MiniappHostUpdate>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let mut host = match DatabaseMiniappHost::get_by_id(&database, data.id).await {
+        Ok(host) => match host {
+            Some(host) => host,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get mini app host {id} from the database: {e}",
+                id = data.id
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if let Err(e) = host
+        .update_capacity(&database, data.capacity, data.draining)
+        .await
+    {
+        log::error!(
+            "Could not update capacity of mini app host {id}: {e}",
+            id = host.id
+        );
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[get("/host_migrations")]
+async fn host_migrations(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseHostMigration::get_all(&database).await {
+        Ok(migrations) => HttpResponse::Ok().json(migrations),
+        Err(e) => {
+            log::error!("Could not get host migrations from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Projects whose container could not be re-applied, the reconciler stops retrying after a few failures
+#[get("/reconcile_failures")]
+async fn reconcile_failures(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseProject::get_all_reconcile_failed(&database).await {
+        Ok(projects) => HttpResponse::Ok().json(projects),
+        Err(e) => {
+            log::error!("Could not get reconcile failed projects from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[post("/project/migrate")]
+async fn project_migrate(
+    database: web::Data<Database>,
+    data: web::Json<ProjectMigration>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let project = match DatabaseProject::get_by_name(&database, &data.project).await {
+        Ok(project) => match project {
+            Some(project) => project,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get project {project} from the database: {e}",
+                project = data.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let source = match project.miniapp_host {
+        Some(source) => source,
+        None => {
+            return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                "Project {project} is not placed on a mini app host.",
+                project = project.name
+            )));
+        }
+    };
+    if source == data.target {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Project {project} is already on mini app host {target}.",
+            project = project.name,
+            target = data.target
+        )));
+    }
+
+    match DatabaseHostMigration::get_unfinished_by_project(&database, &project.name).await {
+        Ok(None) => {}
+        Ok(Some(migration)) => {
+            return HttpResponse::Conflict().json(ResponseError::new(format!(
+                "Project {project} is already being moved by host migration {id}.",
+                project = project.name,
+                id = migration.id
+            )));
+        }
+        Err(e) => {
+            log::error!(
+                "Could not get host migration of project {project} from the database: {e}",
+                project = project.name
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let target = match DatabaseMiniappHost::get_by_id(&database, data.target).await {
+        Ok(target) => match target {
+            Some(target) => target,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get mini app host {id} from the database: {e}",
+                id = data.target
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let hosted = match DatabaseProject::get_count_by_miniapp_host(&database).await {
+        Ok(projects) => projects
+            .into_iter()
+            .find_map(|(host, projects)| (host == target.id).then_some(projects))
+            .unwrap_or_default(),
+        Err(e) => {
+            log::error!("Could not get project count per mini app host from the database: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if target.draining || hosted >= target.capacity.into() {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Mini app host {id} does not accept new projects.",
+            id = target.id
+        )));
+    }
+
+    let mut migration = DatabaseHostMigration {
+        id: 0,
+        project: project.name,
+        source,
+        target: target.id,
+        network: network_of(hosted),
+        status: MigrationStatus::Container,
+        attempts: 0,
+        error: None,
+        created_at: get_time_i64(),
+        finished_at: None,
+        failed_step: None,
+        flake: None,
+        container_request: None,
+        remove_request: None,
+        step_started_at: None,
+    };
+    if let Err(e) = migration.insert(&database).await {
+        log::error!("Could not insert {migration:?} into the database: {e}");
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().json(migration.id)
+}
+
+#[post("/host_migration/resume")]
+async fn host_migration_resume(
+    database: web::Data<Database>,
+    data: web::Json<MigrationResume>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let mut migration = match DatabaseHostMigration::get_by_id(&database, data.id).await {
+        Ok(migration) => match migration {
+            Some(migration) => migration,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get host migration {id} from the database: {e}",
+                id = data.id
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if migration.status != MigrationStatus::Failed {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "Host migration {id} has not failed.",
+            id = migration.id
+        )));
+    }
+
+    if let Err(e) = migration.update_resumed(&database).await {
+        log::error!(
+            "Could not resume host migration {id}: {e}",
+            id = migration.id
+        );
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    HttpResponse::Ok().finish()
+}
+
+#[get("/scaling")]
+async fn scaling(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseScalingDecision::get_latest(&database, 100).await {
+        Ok(decisions) => HttpResponse::Ok().json(Scaling {
+            policy: ScalingPolicy::from_env(),
+            decisions,
+        }),
+        Err(e) => {
+            log::error!("Could not get scaling decisions from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/costs/deployments")]
+async fn costs_deployments(
+    database: web::Data<Database>,
+    range: web::Query<CostRange>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let (since, until) = cost_range(&range);
+    match deployment_costs(&database, since, until).await {
+        Ok(costs) => HttpResponse::Ok().json(costs),
+        Err(e) => {
+            log::error!("Could not compute deployment costs from {since} until {until}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/costs/projects")]
+async fn costs_projects(
+    database: web::Data<Database>,
+    range: web::Query<CostRange>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let (since, until) = cost_range(&range);
+    match project_costs(&database, since, until).await {
+        Ok(costs) => HttpResponse::Ok().json(costs),
+        Err(e) => {
+            log::error!("Could not compute project costs from {since} until {until}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/costs/days")]
+async fn costs_days(
+    database: web::Data<Database>,
+    range: web::Query<CostRange>,
+    req: HttpRequest,
+) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let (since, until) = cost_range(&range);
+    match daily_costs(&database, since, until).await {
+        Ok(costs) => HttpResponse::Ok().json(costs),
+        Err(e) => {
+            log::error!("Could not compute daily costs from {since} until {until}: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/leaders")]
+async fn leaders(database: web::Data<Database>, req: HttpRequest) -> impl Responder {
+    if admin_user(&req).is_none() {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseLeader::get_all(&database).await {
+        Ok(leaders) => HttpResponse::Ok().json(Leaders {
+            instance: instance(),
+            leaders,
+        }),
+        Err(e) => {
+            log::error!("Could not get leaders from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
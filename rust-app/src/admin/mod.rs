@@ -0,0 +1,26 @@
+use actix_web::web::ServiceConfig;
+
+pub mod handlers;
+pub mod models;
+
+pub fn configure(cfg: &mut ServiceConfig) {
+    cfg.service(handlers::templates);
+    cfg.service(handlers::template_add);
+    cfg.service(handlers::template_update);
+    cfg.service(handlers::workers);
+    cfg.service(handlers::worker_register);
+    cfg.service(handlers::worker_drain);
+    cfg.service(handlers::worker_remove);
+    cfg.service(handlers::miniapp_hosts);
+    cfg.service(handlers::miniapp_host_register);
+    cfg.service(handlers::miniapp_host_update);
+    cfg.service(handlers::host_migrations);
+    cfg.service(handlers::reconcile_failures);
+    cfg.service(handlers::project_migrate);
+    cfg.service(handlers::host_migration_resume);
+    cfg.service(handlers::scaling);
+    cfg.service(handlers::costs_deployments);
+    cfg.service(handlers::costs_projects);
+    cfg.service(handlers::costs_days);
+    cfg.service(handlers::leaders);
+}
@@ -0,0 +1,102 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    database::{
+        leaders::DatabaseLeader, miniapp_hosts::DatabaseMiniappHost,
+        scaling_decisions::DatabaseScalingDecision, templates::TemplateOptions,
+        worker_servers::WorkerRole,
+    },
+    utils::autoscaler::ScalingPolicy,
+};
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplateAddition {
+    pub name: String,
+    pub description: String,
+    pub repository: String,
+    pub module: String,
+    pub options: TemplateOptions,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TemplateUpdate {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub repository: String,
+    pub module: String,
+    pub options: TemplateOptions,
+    pub enabled: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorkerRegistration {
+    pub url: String,
+    pub domain: String,
+    /// Defaults to both coder and imagegen
+    pub role: Option<WorkerRole>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorkerDrain {
+    pub id: i32,
+    pub draining: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct WorkerRemoval {
+    pub id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MiniappHostLoad {
+    pub host: DatabaseMiniappHost,
+    /// Projects placed on the host
+    pub projects: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MiniappHostRegistration {
+    pub url: String,
+    pub domain: String,
+    pub capacity: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MiniappHostUpdate {
+    pub id: i32,
+    pub capacity: i32,
+    pub draining: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ProjectMigration {
+    pub project: String,
+    /// Mini app host to move the project to
+    pub target: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct MigrationResume {
+    pub id: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Scaling {
+    pub policy: ScalingPolicy,
+    pub decisions: Vec<DatabaseScalingDecision>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CostRange {
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Leaders {
+    /// Instance that handled this request
+    pub instance: String,
+    pub leaders: Vec<DatabaseLeader>,
+}
@@ -1,5 +1,5 @@
 use alloy::providers::{ProviderBuilder, WsConnect};
-use tokio::{spawn, try_join};
+use tokio::join;
 
 use crate::{database::Database, utils::env::wsrpc};
 
@@ -12,10 +12,9 @@ pub async fn start_event_listeners(database: Database) {
         .await
         .unwrap_or_else(|e| panic!("Could not connect to WS rpc provider: {e}"));
 
-    if let Err(e) = try_join!(
-        spawn(credits::event_listeners(provider.clone(), database.clone())),
-        spawn(nft::event_listeners(provider.clone(), database.clone())),
-    ) {
-        panic!("Event listener error: {e}");
-    }
+    // Not spawned, both listeners stop when the leader task running them is aborted
+    join!(
+        credits::event_listeners(provider.clone(), database.clone()),
+        nft::event_listeners(provider.clone(), database.clone()),
+    );
 }
@@ -2,7 +2,7 @@ use serde::{Deserialize, Serialize};
 use sqlx::{Error, FromRow, query, query_as, query_scalar};
 
 use crate::{
-    database::{Database, DatabaseConnection, promo_code::DatabasePromoCode},
+    database::{Database, DatabaseConnection, DatabaseTransaction, promo_code::DatabasePromoCode},
     utils::time::get_time_i64,
 };
 
@@ -85,6 +85,19 @@ impl DatabaseCredits {
             .await
     }
 
+    /// Serializes charges of an account until the transaction ends, there is no account row to lock
+    pub async fn lock_account_in_transaction(
+        transaction: &mut DatabaseTransaction,
+        account: &str,
+    ) -> Result<(), Error> {
+        query("SELECT pg_advisory_xact_lock(hashtext($1));")
+            .bind(account)
+            .execute(&mut **transaction)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn insert(&self, database: &Database) -> Result<(), Error> {
         let Self {
             account,
@@ -103,6 +116,28 @@ impl DatabaseCredits {
 
         Ok(())
     }
+
+    pub async fn insert_in_transaction(
+        &self,
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<(), Error> {
+        let Self {
+            account,
+            credits,
+            description,
+            date,
+        } = self;
+
+        query("INSERT INTO credits(account, credits, description, date) VALUES ($1, $2, $3, $4);")
+            .bind(account)
+            .bind(credits)
+            .bind(description)
+            .bind(date)
+            .execute(&mut **transaction)
+            .await?;
+
+        Ok(())
+    }
 }
 
 #[derive(Debug)]
@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, query, query_as, query_scalar};
+
+use crate::database::{Database, DatabaseConnection, deployments::DeploymentStatus};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS deployment_logs(id SERIAL PRIMARY KEY, deployment INT4 NOT NULL, phase TEXT NOT NULL, content TEXT NOT NULL, truncated BOOLEAN NOT NULL, captured_at INT8 NOT NULL)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create deployment_logs table: {e}"));
+
+    sqlx::raw_sql(
+        "CREATE INDEX IF NOT EXISTS deployment_logs_deployment ON deployment_logs(deployment)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create deployment_logs index: {e}"));
+}
+
+/// Output of a worker phase (coding chat history or imagegen logs), captured before the worker is released
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseDeploymentLog {
+    pub id: i32,
+    pub deployment: i32,
+    pub phase: DeploymentStatus,
+    pub content: String,
+    pub truncated: bool,
+    pub captured_at: i64,
+}
+
+impl DatabaseDeploymentLog {
+    pub async fn get_all_by_deployment(
+        database: &Database,
+        deployment: i32,
+    ) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, deployment, phase, content, truncated, captured_at FROM deployment_logs WHERE deployment = $1 ORDER BY id ASC",
+        )
+        .bind(deployment)
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_latest_by_deployment(
+        database: &Database,
+        deployment: i32,
+    ) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, deployment, phase, content, truncated, captured_at FROM deployment_logs WHERE deployment = $1 ORDER BY id DESC LIMIT 1",
+        )
+        .bind(deployment)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO deployment_logs(deployment, phase, content, truncated, captured_at) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+            .bind(self.deployment)
+            .bind(self.phase)
+            .bind(&self.content)
+            .bind(self.truncated)
+            .bind(self.captured_at)
+            .fetch_one(&database.connection)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
+    pub async fn delete_all_before(database: &Database, before: i64) -> Result<u64, Error> {
+        let result = query("DELETE FROM deployment_logs WHERE captured_at < $1;")
+            .bind(before)
+            .execute(&database.connection)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
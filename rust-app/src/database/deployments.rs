@@ -1,15 +1,193 @@
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
-use sqlx::{Error, FromRow, query, query_as, query_scalar};
+use sqlx::{Error, FromRow, Type, query, query_as, query_scalar};
 
-use crate::database::{Database, DatabaseConnection};
+use crate::database::{Database, DatabaseConnection, DatabaseTransaction};
 
 pub async fn create_table(connection: &DatabaseConnection) {
     sqlx::raw_sql(
-        "CREATE TABLE IF NOT EXISTS deployments(id SERIAL PRIMARY KEY, project TEXT NOT NULL, instructions TEXT NOT NULL, submitted_at INT8 NOT NULL, coding_started_at INT8, coding_finished_at INT8, coding_git_hash TEXT, imagegen_started_at INT8, imagegen_finished_at INT8, imagegen_git_hash TEXT, deployment_request INT8, deleted BOOL NOT NULL)",
+        "CREATE TABLE IF NOT EXISTS deployments(id SERIAL PRIMARY KEY, project TEXT NOT NULL, instructions TEXT NOT NULL, submitted_at INT8 NOT NULL, coding_started_at INT8, coding_finished_at INT8, coding_git_hash TEXT, imagegen_started_at INT8, imagegen_finished_at INT8, imagegen_git_hash TEXT, deployment_request INT8, deleted BOOL NOT NULL, status TEXT NOT NULL, failure_reason TEXT, retries INT4 NOT NULL, account TEXT, credits INT8 NOT NULL, imagegen_mode TEXT NOT NULL, priority TEXT NOT NULL, rollout_finished_at INT8, rollout_error TEXT)",
     )
     .execute(connection)
     .await
     .unwrap_or_else(|e| panic!("Could not create projects table: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE deployments ADD COLUMN IF NOT EXISTS status TEXT;
+UPDATE deployments SET status = CASE
+    WHEN deployment_request IS NOT NULL THEN 'live'
+    WHEN imagegen_finished_at IS NOT NULL THEN 'failed'
+    WHEN imagegen_started_at IS NOT NULL THEN 'imagegen'
+    WHEN coding_started_at IS NOT NULL THEN 'coding'
+    ELSE 'queued'
+END WHERE status IS NULL;
+UPDATE deployments SET status = 'superseded' WHERE status = 'live' AND EXISTS (SELECT 1 FROM deployments newer WHERE newer.project = deployments.project AND newer.status = 'live' AND newer.id > deployments.id);
+ALTER TABLE deployments ALTER COLUMN status SET NOT NULL;
+ALTER TABLE deployments ADD COLUMN IF NOT EXISTS failure_reason TEXT",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate deployments status: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE deployments ADD COLUMN IF NOT EXISTS retries INT4 NOT NULL DEFAULT 0",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate deployments retries: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE deployments ADD COLUMN IF NOT EXISTS account TEXT, ADD COLUMN IF NOT EXISTS credits INT8 NOT NULL DEFAULT 0",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate deployments credits: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE deployments ADD COLUMN IF NOT EXISTS imagegen_mode TEXT NOT NULL DEFAULT 'auto'",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate deployments imagegen mode: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE deployments ADD COLUMN IF NOT EXISTS priority TEXT NOT NULL DEFAULT 'standard'",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate deployments priority: {e}"));
+
+    // Rollouts of earlier deployments are not followed anymore, their outcome is already reflected by their status
+    sqlx::raw_sql(
+        "DO $$ BEGIN IF NOT EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'deployments' AND column_name = 'rollout_finished_at') THEN ALTER TABLE deployments ADD COLUMN rollout_finished_at INT8, ADD COLUMN rollout_error TEXT; UPDATE deployments SET rollout_finished_at = submitted_at WHERE deployment_request IS NOT NULL AND status <> 'deploying'; END IF; END $$",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate deployments rollout: {e}"));
+
+    // Wake up the dispatcher when a deployment is (re)queued
+    sqlx::raw_sql(
+        "CREATE OR REPLACE FUNCTION notify_deployments_dispatch()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('dispatch', 'deployments');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create notify_deployments_dispatch function: {e}"));
+
+    sqlx::raw_sql(
+        "CREATE OR REPLACE TRIGGER trg_deployments_dispatch
+AFTER INSERT OR UPDATE OF status ON deployments
+FOR EACH ROW
+WHEN (NEW.status IN ('queued', 'imagegen_queued'))
+EXECUTE FUNCTION notify_deployments_dispatch()",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create trg_deployments_dispatch trigger: {e}"));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum DeploymentStatus {
+    Queued,
+    Coding,
+    /// Coding finished, waiting for an imagegen worker
+    ImagegenQueued,
+    Imagegen,
+    Deploying,
+    Live,
+    Failed,
+    Cancelled,
+    Superseded,
+}
+
+impl DeploymentStatus {
+    pub fn can_transition_to(&self, next: DeploymentStatus) -> bool {
+        use DeploymentStatus::*;
+
+        matches!(
+            (self, next),
+            (Queued, Coding | Failed | Cancelled)
+                | (
+                    Coding,
+                    Queued | ImagegenQueued | Deploying | Failed | Cancelled
+                )
+                | (ImagegenQueued, Imagegen | Failed | Cancelled)
+                | (
+                    Imagegen,
+                    Queued | ImagegenQueued | Deploying | Failed | Cancelled
+                )
+                | (Deploying, Live | Failed)
+                | (Live, Superseded)
+                | (Superseded, Live)
+        )
+    }
+}
+
+impl Display for DeploymentStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            DeploymentStatus::Queued => "queued",
+            DeploymentStatus::Coding => "coding",
+            DeploymentStatus::ImagegenQueued => "imagegen_queued",
+            DeploymentStatus::Imagegen => "imagegen",
+            DeploymentStatus::Deploying => "deploying",
+            DeploymentStatus::Live => "live",
+            DeploymentStatus::Failed => "failed",
+            DeploymentStatus::Cancelled => "cancelled",
+            DeploymentStatus::Superseded => "superseded",
+        };
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ImagegenMode {
+    /// Generate images when the coder reports that assets changed
+    #[default]
+    Auto,
+    Force,
+    Skip,
+}
+
+impl Display for ImagegenMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mode = match self {
+            ImagegenMode::Auto => "auto",
+            ImagegenMode::Force => "force",
+            ImagegenMode::Skip => "skip",
+        };
+        write!(f, "{mode}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type, Default)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum DeploymentPriority {
+    #[default]
+    Standard,
+    /// Paid extra to be scheduled before standard deployments
+    Express,
+}
+
+impl Display for DeploymentPriority {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let priority = match self {
+            DeploymentPriority::Standard => "standard",
+            DeploymentPriority::Express => "express",
+        };
+        write!(f, "{priority}")
+    }
 }
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
@@ -26,12 +204,29 @@ pub struct DatabaseDeployment {
     pub imagegen_git_hash: Option<String>,
     pub deployment_request: Option<i64>,
     pub deleted: bool,
+    pub status: DeploymentStatus,
+    pub failure_reason: Option<String>,
+    pub retries: i32,
+    pub account: Option<String>,
+    pub credits: i64,
+    pub imagegen_mode: ImagegenMode,
+    pub priority: DeploymentPriority,
+    /// When the mini app host request of the rollout succeeded or failed
+    pub rollout_finished_at: Option<i64>,
+    pub rollout_error: Option<String>,
 }
 
 impl DatabaseDeployment {
+    /// Git hash of the code that was deployed, coding output when image generation was skipped
+    pub fn git_hash(&self) -> Option<String> {
+        self.imagegen_git_hash
+            .clone()
+            .or_else(|| self.coding_git_hash.clone())
+    }
+
     #[allow(dead_code)]
     pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
-        query_as("SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted FROM deployments")
+        query_as("SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments")
             .fetch_all(&database.connection)
             .await
     }
@@ -42,7 +237,7 @@ impl DatabaseDeployment {
         project: &str,
     ) -> Result<Vec<Self>, Error> {
         query_as(
-            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted FROM deployments WHERE project = $1",
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE project = $1",
         )
         .bind(project)
         .fetch_all(&database.connection)
@@ -54,7 +249,7 @@ impl DatabaseDeployment {
         project: &str,
     ) -> Result<Vec<Self>, Error> {
         query_as(
-            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted FROM deployments WHERE project = $1 and deleted = FALSE",
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE project = $1 and deleted = FALSE",
         )
         .bind(project)
         .fetch_all(&database.connection)
@@ -66,45 +261,169 @@ impl DatabaseDeployment {
         project: &str,
     ) -> Result<Vec<Self>, Error> {
         query_as(
-            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted FROM deployments WHERE coding_started_at IS NULL AND project = $1",
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE status = 'queued' AND project = $1",
         )
         .bind(project)
         .fetch_all(&database.connection)
         .await
     }
 
+    #[allow(dead_code)]
     pub async fn get_next_unfinished(database: &Database) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted FROM deployments WHERE coding_started_at IS NULL ORDER BY id ASC LIMIT 1",
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE status = 'queued' ORDER BY id ASC LIMIT 1",
         )
         .fetch_optional(&database.connection)
         .await
     }
 
+    pub async fn get_all_queued(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE status = 'queued' ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    /// Deployments per account that are being worked on (coding or generating images)
+    pub async fn get_running_count_by_account(
+        database: &Database,
+    ) -> Result<Vec<(String, i64)>, Error> {
+        query_as(
+            "SELECT COALESCE(account, project), COUNT(id) FROM deployments WHERE status IN ('coding', 'imagegen_queued', 'imagegen') GROUP BY COALESCE(account, project)",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    /// Last `limit` deployments that made it past the worker phases
+    pub async fn get_recent_finished(database: &Database, limit: i64) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE coding_finished_at IS NOT NULL AND status IN ('deploying', 'live', 'superseded') ORDER BY coding_finished_at DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_next_imagegen_queued(database: &Database) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE status = 'imagegen_queued' ORDER BY coding_finished_at ASC, id ASC LIMIT 1",
+        )
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    /// Deployments with a mini app host request of which the outcome is not known yet
+    pub async fn get_all_rolling_out(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE deployment_request IS NOT NULL AND rollout_finished_at IS NULL ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_all_by_status(
+        database: &Database,
+        status: DeploymentStatus,
+    ) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE status = $1 ORDER BY id ASC",
+        )
+        .bind(status)
+        .fetch_all(&database.connection)
+        .await
+    }
+
     pub async fn get_queued_count(database: &Database) -> Result<i64, Error> {
-        query_scalar("SELECT COUNT(id) FROM deployments WHERE coding_started_at IS NULL")
+        query_scalar("SELECT COUNT(id) FROM deployments WHERE status = 'queued'")
+            .fetch_one(&database.connection)
+            .await
+    }
+
+    pub async fn get_imagegen_queued_count(database: &Database) -> Result<i64, Error> {
+        query_scalar("SELECT COUNT(id) FROM deployments WHERE status = 'imagegen_queued'")
             .fetch_one(&database.connection)
             .await
     }
 
     pub async fn get_queued_count_before(database: &Database, before: i32) -> Result<i64, Error> {
+        query_scalar("SELECT COUNT(id) FROM deployments WHERE status = 'queued' AND id < $1")
+            .bind(before)
+            .fetch_one(&database.connection)
+            .await
+    }
+
+    pub async fn get_billable_count_by_project(
+        database: &Database,
+        project: &str,
+    ) -> Result<i64, Error> {
+        query_scalar(
+            "SELECT COUNT(id) FROM deployments WHERE project = $1 AND status NOT IN ('failed', 'cancelled')",
+        )
+        .bind(project)
+        .fetch_one(&database.connection)
+        .await
+    }
+
+    pub async fn get_billable_count_by_account_since(
+        database: &Database,
+        account: &str,
+        since: i64,
+    ) -> Result<i64, Error> {
         query_scalar(
-            "SELECT COUNT(id) FROM deployments WHERE coding_started_at IS NULL AND id < $1",
+            "SELECT COUNT(id) FROM deployments WHERE account = $1 AND submitted_at >= $2 AND status NOT IN ('failed', 'cancelled')",
         )
-        .bind(before)
+        .bind(account)
+        .bind(since)
         .fetch_one(&database.connection)
         .await
     }
 
+    pub async fn get_billable_count_by_project_in_transaction(
+        transaction: &mut DatabaseTransaction,
+        project: &str,
+    ) -> Result<i64, Error> {
+        query_scalar(
+            "SELECT COUNT(id) FROM deployments WHERE project = $1 AND status NOT IN ('failed', 'cancelled')",
+        )
+        .bind(project)
+        .fetch_one(&mut **transaction)
+        .await
+    }
+
+    pub async fn get_billable_count_by_account_since_in_transaction(
+        transaction: &mut DatabaseTransaction,
+        account: &str,
+        since: i64,
+    ) -> Result<i64, Error> {
+        query_scalar(
+            "SELECT COUNT(id) FROM deployments WHERE account = $1 AND submitted_at >= $2 AND status NOT IN ('failed', 'cancelled')",
+        )
+        .bind(account)
+        .bind(since)
+        .fetch_one(&mut **transaction)
+        .await
+    }
+
     pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted FROM deployments WHERE id = $1 LIMIT 1",
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE id = $1 LIMIT 1",
         )
         .bind(id)
         .fetch_optional(&database.connection)
         .await
     }
 
+    pub async fn get_all_by_ids(database: &Database, ids: &[i32]) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error FROM deployments WHERE id = ANY($1)",
+        )
+        .bind(ids)
+        .fetch_all(&database.connection)
+        .await
+    }
+
     pub async fn delete_all_after(
         database: &Database,
         project: &str,
@@ -119,8 +438,27 @@ impl DatabaseDeployment {
         Ok(())
     }
 
+    pub async fn supersede_all_live(
+        database: &Database,
+        project: &str,
+        except: i32,
+    ) -> Result<(), Error> {
+        query(
+            "UPDATE deployments SET status = $1 WHERE project = $2 AND status = $3 AND id <> $4;",
+        )
+        .bind(DeploymentStatus::Superseded)
+        .bind(project)
+        .bind(DeploymentStatus::Live)
+        .bind(except)
+        .execute(&database.connection)
+        .await?;
+
+        Ok(())
+    }
+
+    #[allow(dead_code)]
     pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
-        let id: i32 = query_scalar("INSERT INTO deployments(project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11) RETURNING id")
+        let id: i32 = query_scalar("INSERT INTO deployments(project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) RETURNING id")
             .bind(&self.project)
             .bind(&self.instructions)
             .bind(self.submitted_at)
@@ -132,6 +470,15 @@ impl DatabaseDeployment {
             .bind(&self.imagegen_git_hash)
             .bind(self.deployment_request)
             .bind(self.deleted)
+            .bind(self.status)
+            .bind(&self.failure_reason)
+            .bind(self.retries)
+            .bind(&self.account)
+            .bind(self.credits)
+            .bind(self.imagegen_mode)
+            .bind(self.priority)
+            .bind(self.rollout_finished_at)
+            .bind(&self.rollout_error)
             .fetch_one(&database.connection)
             .await?;
 
@@ -140,6 +487,39 @@ impl DatabaseDeployment {
         Ok(())
     }
 
+    pub async fn insert_in_transaction(
+        &mut self,
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO deployments(project, instructions, submitted_at, coding_started_at, coding_finished_at, coding_git_hash, imagegen_started_at, imagegen_finished_at, imagegen_git_hash, deployment_request, deleted, status, failure_reason, retries, account, credits, imagegen_mode, priority, rollout_finished_at, rollout_error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16, $17, $18, $19, $20) RETURNING id")
+            .bind(&self.project)
+            .bind(&self.instructions)
+            .bind(self.submitted_at)
+            .bind(self.coding_started_at)
+            .bind(self.coding_finished_at)
+            .bind(&self.coding_git_hash)
+            .bind(self.imagegen_started_at)
+            .bind(self.imagegen_finished_at)
+            .bind(&self.imagegen_git_hash)
+            .bind(self.deployment_request)
+            .bind(self.deleted)
+            .bind(self.status)
+            .bind(&self.failure_reason)
+            .bind(self.retries)
+            .bind(&self.account)
+            .bind(self.credits)
+            .bind(self.imagegen_mode)
+            .bind(self.priority)
+            .bind(self.rollout_finished_at)
+            .bind(&self.rollout_error)
+            .fetch_one(&mut **transaction)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
     pub async fn update_coding_started_at(
         &mut self,
         database: &Database,
@@ -241,14 +621,269 @@ impl DatabaseDeployment {
         database: &Database,
         deployment_request: Option<i64>,
     ) -> Result<(), Error> {
-        query("UPDATE deployments SET deployment_request = $1 WHERE id = $2;")
+        // A new request has its own rollout outcome
+        query("UPDATE deployments SET deployment_request = $1, rollout_finished_at = NULL, rollout_error = NULL WHERE id = $2;")
             .bind(deployment_request)
             .bind(self.id)
             .execute(&database.connection)
             .await?;
 
         self.deployment_request = deployment_request;
+        self.rollout_finished_at = None;
+        self.rollout_error = None;
+
+        Ok(())
+    }
+
+    pub async fn update_rollout(
+        &mut self,
+        database: &Database,
+        rollout_finished_at: i64,
+        rollout_error: Option<String>,
+    ) -> Result<(), Error> {
+        query("UPDATE deployments SET rollout_finished_at = $1, rollout_error = $2 WHERE id = $3;")
+            .bind(rollout_finished_at)
+            .bind(&rollout_error)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.rollout_finished_at = Some(rollout_finished_at);
+        self.rollout_error = rollout_error;
+
+        Ok(())
+    }
+
+    pub async fn update_status(
+        &mut self,
+        database: &Database,
+        status: DeploymentStatus,
+        failure_reason: Option<String>,
+    ) -> Result<(), Error> {
+        if !self.status.can_transition_to(status) {
+            return Err(Error::Protocol(format!(
+                "Invalid deployment status transition from {from} to {status}",
+                from = self.status
+            )));
+        }
+
+        // Only transition if no one else changed the status in the meantime
+        let result = query(
+            "UPDATE deployments SET status = $1, failure_reason = $2 WHERE id = $3 AND status = $4;",
+        )
+        .bind(status)
+        .bind(&failure_reason)
+        .bind(self.id)
+        .bind(self.status)
+        .execute(&database.connection)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        self.status = status;
+        self.failure_reason = failure_reason;
+
+        Ok(())
+    }
+
+    pub async fn update_status_in_transaction(
+        &mut self,
+        transaction: &mut DatabaseTransaction,
+        status: DeploymentStatus,
+    ) -> Result<(), Error> {
+        if !self.status.can_transition_to(status) {
+            return Err(Error::Protocol(format!(
+                "Invalid deployment status transition from {from} to {status}",
+                from = self.status
+            )));
+        }
+
+        let result = query(
+            "UPDATE deployments SET status = $1, failure_reason = NULL WHERE id = $2 AND status = $3;",
+        )
+        .bind(status)
+        .bind(self.id)
+        .bind(self.status)
+        .execute(&mut **transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        self.status = status;
+        self.failure_reason = None;
 
         Ok(())
     }
+
+    pub async fn requeue(&mut self, database: &Database) -> Result<(), Error> {
+        if !self.status.can_transition_to(DeploymentStatus::Queued) {
+            return Err(Error::Protocol(format!(
+                "Invalid deployment status transition from {from} to {status}",
+                from = self.status,
+                status = DeploymentStatus::Queued
+            )));
+        }
+
+        let result = query(
+            "UPDATE deployments SET status = $1, failure_reason = NULL, coding_started_at = NULL, coding_finished_at = NULL, coding_git_hash = NULL, imagegen_started_at = NULL, imagegen_finished_at = NULL, imagegen_git_hash = NULL, retries = retries + 1 WHERE id = $2 AND status = $3;",
+        )
+        .bind(DeploymentStatus::Queued)
+        .bind(self.id)
+        .bind(self.status)
+        .execute(&database.connection)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        self.status = DeploymentStatus::Queued;
+        self.failure_reason = None;
+        self.coding_started_at = None;
+        self.coding_finished_at = None;
+        self.coding_git_hash = None;
+        self.imagegen_started_at = None;
+        self.imagegen_finished_at = None;
+        self.imagegen_git_hash = None;
+        self.retries += 1;
+
+        Ok(())
+    }
+
+    /// Retry only the imagegen phase, the coding result is kept
+    pub async fn requeue_imagegen(&mut self, database: &Database) -> Result<(), Error> {
+        if !self
+            .status
+            .can_transition_to(DeploymentStatus::ImagegenQueued)
+        {
+            return Err(Error::Protocol(format!(
+                "Invalid deployment status transition from {from} to {status}",
+                from = self.status,
+                status = DeploymentStatus::ImagegenQueued
+            )));
+        }
+
+        let result = query(
+            "UPDATE deployments SET status = $1, failure_reason = NULL, imagegen_started_at = NULL, imagegen_finished_at = NULL, imagegen_git_hash = NULL, retries = retries + 1 WHERE id = $2 AND status = $3;",
+        )
+        .bind(DeploymentStatus::ImagegenQueued)
+        .bind(self.id)
+        .bind(self.status)
+        .execute(&database.connection)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        self.status = DeploymentStatus::ImagegenQueued;
+        self.failure_reason = None;
+        self.imagegen_started_at = None;
+        self.imagegen_finished_at = None;
+        self.imagegen_git_hash = None;
+        self.retries += 1;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl DatabaseDeployment {
+    /// Standard deployment of `project` without account or credits, waiting for a coder
+    pub fn queued(id: i32, project: &str) -> Self {
+        Self {
+            id,
+            project: project.to_string(),
+            instructions: String::new(),
+            submitted_at: 0,
+            coding_started_at: None,
+            coding_finished_at: None,
+            coding_git_hash: None,
+            imagegen_started_at: None,
+            imagegen_finished_at: None,
+            imagegen_git_hash: None,
+            deployment_request: None,
+            deleted: false,
+            status: DeploymentStatus::Queued,
+            failure_reason: None,
+            retries: 0,
+            account: None,
+            credits: 0,
+            imagegen_mode: ImagegenMode::Auto,
+            priority: DeploymentPriority::Standard,
+            rollout_finished_at: None,
+            rollout_error: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DeploymentStatus::{self, *};
+
+    const ALL: [DeploymentStatus; 9] = [
+        Queued,
+        Coding,
+        ImagegenQueued,
+        Imagegen,
+        Deploying,
+        Live,
+        Failed,
+        Cancelled,
+        Superseded,
+    ];
+
+    #[test]
+    fn worker_phases_can_fail_or_be_cancelled() {
+        assert!(Queued.can_transition_to(Coding));
+        assert!(Coding.can_transition_to(ImagegenQueued));
+        assert!(ImagegenQueued.can_transition_to(Imagegen));
+        assert!(Imagegen.can_transition_to(Deploying));
+        // Image generation skipped
+        assert!(Coding.can_transition_to(Deploying));
+        for status in [Queued, Coding, ImagegenQueued, Imagegen] {
+            assert!(status.can_transition_to(Failed), "{status}");
+            assert!(status.can_transition_to(Cancelled), "{status}");
+        }
+    }
+
+    #[test]
+    fn deploying_can_not_be_cancelled() {
+        assert!(Deploying.can_transition_to(Live));
+        assert!(Deploying.can_transition_to(Failed));
+        assert!(!Deploying.can_transition_to(Cancelled));
+    }
+
+    #[test]
+    fn live_and_superseded_swap_on_rollback() {
+        assert!(Live.can_transition_to(Superseded));
+        assert!(Superseded.can_transition_to(Live));
+        assert!(!Live.can_transition_to(Failed));
+    }
+
+    #[test]
+    fn failed_and_cancelled_are_final() {
+        for next in ALL {
+            assert!(!Failed.can_transition_to(next), "failed to {next}");
+            assert!(!Cancelled.can_transition_to(next), "cancelled to {next}");
+        }
+    }
+
+    #[test]
+    fn phases_do_not_go_back() {
+        for status in ALL {
+            assert!(!status.can_transition_to(status), "{status}");
+        }
+        assert!(!Imagegen.can_transition_to(Coding));
+        // Imagegen has to wait for an imagegen worker
+        assert!(!Coding.can_transition_to(Imagegen));
+    }
+
+    #[test]
+    fn worker_phases_can_be_requeued() {
+        assert!(Coding.can_transition_to(Queued));
+        assert!(Imagegen.can_transition_to(Queued));
+        assert!(Imagegen.can_transition_to(ImagegenQueued));
+        assert!(!Deploying.can_transition_to(Queued));
+    }
 }
@@ -0,0 +1,294 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, Type, query, query_as, query_scalar};
+
+use crate::{
+    database::{Database, DatabaseConnection},
+    utils::time::get_time_i64,
+};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS host_migrations(id SERIAL PRIMARY KEY, project TEXT NOT NULL, source INT4 NOT NULL, target INT4 NOT NULL, network TEXT NOT NULL, status TEXT NOT NULL, attempts INT4 NOT NULL, error TEXT, created_at INT8 NOT NULL, finished_at INT8, failed_step TEXT, flake TEXT, container_request INT8, remove_request INT8, step_started_at INT8)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create host_migrations table: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE host_migrations ADD COLUMN IF NOT EXISTS step_started_at INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate host_migrations step_started_at: {e}"));
+
+    // A project can only be moved by one migration at a time
+    sqlx::raw_sql(
+        "CREATE UNIQUE INDEX IF NOT EXISTS host_migrations_unfinished ON host_migrations(project) WHERE status NOT IN ('done', 'failed')",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create host_migrations unfinished index: {e}"));
+}
+
+/// Step of moving a project to another mini app host that is executed next, steps are executed in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum MigrationStatus {
+    /// Deploy the project container on the target host
+    Container,
+    /// Point the project to the target host
+    Assign,
+    /// Wait until the host sync updated the expose files of both hosts
+    Expose,
+    /// Wait until both hosts are rebuilt with their new expose files
+    Rebuild,
+    /// Remove the project container from the source host
+    Remove,
+    Done,
+    Failed,
+}
+
+impl MigrationStatus {
+    pub fn next(&self) -> Self {
+        match self {
+            MigrationStatus::Container => MigrationStatus::Assign,
+            MigrationStatus::Assign => MigrationStatus::Expose,
+            MigrationStatus::Expose => MigrationStatus::Rebuild,
+            MigrationStatus::Rebuild => MigrationStatus::Remove,
+            MigrationStatus::Remove => MigrationStatus::Done,
+            MigrationStatus::Done => MigrationStatus::Done,
+            MigrationStatus::Failed => MigrationStatus::Failed,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, MigrationStatus::Done | MigrationStatus::Failed)
+    }
+}
+
+impl Display for MigrationStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            MigrationStatus::Container => "container",
+            MigrationStatus::Assign => "assign",
+            MigrationStatus::Expose => "expose",
+            MigrationStatus::Rebuild => "rebuild",
+            MigrationStatus::Remove => "remove",
+            MigrationStatus::Done => "done",
+            MigrationStatus::Failed => "failed",
+        };
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseHostMigration {
+    pub id: i32,
+    pub project: String,
+    /// Mini app host the project is moved from
+    pub source: i32,
+    /// Mini app host the project is moved to
+    pub target: i32,
+    /// Network bridge of the container on the target host
+    pub network: String,
+    pub status: MigrationStatus,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+    pub failed_step: Option<MigrationStatus>,
+    /// Flake deployed on the target host, redeployed when the project changed before it was assigned
+    pub flake: Option<String>,
+    pub container_request: Option<i64>,
+    pub remove_request: Option<i64>,
+    /// Time the current step was started (or resumed), the first step starts when the migration is created
+    pub step_started_at: Option<i64>,
+}
+
+impl DatabaseHostMigration {
+    pub fn get_step_started_at(&self) -> i64 {
+        self.step_started_at.unwrap_or(self.created_at)
+    }
+
+    pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, source, target, network, status, attempts, error, created_at, finished_at, failed_step, flake, container_request, remove_request, step_started_at FROM host_migrations ORDER BY id DESC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_all_unfinished(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, source, target, network, status, attempts, error, created_at, finished_at, failed_step, flake, container_request, remove_request, step_started_at FROM host_migrations WHERE status NOT IN ('done', 'failed') ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, project, source, target, network, status, attempts, error, created_at, finished_at, failed_step, flake, container_request, remove_request, step_started_at FROM host_migrations WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn get_unfinished_by_project(
+        database: &Database,
+        project: &str,
+    ) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, project, source, target, network, status, attempts, error, created_at, finished_at, failed_step, flake, container_request, remove_request, step_started_at FROM host_migrations WHERE project = $1 AND status NOT IN ('done', 'failed')",
+        )
+        .bind(project)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO host_migrations(project, source, target, network, status, attempts, error, created_at, finished_at, failed_step, flake, container_request, remove_request, step_started_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING id")
+            .bind(&self.project)
+            .bind(self.source)
+            .bind(self.target)
+            .bind(&self.network)
+            .bind(self.status)
+            .bind(self.attempts)
+            .bind(&self.error)
+            .bind(self.created_at)
+            .bind(self.finished_at)
+            .bind(self.failed_step)
+            .bind(&self.flake)
+            .bind(self.container_request)
+            .bind(self.remove_request)
+            .bind(self.step_started_at)
+            .fetch_one(&database.connection)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
+    pub async fn update_status(
+        &mut self,
+        database: &Database,
+        status: MigrationStatus,
+        finished_at: Option<i64>,
+    ) -> Result<(), Error> {
+        let step_started_at = get_time_i64();
+        query(
+            "UPDATE host_migrations SET status = $1, attempts = 0, error = NULL, finished_at = $2, step_started_at = $3 WHERE id = $4;",
+        )
+        .bind(status)
+        .bind(finished_at)
+        .bind(step_started_at)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.status = status;
+        self.attempts = 0;
+        self.error = None;
+        self.finished_at = finished_at;
+        self.step_started_at = Some(step_started_at);
+
+        Ok(())
+    }
+
+    pub async fn update_error(&mut self, database: &Database, error: String) -> Result<(), Error> {
+        query("UPDATE host_migrations SET attempts = attempts + 1, error = $1 WHERE id = $2;")
+            .bind(&error)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.attempts += 1;
+        self.error = Some(error);
+
+        Ok(())
+    }
+
+    pub async fn update_failed(
+        &mut self,
+        database: &Database,
+        finished_at: i64,
+    ) -> Result<(), Error> {
+        query(
+            "UPDATE host_migrations SET status = $1, finished_at = $2, failed_step = $3 WHERE id = $4;",
+        )
+        .bind(MigrationStatus::Failed)
+        .bind(finished_at)
+        .bind(self.status)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.failed_step = Some(self.status);
+        self.status = MigrationStatus::Failed;
+        self.finished_at = Some(finished_at);
+
+        Ok(())
+    }
+
+    /// Continue a failed migration at the step it failed at
+    pub async fn update_resumed(&mut self, database: &Database) -> Result<(), Error> {
+        let status = self.failed_step.unwrap_or(MigrationStatus::Container);
+        let step_started_at = get_time_i64();
+        query(
+            "UPDATE host_migrations SET status = $1, attempts = 0, error = NULL, finished_at = NULL, failed_step = NULL, step_started_at = $2 WHERE id = $3 AND status = $4;",
+        )
+        .bind(status)
+        .bind(step_started_at)
+        .bind(self.id)
+        .bind(MigrationStatus::Failed)
+        .execute(&database.connection)
+        .await?;
+
+        self.status = status;
+        self.attempts = 0;
+        self.error = None;
+        self.finished_at = None;
+        self.failed_step = None;
+        self.step_started_at = Some(step_started_at);
+
+        Ok(())
+    }
+
+    pub async fn update_container_request(
+        &mut self,
+        database: &Database,
+        container_request: Option<i64>,
+        flake: Option<String>,
+    ) -> Result<(), Error> {
+        query("UPDATE host_migrations SET container_request = $1, flake = $2 WHERE id = $3;")
+            .bind(container_request)
+            .bind(&flake)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.container_request = container_request;
+        self.flake = flake;
+
+        Ok(())
+    }
+
+    pub async fn update_remove_request(
+        &mut self,
+        database: &Database,
+        remove_request: Option<i64>,
+    ) -> Result<(), Error> {
+        query("UPDATE host_migrations SET remove_request = $1 WHERE id = $2;")
+            .bind(remove_request)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.remove_request = remove_request;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,60 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, query, query_as};
+
+use crate::database::{Database, DatabaseConnection};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS leaders(role TEXT PRIMARY KEY, instance TEXT NOT NULL, acquired_at INT8 NOT NULL, heartbeat_at INT8 NOT NULL)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create leaders table: {e}"));
+}
+
+/// Instance that last held the lock of a singleton background loop, the lock itself is a Postgres advisory lock
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseLeader {
+    pub role: String,
+    pub instance: String,
+    pub acquired_at: i64,
+    /// Updated while the instance still holds the lock
+    pub heartbeat_at: i64,
+}
+
+impl DatabaseLeader {
+    pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as("SELECT role, instance, acquired_at, heartbeat_at FROM leaders ORDER BY role ASC")
+            .fetch_all(&database.connection)
+            .await
+    }
+
+    pub async fn upsert(&self, database: &Database) -> Result<(), Error> {
+        query("INSERT INTO leaders(role, instance, acquired_at, heartbeat_at) VALUES ($1, $2, $3, $4) ON CONFLICT (role) DO UPDATE SET instance = EXCLUDED.instance, acquired_at = EXCLUDED.acquired_at, heartbeat_at = EXCLUDED.heartbeat_at;")
+            .bind(&self.role)
+            .bind(&self.instance)
+            .bind(self.acquired_at)
+            .bind(self.heartbeat_at)
+            .execute(&database.connection)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_heartbeat_at(
+        &mut self,
+        database: &Database,
+        heartbeat_at: i64,
+    ) -> Result<(), Error> {
+        query("UPDATE leaders SET heartbeat_at = $1 WHERE role = $2 AND instance = $3;")
+            .bind(heartbeat_at)
+            .bind(&self.role)
+            .bind(&self.instance)
+            .execute(&database.connection)
+            .await?;
+
+        self.heartbeat_at = heartbeat_at;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,247 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, query, query_as, query_scalar};
+
+use crate::{
+    database::{Database, DatabaseConnection, DatabaseTransaction},
+    utils::{
+        env::{miniapphostcapacity, miniapphostdomain, miniapphosturl},
+        time::get_time_i64,
+    },
+};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS miniapp_hosts(id SERIAL PRIMARY KEY, url TEXT UNIQUE NOT NULL, domain TEXT NOT NULL, capacity INT4 NOT NULL, draining BOOL NOT NULL DEFAULT FALSE, created_at INT8 NOT NULL, exposed TEXT, rebuild_exposed TEXT, rebuild_request INT8, synced_at INT8, drifted_at INT8, rebuild_failures INT4 NOT NULL DEFAULT 0, rebuild_failed_at INT8)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create miniapp_hosts table: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE miniapp_hosts ADD COLUMN IF NOT EXISTS exposed TEXT, ADD COLUMN IF NOT EXISTS rebuild_exposed TEXT, ADD COLUMN IF NOT EXISTS rebuild_request INT8, ADD COLUMN IF NOT EXISTS synced_at INT8, ADD COLUMN IF NOT EXISTS drifted_at INT8",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate miniapp_hosts host sync: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE miniapp_hosts ADD COLUMN IF NOT EXISTS rebuild_failures INT4 NOT NULL DEFAULT 0, ADD COLUMN IF NOT EXISTS rebuild_failed_at INT8",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate miniapp_hosts rebuild failures: {e}"));
+
+    // All projects used to be hosted on a single mini app host
+    query("INSERT INTO miniapp_hosts(url, domain, capacity, draining, created_at) SELECT $1, $2, $3, FALSE, $4 WHERE NOT EXISTS (SELECT 1 FROM miniapp_hosts)")
+        .bind(miniapphosturl())
+        .bind(miniapphostdomain())
+        .bind(miniapphostcapacity())
+        .bind(get_time_i64())
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate miniapp_hosts initial host: {e}"));
+}
+
+/// Xnode running the containers of mini apps
+#[derive(Debug, FromRow, Serialize, Deserialize, Clone)]
+pub struct DatabaseMiniappHost {
+    pub id: i32,
+    pub url: String,
+    pub domain: String,
+    /// Maximum amount of projects placed on this host
+    pub capacity: i32,
+    /// Draining hosts keep their projects, but new projects are not placed on them
+    pub draining: bool,
+    pub created_at: i64,
+    /// Expose file content applied by the last successful os rebuild
+    pub exposed: Option<String>,
+    /// Expose file content the pending os rebuild applies
+    pub rebuild_exposed: Option<String>,
+    pub rebuild_request: Option<i64>,
+    /// Last time the expose file was verified against the database
+    pub synced_at: Option<i64>,
+    /// Last time the expose file did not match the database
+    pub drifted_at: Option<i64>,
+    /// Os rebuilds that failed in a row, new rebuilds are backed off while it keeps failing
+    pub rebuild_failures: i32,
+    pub rebuild_failed_at: Option<i64>,
+}
+
+impl DatabaseMiniappHost {
+    pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, url, domain, capacity, draining, created_at, exposed, rebuild_exposed, rebuild_request, synced_at, drifted_at, rebuild_failures, rebuild_failed_at FROM miniapp_hosts ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, url, domain, capacity, draining, created_at, exposed, rebuild_exposed, rebuild_request, synced_at, drifted_at, rebuild_failures, rebuild_failed_at FROM miniapp_hosts WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    /// Least loaded host with capacity left, locked until the transaction ends so concurrent placements are counted
+    pub async fn place_in_transaction(
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, url, domain, capacity, draining, created_at, exposed, rebuild_exposed, rebuild_request, synced_at, drifted_at, rebuild_failures, rebuild_failed_at FROM miniapp_hosts WHERE NOT draining AND capacity > (SELECT COUNT(id) FROM projects WHERE projects.miniapp_host = miniapp_hosts.id) ORDER BY (SELECT COUNT(id) FROM projects WHERE projects.miniapp_host = miniapp_hosts.id)::FLOAT8 / capacity ASC, id ASC LIMIT 1 FOR UPDATE",
+        )
+        .fetch_optional(&mut **transaction)
+        .await
+    }
+
+    pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO miniapp_hosts(url, domain, capacity, draining, created_at, exposed, rebuild_exposed, rebuild_request, synced_at, drifted_at, rebuild_failures, rebuild_failed_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12) RETURNING id")
+            .bind(&self.url)
+            .bind(&self.domain)
+            .bind(self.capacity)
+            .bind(self.draining)
+            .bind(self.created_at)
+            .bind(&self.exposed)
+            .bind(&self.rebuild_exposed)
+            .bind(self.rebuild_request)
+            .bind(self.synced_at)
+            .bind(self.drifted_at)
+            .bind(self.rebuild_failures)
+            .bind(self.rebuild_failed_at)
+            .fetch_one(&database.connection)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
+    pub async fn update_capacity(
+        &mut self,
+        database: &Database,
+        capacity: i32,
+        draining: bool,
+    ) -> Result<(), Error> {
+        query("UPDATE miniapp_hosts SET capacity = $1, draining = $2 WHERE id = $3;")
+            .bind(capacity)
+            .bind(draining)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.capacity = capacity;
+        self.draining = draining;
+
+        Ok(())
+    }
+
+    pub async fn update_rebuild_request(
+        &mut self,
+        database: &Database,
+        rebuild_request: Option<i64>,
+        rebuild_exposed: Option<String>,
+    ) -> Result<(), Error> {
+        query("UPDATE miniapp_hosts SET rebuild_request = $1, rebuild_exposed = $2 WHERE id = $3;")
+            .bind(rebuild_request)
+            .bind(&rebuild_exposed)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.rebuild_request = rebuild_request;
+        self.rebuild_exposed = rebuild_exposed;
+
+        Ok(())
+    }
+
+    /// The pending os rebuild succeeded, its expose file content is applied
+    pub async fn update_exposed(&mut self, database: &Database) -> Result<(), Error> {
+        query("UPDATE miniapp_hosts SET exposed = rebuild_exposed, rebuild_exposed = NULL, rebuild_request = NULL, rebuild_failures = 0 WHERE id = $1;")
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.exposed = self.rebuild_exposed.take();
+        self.rebuild_request = None;
+        self.rebuild_failures = 0;
+
+        Ok(())
+    }
+
+    /// The pending os rebuild failed, its expose file content is applied by a later rebuild
+    pub async fn update_rebuild_failed(
+        &mut self,
+        database: &Database,
+        rebuild_failed_at: i64,
+    ) -> Result<(), Error> {
+        query("UPDATE miniapp_hosts SET rebuild_request = NULL, rebuild_failures = rebuild_failures + 1, rebuild_failed_at = $1 WHERE id = $2;")
+            .bind(rebuild_failed_at)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.rebuild_request = None;
+        self.rebuild_failures += 1;
+        self.rebuild_failed_at = Some(rebuild_failed_at);
+
+        Ok(())
+    }
+
+    pub async fn update_synced_at(
+        &mut self,
+        database: &Database,
+        synced_at: i64,
+    ) -> Result<(), Error> {
+        query("UPDATE miniapp_hosts SET synced_at = $1 WHERE id = $2;")
+            .bind(synced_at)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.synced_at = Some(synced_at);
+
+        Ok(())
+    }
+
+    pub async fn update_drifted_at(
+        &mut self,
+        database: &Database,
+        drifted_at: i64,
+    ) -> Result<(), Error> {
+        query("UPDATE miniapp_hosts SET drifted_at = $1 WHERE id = $2;")
+            .bind(drifted_at)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.drifted_at = Some(drifted_at);
+
+        Ok(())
+    }
+
+    /// Whether the last written expose file lists the project
+    pub fn is_listed(&self, project: &str) -> bool {
+        self.rebuild_exposed
+            .as_ref()
+            .or(self.exposed.as_ref())
+            .is_some_and(|exposed| exposed.lines().any(|line| line == project))
+    }
+
+    /// Os rebuild that is applying an expose file listing the project
+    pub fn rebuilding(&self, project: &str) -> Option<i64> {
+        self.rebuild_request.filter(|_| {
+            self.rebuild_exposed
+                .as_ref()
+                .is_some_and(|exposed| exposed.lines().any(|line| line == project))
+        })
+    }
+
+    /// Whether the last applied expose file lists the project
+    pub fn is_exposed(&self, project: &str) -> bool {
+        self.exposed
+            .as_ref()
+            .is_some_and(|exposed| exposed.lines().any(|line| line == project))
+    }
+}
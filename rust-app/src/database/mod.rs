@@ -1,15 +1,27 @@
-use sqlx::{Pool, Postgres, postgres::PgPoolOptions};
+use sqlx::{
+    Error, Pool, Postgres, Transaction,
+    postgres::{PgConnection, PgListener, PgPoolOptions},
+};
 
 use crate::utils::env::database;
 
 pub mod credits;
+pub mod deployment_logs;
 pub mod deployments;
+pub mod host_migrations;
+pub mod leaders;
+pub mod miniapp_hosts;
 pub mod projects;
 pub mod promo_code;
+pub mod provisionings;
+pub mod scaling_decisions;
+pub mod templates;
 pub mod waitlist;
+pub mod worker_events;
 pub mod worker_servers;
 
 pub type DatabaseConnection = Pool<Postgres>;
+pub type DatabaseTransaction = Transaction<'static, Postgres>;
 
 #[derive(Clone)]
 pub struct Database {
@@ -22,6 +34,23 @@ impl Database {
             connection: create_connection().await,
         }
     }
+
+    pub async fn begin(&self) -> Result<DatabaseTransaction, Error> {
+        self.connection.begin().await
+    }
+
+    /// Connection taken out of the pool, session state (like advisory locks) ends when it is dropped
+    pub async fn detached(&self) -> Result<PgConnection, Error> {
+        Ok(self.connection.acquire().await?.detach())
+    }
+
+    /// Dedicated connection receiving the notifications of `channel`
+    pub async fn listen(&self, channel: &str) -> Result<PgListener, Error> {
+        let mut listener = PgListener::connect_with(&self.connection).await?;
+        listener.listen(channel).await?;
+
+        Ok(listener)
+    }
 }
 
 pub async fn create_connection() -> DatabaseConnection {
@@ -34,10 +63,18 @@ pub async fn create_connection() -> DatabaseConnection {
         .unwrap_or_else(|e| panic!("Could not establish database connection: {e}"));
 
     credits::create_table(&connection).await;
+    deployment_logs::create_table(&connection).await;
     deployments::create_table(&connection).await;
+    host_migrations::create_table(&connection).await;
+    leaders::create_table(&connection).await;
+    miniapp_hosts::create_table(&connection).await;
     projects::create_table(&connection).await;
     promo_code::create_table(&connection).await;
+    provisionings::create_table(&connection).await;
+    scaling_decisions::create_table(&connection).await;
+    templates::create_table(&connection).await;
     waitlist::create_table(&connection).await;
+    worker_events::create_table(&connection).await;
     worker_servers::create_table(&connection).await;
 
     connection
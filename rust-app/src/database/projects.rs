@@ -1,15 +1,64 @@
 use serde::{Deserialize, Serialize};
 use sqlx::{Error, FromRow, query, query_as, query_scalar, types::Json};
 
-use crate::database::{Database, DatabaseConnection};
+use crate::{
+    database::{Database, DatabaseConnection, DatabaseTransaction, templates::ProjectTemplate},
+    source_host::{SourceHost, source_host},
+};
 
 pub async fn create_table(connection: &DatabaseConnection) {
     sqlx::raw_sql(
-        "CREATE TABLE IF NOT EXISTS projects(id SERIAL PRIMARY KEY, name TEXT UNIQUE NOT NULL, owner TEXT NOT NULL, account_association JSON, base_build JSON, version TEXT, nft_mint TEXT)",
+        "CREATE TABLE IF NOT EXISTS projects(id SERIAL PRIMARY KEY, name TEXT UNIQUE NOT NULL, owner TEXT NOT NULL, account_association JSON, base_build JSON, version TEXT, nft_mint TEXT, template JSON, miniapp_host INT4, network TEXT, reconcile_request INT8, change_request INT8, reconcile_failures INT4 NOT NULL DEFAULT 0, reconcile_failed_at INT8, reconcile_error TEXT)",
     )
     .execute(connection)
     .await
     .unwrap_or_else(|e| panic!("Could not create projects table: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE projects ADD COLUMN IF NOT EXISTS template JSON")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate projects template: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE projects ADD COLUMN IF NOT EXISTS miniapp_host INT4, ADD COLUMN IF NOT EXISTS network TEXT",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate projects placement: {e}"));
+
+    // Projects from before the host registry are on the initial mini app host
+    sqlx::raw_sql(
+        "UPDATE projects SET miniapp_host = (SELECT MIN(id) FROM miniapp_hosts) WHERE miniapp_host IS NULL",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate projects miniapp_host: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE projects ADD COLUMN IF NOT EXISTS reconcile_request INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate projects reconcile_request: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE projects ADD COLUMN IF NOT EXISTS change_request INT8, ADD COLUMN IF NOT EXISTS reconcile_failures INT4 NOT NULL DEFAULT 0, ADD COLUMN IF NOT EXISTS reconcile_failed_at INT8, ADD COLUMN IF NOT EXISTS reconcile_error TEXT",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate projects reconcile failures: {e}"));
+}
+
+/// Containers per network bridge of a mini app host
+const NETWORK_SIZE: i64 = 500;
+
+/// Network bridge of the container placed at `index` on its mini app host
+pub fn network_of(index: i64) -> String {
+    let bracket = 1 + index / NETWORK_SIZE;
+
+    if bracket == 1 {
+        "containernet".to_string()
+    } else {
+        format!("net{bracket}")
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
@@ -33,11 +82,37 @@ pub struct DatabaseProject {
     pub base_build: Option<Json<BaseBuild>>,
     pub version: Option<String>,
     pub nft_mint: Option<String>,
+    pub template: Option<Json<ProjectTemplate>>,
+    pub miniapp_host: Option<i32>,
+    /// Network bridge on the mini app host, projects from before placement was stored derive it from their id
+    pub network: Option<String>,
+    /// Pending request re-applying the container of the project after it diverged on its mini app host
+    pub reconcile_request: Option<i64>,
+    /// Pending container change submitted by a handler (e.g. reset), the project is left alone by the reconciler until it finished
+    pub change_request: Option<i64>,
+    /// Failed re-applies in a row, the reconciler gives up after a few until an admin looks at it
+    pub reconcile_failures: i32,
+    pub reconcile_failed_at: Option<i64>,
+    pub reconcile_error: Option<String>,
 }
 
 impl DatabaseProject {
     pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
-        query_as("SELECT id, name, owner, account_association, base_build, version, nft_mint FROM projects")
+        query_as("SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects")
+            .fetch_all(&database.connection)
+            .await
+    }
+
+    /// Projects with a container change submitted by a handler that did not finish yet
+    pub async fn get_all_changing(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as("SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE change_request IS NOT NULL")
+            .fetch_all(&database.connection)
+            .await
+    }
+
+    /// Projects whose container could not be re-applied the last time
+    pub async fn get_all_reconcile_failed(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as("SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE reconcile_failures > 0 ORDER BY id ASC")
             .fetch_all(&database.connection)
             .await
     }
@@ -48,9 +123,40 @@ impl DatabaseProject {
             .await
     }
 
+    pub async fn get_all_by_miniapp_host(
+        database: &Database,
+        miniapp_host: i32,
+    ) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE miniapp_host = $1",
+        )
+        .bind(miniapp_host)
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    /// Amount of projects placed on each mini app host
+    pub async fn get_count_by_miniapp_host(database: &Database) -> Result<Vec<(i32, i64)>, Error> {
+        query_as(
+            "SELECT miniapp_host, COUNT(id) FROM projects WHERE miniapp_host IS NOT NULL GROUP BY miniapp_host",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_count_by_miniapp_host_in_transaction(
+        transaction: &mut DatabaseTransaction,
+        miniapp_host: i32,
+    ) -> Result<i64, Error> {
+        query_scalar("SELECT COUNT(id) FROM projects WHERE miniapp_host = $1")
+            .bind(miniapp_host)
+            .fetch_one(&mut **transaction)
+            .await
+    }
+
     pub async fn get_all_by_owner(database: &Database, owner: &str) -> Result<Vec<Self>, Error> {
         query_as(
-            "SELECT id, name, owner, account_association, base_build, version, nft_mint FROM projects WHERE owner = $1",
+            "SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE owner = $1",
         )
         .bind(owner)
         .fetch_all(&database.connection)
@@ -59,7 +165,7 @@ impl DatabaseProject {
 
     pub async fn get_next_unminted(database: &Database) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, name, owner, account_association, base_build, version, nft_mint FROM projects WHERE nft_mint IS NULL ORDER BY id ASC LIMIT 1",
+            "SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE nft_mint IS NULL AND NOT EXISTS (SELECT 1 FROM provisionings WHERE provisionings.project = projects.name AND provisionings.status <> 'ready') ORDER BY id ASC LIMIT 1",
         )
         .fetch_optional(&database.connection)
         .await
@@ -67,7 +173,7 @@ impl DatabaseProject {
 
     pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, name, owner, account_association, base_build, version, nft_mint FROM projects WHERE id = $1",
+            "SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE id = $1",
         )
         .bind(id)
         .fetch_optional(&database.connection)
@@ -76,21 +182,43 @@ impl DatabaseProject {
 
     pub async fn get_by_name(database: &Database, name: &str) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, name, owner, account_association, base_build, version, nft_mint FROM projects WHERE name = $1",
+            "SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE name = $1",
         )
         .bind(name)
         .fetch_optional(&database.connection)
         .await
     }
 
+    /// Waits for a concurrent placement change (e.g. a host migration assigning the project) to commit before reading
+    pub async fn get_by_name_for_share(
+        database: &Database,
+        name: &str,
+    ) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error FROM projects WHERE name = $1 FOR SHARE",
+        )
+        .bind(name)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    #[allow(dead_code)]
     pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
-        let id: i32 = query_scalar("INSERT INTO projects(name, owner, account_association, base_build, version, nft_mint) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id")
+        let id: i32 = query_scalar("INSERT INTO projects(name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING id")
             .bind(&self.name)
             .bind(&self.owner)
             .bind(&self.account_association)
             .bind(&self.base_build)
             .bind(&self.version)
             .bind(&self.nft_mint)
+            .bind(&self.template)
+            .bind(self.miniapp_host)
+            .bind(&self.network)
+            .bind(self.reconcile_request)
+            .bind(self.change_request)
+            .bind(self.reconcile_failures)
+            .bind(self.reconcile_failed_at)
+            .bind(&self.reconcile_error)
             .fetch_one(&database.connection)
             .await?;
 
@@ -99,6 +227,42 @@ impl DatabaseProject {
         Ok(())
     }
 
+    pub async fn insert_in_transaction(
+        &mut self,
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO projects(name, owner, account_association, base_build, version, nft_mint, template, miniapp_host, network, reconcile_request, change_request, reconcile_failures, reconcile_failed_at, reconcile_error) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING id")
+            .bind(&self.name)
+            .bind(&self.owner)
+            .bind(&self.account_association)
+            .bind(&self.base_build)
+            .bind(&self.version)
+            .bind(&self.nft_mint)
+            .bind(&self.template)
+            .bind(self.miniapp_host)
+            .bind(&self.network)
+            .bind(self.reconcile_request)
+            .bind(self.change_request)
+            .bind(self.reconcile_failures)
+            .bind(self.reconcile_failed_at)
+            .bind(&self.reconcile_error)
+            .fetch_one(&mut **transaction)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, database: &Database) -> Result<(), Error> {
+        query("DELETE FROM projects WHERE id = $1;")
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        Ok(())
+    }
+
     pub async fn update_owner(&mut self, database: &Database, owner: String) -> Result<(), Error> {
         query("UPDATE projects SET owner = $1 WHERE id = $2;")
             .bind(&owner)
@@ -177,45 +341,175 @@ impl DatabaseProject {
         Ok(())
     }
 
-    pub fn get_flake(&self) -> String {
-        let header = self
-            .account_association
-            .as_ref()
-            .map(|json| json.header.clone())
-            .unwrap_or_default();
-        let payload = self
-            .account_association
-            .as_ref()
-            .map(|json| json.payload.clone())
-            .unwrap_or_default();
-        let signature = self
-            .account_association
-            .as_ref()
-            .map(|json| json.signature.clone())
-            .unwrap_or_default();
-        let allowed_addresses = self
-            .base_build
+    /// Move the project to another mini app host, RowNotFound while one of its deployments is rolling out on the current host
+    pub async fn update_miniapp_host(
+        &mut self,
+        database: &Database,
+        miniapp_host: i32,
+        network: String,
+    ) -> Result<(), Error> {
+        let result = query(
+            "UPDATE projects SET miniapp_host = $1, network = $2 WHERE id = $3 AND NOT EXISTS (SELECT 1 FROM deployments WHERE deployments.project = projects.name AND deployments.status = 'deploying');",
+        )
+        .bind(miniapp_host)
+        .bind(&network)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+
+        self.miniapp_host = Some(miniapp_host);
+        self.network = Some(network);
+
+        Ok(())
+    }
+
+    pub async fn update_reconcile_request(
+        &mut self,
+        database: &Database,
+        reconcile_request: Option<i64>,
+    ) -> Result<(), Error> {
+        query("UPDATE projects SET reconcile_request = $1 WHERE id = $2;")
+            .bind(reconcile_request)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.reconcile_request = reconcile_request;
+
+        Ok(())
+    }
+
+    pub async fn update_change_request(
+        &mut self,
+        database: &Database,
+        change_request: Option<i64>,
+    ) -> Result<(), Error> {
+        query("UPDATE projects SET change_request = $1 WHERE id = $2;")
+            .bind(change_request)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.change_request = change_request;
+
+        Ok(())
+    }
+
+    pub async fn update_reconcile_failed(
+        &mut self,
+        database: &Database,
+        error: String,
+        failed_at: i64,
+    ) -> Result<(), Error> {
+        query(
+            "UPDATE projects SET reconcile_failures = reconcile_failures + 1, reconcile_failed_at = $1, reconcile_error = $2 WHERE id = $3;",
+        )
+        .bind(failed_at)
+        .bind(&error)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.reconcile_failures += 1;
+        self.reconcile_failed_at = Some(failed_at);
+        self.reconcile_error = Some(error);
+
+        Ok(())
+    }
+
+    pub async fn reset_reconcile_failures(&mut self, database: &Database) -> Result<(), Error> {
+        query(
+            "UPDATE projects SET reconcile_failures = 0, reconcile_failed_at = NULL, reconcile_error = NULL WHERE id = $1;",
+        )
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.reconcile_failures = 0;
+        self.reconcile_failed_at = None;
+        self.reconcile_error = None;
+
+        Ok(())
+    }
+
+    /// Projects created before templates existed use the default template
+    pub fn get_template(&self) -> ProjectTemplate {
+        self.template
             .as_ref()
-            .map(|json| {
-                json.allowed_addresses
-                    .iter()
-                    .map(|address| format!("\"{address}\""))
-                    .collect::<Vec<String>>()
-                    .join(" ")
-            })
-            .unwrap_or_default();
+            .map(|template| template.as_ref().clone())
+            .unwrap_or_default()
+    }
+
+    pub fn get_flake(&self) -> String {
+        let template = self.get_template();
         let version = self
             .version
             .as_ref()
-            .map(|version| format!("/{version}"))
-            .unwrap_or_default()
-            .replace("\n", "");
+            .map(|version| version.replace("\n", ""));
+        let url = source_host().flake_url(&self.name, version.as_deref());
+
+        let mut settings = vec![
+            format!("{enable} = true;", enable = template.options.enable),
+            format!(
+                "{option} = \"https://{name}.miniapp-factory.marketplace.openxai.network\";",
+                option = template.options.url,
+                name = self.name
+            ),
+        ];
+        if let Some(option) = &template.options.account_association {
+            let header = self
+                .account_association
+                .as_ref()
+                .map(|json| json.header.clone())
+                .unwrap_or_default();
+            let payload = self
+                .account_association
+                .as_ref()
+                .map(|json| json.payload.clone())
+                .unwrap_or_default();
+            let signature = self
+                .account_association
+                .as_ref()
+                .map(|json| json.signature.clone())
+                .unwrap_or_default();
+            settings.push(format!(
+                "\
+{option} = {{
+            header = \"{header}\";
+            payload = \"{payload}\";
+            signature = \"{signature}\";
+          }};"
+            ));
+        }
+        if let Some(option) = &template.options.base_builder {
+            let allowed_addresses = self
+                .base_build
+                .as_ref()
+                .map(|json| {
+                    json.allowed_addresses
+                        .iter()
+                        .map(|address| format!("\"{address}\""))
+                        .collect::<Vec<String>>()
+                        .join(" ")
+                })
+                .unwrap_or_default();
+            settings.push(format!(
+                "\
+{option} = {{
+            allowedAddresses = [ {allowed_addresses} ];
+          }};"
+            ));
+        }
+
         format!(
             "\
 {{
   inputs = {{
     xnode-manager.url = \"github:Openmesh-Network/xnode-manager\";
-    xnode-miniapp-template.url = \"github:miniapp-factory/{name}{version}\";
+    xnode-miniapp-template.url = \"{url}\";
     nixpkgs.follows = \"xnode-miniapp-template/nixpkgs\";
   }};
 
@@ -233,33 +527,42 @@ impl DatabaseProject {
             hostname = ./xnode-config/hostname;
           }};
         }}
-        inputs.xnode-miniapp-template.nixosModules.default
+        inputs.xnode-miniapp-template.{module}
         {{
-          services.xnode-miniapp-template.enable = true;
-          services.xnode-miniapp-template.url = \"https://{name}.miniapp-factory.marketplace.openxai.network\";
-          services.xnode-miniapp-template.accountAssociation = {{
-            header = \"{header}\";
-            payload = \"{payload}\";
-            signature = \"{signature}\";
-          }};
-          services.xnode-miniapp-template.baseBuilder = {{
-            allowedAddresses = [ {allowed_addresses} ];
-          }};
+          {settings}
         }}
       ];
     }};
   }};
-}}", name = self.name
+}}",
+            module = template.module,
+            settings = settings.join("\n          ")
         )
     }
 
     pub fn get_network(&self) -> Option<String> {
-        let bracket = 1 + self.id / 500;
+        Some(
+            self.network
+                .clone()
+                .unwrap_or_else(|| network_of(self.id.into())),
+        )
+    }
+}
 
-        if bracket == 1 {
-            Some("containernet".to_string())
-        } else {
-            Some(format!("net{bracket}"))
-        }
+#[cfg(test)]
+mod tests {
+    use super::network_of;
+
+    #[test]
+    fn first_bracket_uses_the_default_network() {
+        assert_eq!(network_of(0), "containernet");
+        assert_eq!(network_of(499), "containernet");
+    }
+
+    #[test]
+    fn later_brackets_get_their_own_bridge() {
+        assert_eq!(network_of(500), "net2");
+        assert_eq!(network_of(999), "net2");
+        assert_eq!(network_of(1000), "net3");
     }
 }
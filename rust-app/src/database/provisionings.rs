@@ -0,0 +1,275 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, Type, query, query_as, query_scalar};
+
+use crate::{
+    database::{Database, DatabaseConnection, DatabaseTransaction},
+    utils::time::get_time_i64,
+};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS provisionings(id SERIAL PRIMARY KEY, project TEXT NOT NULL, account TEXT NOT NULL, credits INT8 NOT NULL, status TEXT NOT NULL, attempts INT4 NOT NULL, error TEXT, created_at INT8 NOT NULL, finished_at INT8, failed_step TEXT, rebuild_request INT8, container_request INT8, step_started_at INT8, failing_since INT8, retry_at INT8)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create provisionings table: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE provisionings ADD COLUMN IF NOT EXISTS failed_step TEXT;
+ALTER TABLE provisionings ADD COLUMN IF NOT EXISTS rebuild_request INT8;
+ALTER TABLE provisionings ADD COLUMN IF NOT EXISTS container_request INT8",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate provisionings requests: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE provisionings ADD COLUMN IF NOT EXISTS step_started_at INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate provisionings step_started_at: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE provisionings ADD COLUMN IF NOT EXISTS failing_since INT8;
+ALTER TABLE provisionings ADD COLUMN IF NOT EXISTS retry_at INT8",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate provisionings retries: {e}"));
+}
+
+/// Step of project creation that is executed next, steps are executed in declaration order
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum ProvisioningStatus {
+    Repository,
+    Expose,
+    Rebuild,
+    Container,
+    Ready,
+    Failed,
+}
+
+impl ProvisioningStatus {
+    pub fn next(&self) -> Self {
+        match self {
+            ProvisioningStatus::Repository => ProvisioningStatus::Expose,
+            ProvisioningStatus::Expose => ProvisioningStatus::Rebuild,
+            ProvisioningStatus::Rebuild => ProvisioningStatus::Container,
+            ProvisioningStatus::Container => ProvisioningStatus::Ready,
+            ProvisioningStatus::Ready => ProvisioningStatus::Ready,
+            ProvisioningStatus::Failed => ProvisioningStatus::Failed,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(self, ProvisioningStatus::Ready | ProvisioningStatus::Failed)
+    }
+}
+
+impl Display for ProvisioningStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let status = match self {
+            ProvisioningStatus::Repository => "repository",
+            ProvisioningStatus::Expose => "expose",
+            ProvisioningStatus::Rebuild => "rebuild",
+            ProvisioningStatus::Container => "container",
+            ProvisioningStatus::Ready => "ready",
+            ProvisioningStatus::Failed => "failed",
+        };
+        write!(f, "{status}")
+    }
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseProvisioning {
+    pub id: i32,
+    pub project: String,
+    pub account: String,
+    pub credits: i64,
+    pub status: ProvisioningStatus,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+    pub failed_step: Option<ProvisioningStatus>,
+    pub rebuild_request: Option<i64>,
+    pub container_request: Option<i64>,
+    /// Time the current step was started, the first step starts when the provisioning is created
+    pub step_started_at: Option<i64>,
+    /// Time of the first failure of the current step
+    pub failing_since: Option<i64>,
+    /// The current step is not attempted again before this time, after it failed
+    pub retry_at: Option<i64>,
+}
+
+impl DatabaseProvisioning {
+    pub fn get_step_started_at(&self) -> i64 {
+        self.step_started_at.unwrap_or(self.created_at)
+    }
+
+    pub async fn get_all_unfinished(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, project, account, credits, status, attempts, error, created_at, finished_at, failed_step, rebuild_request, container_request, step_started_at, failing_since, retry_at FROM provisionings WHERE status NOT IN ('ready', 'failed') ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, project, account, credits, status, attempts, error, created_at, finished_at, failed_step, rebuild_request, container_request, step_started_at, failing_since, retry_at FROM provisionings WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn get_latest_by_project(
+        database: &Database,
+        project: &str,
+    ) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, project, account, credits, status, attempts, error, created_at, finished_at, failed_step, rebuild_request, container_request, step_started_at, failing_since, retry_at FROM provisionings WHERE project = $1 ORDER BY id DESC LIMIT 1",
+        )
+        .bind(project)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn insert_in_transaction(
+        &mut self,
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO provisionings(project, account, credits, status, attempts, error, created_at, finished_at, failed_step, rebuild_request, container_request, step_started_at, failing_since, retry_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14) RETURNING id")
+            .bind(&self.project)
+            .bind(&self.account)
+            .bind(self.credits)
+            .bind(self.status)
+            .bind(self.attempts)
+            .bind(&self.error)
+            .bind(self.created_at)
+            .bind(self.finished_at)
+            .bind(self.failed_step)
+            .bind(self.rebuild_request)
+            .bind(self.container_request)
+            .bind(self.step_started_at)
+            .bind(self.failing_since)
+            .bind(self.retry_at)
+            .fetch_one(&mut **transaction)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
+    pub async fn update_status(
+        &mut self,
+        database: &Database,
+        status: ProvisioningStatus,
+        finished_at: Option<i64>,
+    ) -> Result<(), Error> {
+        let step_started_at = get_time_i64();
+        query(
+            "UPDATE provisionings SET status = $1, attempts = 0, error = NULL, finished_at = $2, step_started_at = $3, failing_since = NULL, retry_at = NULL WHERE id = $4;",
+        )
+        .bind(status)
+        .bind(finished_at)
+        .bind(step_started_at)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.status = status;
+        self.attempts = 0;
+        self.error = None;
+        self.finished_at = finished_at;
+        self.step_started_at = Some(step_started_at);
+        self.failing_since = None;
+        self.retry_at = None;
+
+        Ok(())
+    }
+
+    pub async fn update_error(
+        &mut self,
+        database: &Database,
+        error: String,
+        retry_at: i64,
+    ) -> Result<(), Error> {
+        let failing_since = self.failing_since.unwrap_or_else(get_time_i64);
+        query(
+            "UPDATE provisionings SET attempts = attempts + 1, error = $1, failing_since = $2, retry_at = $3 WHERE id = $4;",
+        )
+        .bind(&error)
+        .bind(failing_since)
+        .bind(retry_at)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.attempts += 1;
+        self.error = Some(error);
+        self.failing_since = Some(failing_since);
+        self.retry_at = Some(retry_at);
+
+        Ok(())
+    }
+
+    pub async fn update_failed(
+        &mut self,
+        database: &Database,
+        finished_at: i64,
+    ) -> Result<(), Error> {
+        query(
+            "UPDATE provisionings SET status = $1, finished_at = $2, failed_step = $3 WHERE id = $4;",
+        )
+        .bind(ProvisioningStatus::Failed)
+        .bind(finished_at)
+        .bind(self.status)
+        .bind(self.id)
+        .execute(&database.connection)
+        .await?;
+
+        self.failed_step = Some(self.status);
+        self.status = ProvisioningStatus::Failed;
+        self.finished_at = Some(finished_at);
+
+        Ok(())
+    }
+
+    pub async fn update_rebuild_request(
+        &mut self,
+        database: &Database,
+        rebuild_request: Option<i64>,
+    ) -> Result<(), Error> {
+        query("UPDATE provisionings SET rebuild_request = $1 WHERE id = $2;")
+            .bind(rebuild_request)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.rebuild_request = rebuild_request;
+
+        Ok(())
+    }
+
+    pub async fn update_container_request(
+        &mut self,
+        database: &Database,
+        container_request: Option<i64>,
+    ) -> Result<(), Error> {
+        query("UPDATE provisionings SET container_request = $1 WHERE id = $2;")
+            .bind(container_request)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.container_request = container_request;
+
+        Ok(())
+    }
+}
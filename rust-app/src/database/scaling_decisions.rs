@@ -0,0 +1,55 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, query_as, query_scalar, types::Json};
+
+use crate::database::{Database, DatabaseConnection};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS scaling_decisions(id SERIAL PRIMARY KEY, decided_at INT8 NOT NULL, queued INT8 NOT NULL, workers INT8 NOT NULL, desired INT8 NOT NULL, deploy INT8 NOT NULL, undeploy JSON NOT NULL, reason TEXT NOT NULL)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create scaling_decisions table: {e}"));
+}
+
+/// Autoscaler decision that changed the worker pool
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseScalingDecision {
+    pub id: i32,
+    pub decided_at: i64,
+    pub queued: i64,
+    /// Active (not draining) workers at the time of the decision
+    pub workers: i64,
+    pub desired: i64,
+    pub deploy: i64,
+    pub undeploy: Json<Vec<i32>>,
+    pub reason: String,
+}
+
+impl DatabaseScalingDecision {
+    pub async fn get_latest(database: &Database, limit: i64) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, decided_at, queued, workers, desired, deploy, undeploy, reason FROM scaling_decisions ORDER BY id DESC LIMIT $1",
+        )
+        .bind(limit)
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO scaling_decisions(decided_at, queued, workers, desired, deploy, undeploy, reason) VALUES ($1, $2, $3, $4, $5, $6, $7) RETURNING id")
+            .bind(self.decided_at)
+            .bind(self.queued)
+            .bind(self.workers)
+            .bind(self.desired)
+            .bind(self.deploy)
+            .bind(&self.undeploy)
+            .bind(&self.reason)
+            .fetch_one(&database.connection)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,156 @@
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, query, query_as, query_scalar, types::Json};
+
+use crate::{
+    database::{Database, DatabaseConnection},
+    utils::env::sourcetemplate,
+};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS templates(id SERIAL PRIMARY KEY, name TEXT UNIQUE NOT NULL, description TEXT NOT NULL, repository TEXT NOT NULL, module TEXT NOT NULL, options JSON NOT NULL, enabled BOOLEAN NOT NULL)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create templates table: {e}"));
+
+    let template = ProjectTemplate::default();
+    query("INSERT INTO templates(name, description, repository, module, options, enabled) VALUES ($1, $2, $3, $4, $5, TRUE) ON CONFLICT (name) DO NOTHING;")
+        .bind(&template.name)
+        .bind("Farcaster mini app")
+        .bind(&template.repository)
+        .bind(&template.module)
+        .bind(Json::from(template.options))
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not insert default template: {e}"));
+}
+
+/// NixOS option names the template module exposes, settings are only allowed when the template has an option for them
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct TemplateOptions {
+    pub enable: String,
+    pub url: String,
+    pub account_association: Option<String>,
+    pub base_builder: Option<String>,
+}
+
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseTemplate {
+    pub id: i32,
+    pub name: String,
+    pub description: String,
+    pub repository: String,
+    pub module: String,
+    pub options: Json<TemplateOptions>,
+    pub enabled: bool,
+}
+
+/// Snapshot of the template a project was created from
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ProjectTemplate {
+    pub id: i32,
+    pub name: String,
+    pub repository: String,
+    pub module: String,
+    pub options: TemplateOptions,
+}
+
+impl Default for ProjectTemplate {
+    fn default() -> Self {
+        Self {
+            id: 0,
+            name: "default".to_string(),
+            repository: sourcetemplate(),
+            module: "nixosModules.default".to_string(),
+            options: TemplateOptions {
+                enable: "services.xnode-miniapp-template.enable".to_string(),
+                url: "services.xnode-miniapp-template.url".to_string(),
+                account_association: Some(
+                    "services.xnode-miniapp-template.accountAssociation".to_string(),
+                ),
+                base_builder: Some("services.xnode-miniapp-template.baseBuilder".to_string()),
+            },
+        }
+    }
+}
+
+impl From<&DatabaseTemplate> for ProjectTemplate {
+    fn from(value: &DatabaseTemplate) -> Self {
+        Self {
+            id: value.id,
+            name: value.name.clone(),
+            repository: value.repository.clone(),
+            module: value.module.clone(),
+            options: value.options.as_ref().clone(),
+        }
+    }
+}
+
+impl DatabaseTemplate {
+    pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, name, description, repository, module, options, enabled FROM templates ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_all_enabled(database: &Database) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, name, description, repository, module, options, enabled FROM templates WHERE enabled = TRUE ORDER BY id ASC",
+        )
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, name, description, repository, module, options, enabled FROM templates WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn get_by_name(database: &Database, name: &str) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, name, description, repository, module, options, enabled FROM templates WHERE name = $1",
+        )
+        .bind(name)
+        .fetch_optional(&database.connection)
+        .await
+    }
+
+    pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO templates(name, description, repository, module, options, enabled) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id")
+            .bind(&self.name)
+            .bind(&self.description)
+            .bind(&self.repository)
+            .bind(&self.module)
+            .bind(&self.options)
+            .bind(self.enabled)
+            .fetch_one(&database.connection)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+
+    /// Existing projects keep the snapshot of the template they were created with
+    pub async fn update(&self, database: &Database) -> Result<(), Error> {
+        query("UPDATE templates SET name = $1, description = $2, repository = $3, module = $4, options = $5, enabled = $6 WHERE id = $7;")
+            .bind(&self.name)
+            .bind(&self.description)
+            .bind(&self.repository)
+            .bind(&self.module)
+            .bind(&self.options)
+            .bind(self.enabled)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        Ok(())
+    }
+}
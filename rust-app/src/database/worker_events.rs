@@ -0,0 +1,107 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use sqlx::{Error, FromRow, Type, query_as, query_scalar};
+
+use crate::database::{Database, DatabaseConnection, DatabaseTransaction};
+
+pub async fn create_table(connection: &DatabaseConnection) {
+    sqlx::raw_sql(
+        "CREATE TABLE IF NOT EXISTS worker_events(id SERIAL PRIMARY KEY, worker INT4 NOT NULL, event TEXT NOT NULL, deployment INT4, hourly_price INT8 NOT NULL, at INT8 NOT NULL)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create worker_events table: {e}"));
+
+    sqlx::raw_sql("CREATE INDEX IF NOT EXISTS worker_events_at ON worker_events(at)")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not create worker_events index: {e}"));
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum WorkerEventKind {
+    Deployed,
+    SetupFinished,
+    Assigned,
+    Unassigned,
+    Undeployed,
+}
+
+impl Display for WorkerEventKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let event = match self {
+            WorkerEventKind::Deployed => "deployed",
+            WorkerEventKind::SetupFinished => "setup_finished",
+            WorkerEventKind::Assigned => "assigned",
+            WorkerEventKind::Unassigned => "unassigned",
+            WorkerEventKind::Undeployed => "undeployed",
+        };
+        write!(f, "{event}")
+    }
+}
+
+/// Lifecycle event of a worker server, kept after the worker is undeployed
+#[derive(Debug, FromRow, Serialize, Deserialize)]
+pub struct DatabaseWorkerEvent {
+    pub id: i32,
+    pub worker: i32,
+    pub event: WorkerEventKind,
+    /// Deployment the worker was (un)assigned
+    pub deployment: Option<i32>,
+    /// Price of the worker flavor in USD cents per hour at the time of the event
+    pub hourly_price: i64,
+    pub at: i64,
+}
+
+impl DatabaseWorkerEvent {
+    pub async fn get_all_before(database: &Database, before: i64) -> Result<Vec<Self>, Error> {
+        query_as(
+            "SELECT id, worker, event, deployment, hourly_price, at FROM worker_events WHERE at < $1 ORDER BY at ASC, id ASC",
+        )
+        .bind(before)
+        .fetch_all(&database.connection)
+        .await
+    }
+
+    /// Average seconds between deployment and finished setup of the last `limit` workers
+    pub async fn get_average_setup_duration(
+        database: &Database,
+        limit: i64,
+    ) -> Result<Option<f64>, Error> {
+        query_scalar(
+            "SELECT AVG(duration)::FLOAT8 FROM (SELECT finished.at - deployed.at AS duration FROM worker_events deployed JOIN worker_events finished ON finished.worker = deployed.worker AND finished.event = 'setup_finished' WHERE deployed.event = 'deployed' ORDER BY finished.at DESC LIMIT $1) recent",
+        )
+        .bind(limit)
+        .fetch_one(&database.connection)
+        .await
+    }
+
+    /// When the worker was deployed, `None` for workers from before events were recorded
+    pub async fn get_deployed_at(database: &Database, worker: i32) -> Result<Option<i64>, Error> {
+        query_scalar("SELECT MAX(at) FROM worker_events WHERE worker = $1 AND event = 'deployed'")
+            .bind(worker)
+            .fetch_one(&database.connection)
+            .await
+    }
+
+    pub async fn insert_in_transaction(
+        &mut self,
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<(), Error> {
+        let id: i32 = query_scalar("INSERT INTO worker_events(worker, event, deployment, hourly_price, at) VALUES ($1, $2, $3, $4, $5) RETURNING id")
+            .bind(self.worker)
+            .bind(self.event)
+            .bind(self.deployment)
+            .bind(self.hourly_price)
+            .bind(self.at)
+            .fetch_one(&mut **transaction)
+            .await?;
+
+        self.id = id;
+
+        Ok(())
+    }
+}
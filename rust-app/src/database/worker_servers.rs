@@ -1,54 +1,237 @@
+use std::fmt::Display;
+
 use serde::{Deserialize, Serialize};
-use sqlx::{Error, FromRow, query, query_as, query_scalar, types::Json};
-use xnode_deployer::hyperstack::HyperstackOutput;
+use serde_json::Value;
+use sqlx::{Error, FromRow, Type, query, query_as, query_scalar, types::Json};
 
-use crate::database::{Database, DatabaseConnection};
+use crate::{
+    database::{
+        Database, DatabaseConnection, DatabaseTransaction,
+        deployments::{DatabaseDeployment, DeploymentStatus},
+        worker_events::{DatabaseWorkerEvent, WorkerEventKind},
+    },
+    utils::time::get_time_i64,
+};
 
 pub async fn create_table(connection: &DatabaseConnection) {
     sqlx::raw_sql(
-        "CREATE TABLE IF NOT EXISTS worker_servers(id SERIAL PRIMARY KEY, hardware JSON NOT NULL, coder_deployment INT8, imagegen_deployment INT8, setup_finished BOOL NOT NULL, assignment INT4, dynamic BOOL NOT NULL)",
+        "CREATE TABLE IF NOT EXISTS worker_servers(id SERIAL PRIMARY KEY, provider TEXT NOT NULL, handle JSON NOT NULL, coder_deployment INT8, imagegen_deployment INT8, setup_finished BOOL NOT NULL, assignment INT4, dynamic BOOL NOT NULL, unreachable_since INT8, draining BOOL NOT NULL DEFAULT FALSE, idle_since INT8, flavor TEXT NOT NULL DEFAULT '', hourly_price INT8 NOT NULL DEFAULT 0, deleted_at INT8, role TEXT NOT NULL DEFAULT 'both', assigned_at INT8, claim_backoff_until INT8)",
     )
     .execute(connection)
     .await
     .unwrap_or_else(|e| panic!("Could not create projects table: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS unreachable_since INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate worker_servers unreachable_since: {e}"));
+
+    // Worker servers used to always be Hyperstack virtual machines
+    sqlx::raw_sql(
+        "DO $$ BEGIN IF EXISTS (SELECT 1 FROM information_schema.columns WHERE table_name = 'worker_servers' AND column_name = 'hardware') THEN ALTER TABLE worker_servers RENAME COLUMN hardware TO handle; END IF; END $$",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate worker_servers handle: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS provider TEXT NOT NULL DEFAULT 'hyperstack'",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate worker_servers provider: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS draining BOOL NOT NULL DEFAULT FALSE",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate worker_servers draining: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS idle_since INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate worker_servers idle_since: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS flavor TEXT NOT NULL DEFAULT '', ADD COLUMN IF NOT EXISTS hourly_price INT8 NOT NULL DEFAULT 0, ADD COLUMN IF NOT EXISTS deleted_at INT8",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate worker_servers cost accounting: {e}"));
+
+    sqlx::raw_sql(
+        "ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'both'",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate worker_servers role: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS assigned_at INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate worker_servers assigned_at: {e}"));
+
+    sqlx::raw_sql("ALTER TABLE worker_servers ADD COLUMN IF NOT EXISTS claim_backoff_until INT8")
+        .execute(connection)
+        .await
+        .unwrap_or_else(|e| panic!("Could not migrate worker_servers claim_backoff_until: {e}"));
+
+    // Wake up the dispatcher when a worker becomes available
+    sqlx::raw_sql(
+        "CREATE OR REPLACE FUNCTION notify_worker_servers_dispatch()
+RETURNS TRIGGER AS $$
+BEGIN
+    PERFORM pg_notify('dispatch', 'worker_servers');
+    RETURN NEW;
+END;
+$$ LANGUAGE plpgsql",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create notify_worker_servers_dispatch function: {e}"));
+
+    sqlx::raw_sql(
+        "CREATE OR REPLACE TRIGGER trg_worker_servers_dispatch
+AFTER UPDATE OF assignment, setup_finished, unreachable_since, draining ON worker_servers
+FOR EACH ROW
+WHEN (NEW.assignment IS NULL AND NEW.setup_finished AND NEW.unreachable_since IS NULL AND NOT NEW.draining)
+EXECUTE FUNCTION notify_worker_servers_dispatch()",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not create trg_worker_servers_dispatch trigger: {e}"));
+
+    // Workers from before event recording are accounted from the moment of migration
+    sqlx::raw_sql(
+        "INSERT INTO worker_events(worker, event, hourly_price, at) SELECT id, 'deployed', hourly_price, EXTRACT(EPOCH FROM NOW())::INT8 FROM worker_servers WHERE deleted_at IS NULL AND NOT EXISTS (SELECT 1 FROM worker_events WHERE worker_events.worker = worker_servers.id)",
+    )
+    .execute(connection)
+    .await
+    .unwrap_or_else(|e| panic!("Could not migrate worker_events of existing workers: {e}"));
+}
+
+/// Kind of provider a worker server is managed by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum WorkerProviderKind {
+    Hyperstack,
+    Static,
+    Mock,
+}
+
+impl Display for WorkerProviderKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let provider = match self {
+            WorkerProviderKind::Hyperstack => "hyperstack",
+            WorkerProviderKind::Static => "static",
+            WorkerProviderKind::Mock => "mock",
+        };
+        write!(f, "{provider}")
+    }
+}
+
+/// Containers a worker server runs, which decides the queues it takes deployments from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, Type)]
+#[serde(rename_all = "snake_case")]
+#[sqlx(type_name = "TEXT", rename_all = "snake_case")]
+pub enum WorkerRole {
+    Both,
+    Coder,
+    Imagegen,
+}
+
+impl WorkerRole {
+    pub fn is_coder(&self) -> bool {
+        matches!(self, WorkerRole::Both | WorkerRole::Coder)
+    }
+
+    pub fn is_imagegen(&self) -> bool {
+        matches!(self, WorkerRole::Both | WorkerRole::Imagegen)
+    }
+
+    /// Names of the worker containers, which are also the names of their services and users
+    pub fn containers(&self) -> Vec<&'static str> {
+        let mut containers = vec![];
+        if self.is_coder() {
+            containers.push("miniapp-factory-coder");
+        }
+        if self.is_imagegen() {
+            containers.push("miniapp-factory-imagegen");
+        }
+        containers
+    }
+}
+
+impl Display for WorkerRole {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let role = match self {
+            WorkerRole::Both => "both",
+            WorkerRole::Coder => "coder",
+            WorkerRole::Imagegen => "imagegen",
+        };
+        write!(f, "{role}")
+    }
 }
 
 #[derive(Debug, FromRow, Serialize, Deserialize)]
 pub struct DatabaseWorkerServer {
     pub id: i32,
-    pub hardware: Json<HyperstackOutput>,
+    pub provider: WorkerProviderKind,
+    /// Provider specific reference to the worker (e.g. the Hyperstack virtual machine)
+    pub handle: Json<Value>,
     pub coder_deployment: Option<i64>,
     pub imagegen_deployment: Option<i64>,
     pub setup_finished: bool,
     pub assignment: Option<i32>,
+    /// Deployed by the factory itself and undeployed when idle, registered (static) workers are never undeployed
     pub dynamic: bool,
+    pub unreachable_since: Option<i64>,
+    /// Finishes its current assignment, but does not receive new ones
+    pub draining: bool,
+    /// Time the worker was last released (or deployed), None while it has an assignment
+    pub idle_since: Option<i64>,
+    pub flavor: String,
+    /// USD cents per hour
+    pub hourly_price: i64,
+    /// Undeployed or removed workers are kept for cost accounting
+    pub deleted_at: Option<i64>,
+    pub role: WorkerRole,
+    /// Time the current assignment was claimed
+    pub assigned_at: Option<i64>,
+    /// Not claimed before this time, after an assignment could not be started on it
+    pub claim_backoff_until: Option<i64>,
 }
 
 impl DatabaseWorkerServer {
-    #[allow(dead_code)]
     pub async fn get_all(database: &Database) -> Result<Vec<Self>, Error> {
-        query_as("SELECT id, hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic FROM worker_servers")
+        query_as("SELECT id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until FROM worker_servers WHERE deleted_at IS NULL")
             .fetch_all(&database.connection)
             .await
     }
 
-    pub async fn get_count(database: &Database) -> Result<i64, Error> {
-        query_scalar("SELECT COUNT(id) FROM worker_servers")
-            .fetch_one(&database.connection)
-            .await
+    pub async fn get_by_id(database: &Database, id: i32) -> Result<Option<Self>, Error> {
+        query_as(
+            "SELECT id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until FROM worker_servers WHERE deleted_at IS NULL AND id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&database.connection)
+        .await
     }
 
-    pub async fn get_all_no_setup_finished(database: &Database) -> Result<Vec<Self>, Error> {
-        query_as(
-            "SELECT id, hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic FROM worker_servers WHERE setup_finished = FALSE",
+    /// Draining workers are not counted
+    pub async fn get_count(database: &Database) -> Result<i64, Error> {
+        query_scalar(
+            "SELECT COUNT(id) FROM worker_servers WHERE deleted_at IS NULL AND draining = FALSE",
         )
-        .fetch_all(&database.connection)
+        .fetch_one(&database.connection)
         .await
     }
 
-    pub async fn get_all_dynamic_unassigned(database: &Database) -> Result<Vec<Self>, Error> {
+    pub async fn get_all_no_setup_finished(database: &Database) -> Result<Vec<Self>, Error> {
         query_as(
-            "SELECT id, hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic FROM worker_servers WHERE dynamic = TRUE AND assignment IS NULL",
+            "SELECT id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until FROM worker_servers WHERE deleted_at IS NULL AND setup_finished = FALSE",
         )
         .fetch_all(&database.connection)
         .await
@@ -56,43 +239,122 @@ impl DatabaseWorkerServer {
 
     pub async fn get_all_assigned(database: &Database) -> Result<Vec<Self>, Error> {
         query_as(
-            "SELECT id, hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic FROM worker_servers WHERE assignment IS NOT NULL",
+            "SELECT id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until FROM worker_servers WHERE deleted_at IS NULL AND assignment IS NOT NULL",
         )
         .fetch_all(&database.connection)
         .await
     }
 
-    pub async fn get_available(database: &Database) -> Result<Option<Self>, Error> {
+    /// Idle worker that is able to fulfill `role`
+    #[allow(dead_code)]
+    pub async fn get_available(
+        database: &Database,
+        role: WorkerRole,
+    ) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic FROM worker_servers WHERE setup_finished = TRUE AND assignment IS NULL LIMIT 1",
+            "SELECT id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until FROM worker_servers WHERE deleted_at IS NULL AND setup_finished = TRUE AND assignment IS NULL AND unreachable_since IS NULL AND draining = FALSE AND role IN ('both', $1) AND (claim_backoff_until IS NULL OR claim_backoff_until <= $2) ORDER BY role = 'both' ASC, id ASC LIMIT 1",
         )
+        .bind(role)
+        .bind(get_time_i64())
         .fetch_optional(&database.connection)
         .await
     }
 
+    /// Assign `deployment` to an idle worker that is able to fulfill `role` and move the deployment to `status`, in a single transaction
+    ///
+    /// Workers locked by a concurrent claim are skipped, RowNotFound when the deployment status was changed by someone else.
+    pub async fn claim_available(
+        database: &Database,
+        role: WorkerRole,
+        deployment: &mut DatabaseDeployment,
+        status: DeploymentStatus,
+    ) -> Result<Option<Self>, Error> {
+        let mut transaction = database.begin().await?;
+        let server: Option<Self> = query_as(
+            "UPDATE worker_servers SET assignment = $1, idle_since = NULL, assigned_at = $2 WHERE id = (SELECT id FROM worker_servers WHERE deleted_at IS NULL AND setup_finished = TRUE AND assignment IS NULL AND unreachable_since IS NULL AND draining = FALSE AND role IN ('both', $3) AND (claim_backoff_until IS NULL OR claim_backoff_until <= $2) ORDER BY role = 'both' ASC, id ASC LIMIT 1 FOR UPDATE SKIP LOCKED) RETURNING id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until",
+        )
+        .bind(deployment.id)
+        .bind(get_time_i64())
+        .bind(role)
+        .fetch_optional(&mut *transaction)
+        .await?;
+        let server = match server {
+            Some(server) => server,
+            None => {
+                return Ok(None);
+            }
+        };
+
+        deployment
+            .update_status_in_transaction(&mut transaction, status)
+            .await?;
+        server
+            .record_event(
+                &mut transaction,
+                WorkerEventKind::Assigned,
+                Some(deployment.id),
+            )
+            .await?;
+        transaction.commit().await?;
+
+        Ok(Some(server))
+    }
+
     pub async fn get_by_assignment(
         database: &Database,
         assignment: Option<i32>,
     ) -> Result<Option<Self>, Error> {
         query_as(
-            "SELECT id, hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic FROM worker_servers WHERE assignment = $1 LIMIT 1",
+            "SELECT id, provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until FROM worker_servers WHERE deleted_at IS NULL AND assignment = $1 LIMIT 1",
         ).bind(assignment)
         .fetch_optional(&database.connection)
         .await
     }
 
+    async fn record_event(
+        &self,
+        transaction: &mut DatabaseTransaction,
+        event: WorkerEventKind,
+        deployment: Option<i32>,
+    ) -> Result<(), Error> {
+        DatabaseWorkerEvent {
+            id: 0,
+            worker: self.id,
+            event,
+            deployment,
+            hourly_price: self.hourly_price,
+            at: get_time_i64(),
+        }
+        .insert_in_transaction(transaction)
+        .await
+    }
+
     pub async fn insert(&mut self, database: &Database) -> Result<(), Error> {
-        let id: i32 = query_scalar("INSERT INTO worker_servers(hardware, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic) VALUES ($1, $2, $3, $4, $5, $6) RETURNING id")
-            .bind(&self.hardware)
+        let mut transaction = database.begin().await?;
+        let id: i32 = query_scalar("INSERT INTO worker_servers(provider, handle, coder_deployment, imagegen_deployment, setup_finished, assignment, dynamic, unreachable_since, draining, idle_since, flavor, hourly_price, deleted_at, role, assigned_at, claim_backoff_until) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13, $14, $15, $16) RETURNING id")
+            .bind(self.provider)
+            .bind(&self.handle)
             .bind(self.coder_deployment)
             .bind(self.imagegen_deployment)
             .bind(self.setup_finished)
             .bind(self.assignment)
             .bind(self.dynamic)
-            .fetch_one(&database.connection)
+            .bind(self.unreachable_since)
+            .bind(self.draining)
+            .bind(self.idle_since)
+            .bind(&self.flavor)
+            .bind(self.hourly_price)
+            .bind(self.deleted_at)
+            .bind(self.role)
+            .bind(self.assigned_at)
+            .bind(self.claim_backoff_until)
+            .fetch_one(&mut *transaction)
             .await?;
 
         self.id = id;
+        self.record_event(&mut transaction, WorkerEventKind::Deployed, None)
+            .await?;
+        transaction.commit().await?;
 
         Ok(())
     }
@@ -134,39 +396,178 @@ impl DatabaseWorkerServer {
         database: &Database,
         setup_finished: bool,
     ) -> Result<(), Error> {
+        let mut transaction = database.begin().await?;
         query("UPDATE worker_servers SET setup_finished = $1 WHERE id = $2;")
             .bind(setup_finished)
             .bind(self.id)
-            .execute(&database.connection)
+            .execute(&mut *transaction)
             .await?;
+        if setup_finished && !self.setup_finished {
+            self.record_event(&mut transaction, WorkerEventKind::SetupFinished, None)
+                .await?;
+        }
+        transaction.commit().await?;
 
         self.setup_finished = setup_finished;
 
         Ok(())
     }
 
-    pub async fn update_assignment(
+    /// Lock the server until the transaction ends, as long as it still has the assignment of this copy
+    /// Whether the server still has the assignment of this copy
+    pub async fn is_current(&self, database: &Database) -> Result<bool, Error> {
+        let current: Option<i32> = query_scalar(
+            "SELECT id FROM worker_servers WHERE id = $1 AND assignment IS NOT DISTINCT FROM $2",
+        )
+        .bind(self.id)
+        .bind(self.assignment)
+        .fetch_optional(&database.connection)
+        .await?;
+
+        Ok(current.is_some())
+    }
+
+    /// Only releases the assignment of this copy, a copy read before the server was released and claimed again fails with RowNotFound
+    pub async fn release_in_transaction(
+        &mut self,
+        transaction: &mut DatabaseTransaction,
+    ) -> Result<(), Error> {
+        let idle_since = get_time_i64();
+        let result = query(
+            "UPDATE worker_servers SET assignment = NULL, idle_since = $1, assigned_at = NULL WHERE id = $2 AND assignment IS NOT DISTINCT FROM $3;",
+        )
+        .bind(idle_since)
+        .bind(self.id)
+        .bind(self.assignment)
+        .execute(&mut **transaction)
+        .await?;
+        if result.rows_affected() == 0 {
+            return Err(Error::RowNotFound);
+        }
+        if self.assignment.is_some() {
+            self.record_event(transaction, WorkerEventKind::Unassigned, self.assignment)
+                .await?;
+        }
+
+        self.assignment = None;
+        self.idle_since = Some(idle_since);
+        self.assigned_at = None;
+
+        Ok(())
+    }
+
+    pub async fn release(&mut self, database: &Database) -> Result<(), Error> {
+        let mut transaction = database.begin().await?;
+        self.release_in_transaction(&mut transaction).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    pub async fn update_unreachable_since(
         &mut self,
         database: &Database,
-        assignment: Option<i32>,
+        unreachable_since: Option<i64>,
     ) -> Result<(), Error> {
-        query("UPDATE worker_servers SET assignment = $1 WHERE id = $2;")
-            .bind(assignment)
+        query("UPDATE worker_servers SET unreachable_since = $1 WHERE id = $2;")
+            .bind(unreachable_since)
             .bind(self.id)
             .execute(&database.connection)
             .await?;
 
-        self.assignment = assignment;
+        self.unreachable_since = unreachable_since;
 
         Ok(())
     }
 
-    pub async fn delete(self, database: &Database) -> Result<(), Error> {
-        query("DELETE FROM worker_servers WHERE id = $1;")
+    pub async fn update_claim_backoff_until(
+        &mut self,
+        database: &Database,
+        claim_backoff_until: Option<i64>,
+    ) -> Result<(), Error> {
+        query("UPDATE worker_servers SET claim_backoff_until = $1 WHERE id = $2;")
+            .bind(claim_backoff_until)
             .bind(self.id)
             .execute(&database.connection)
             .await?;
 
+        self.claim_backoff_until = claim_backoff_until;
+
         Ok(())
     }
+
+    pub async fn update_draining(
+        &mut self,
+        database: &Database,
+        draining: bool,
+    ) -> Result<(), Error> {
+        query("UPDATE worker_servers SET draining = $1 WHERE id = $2;")
+            .bind(draining)
+            .bind(self.id)
+            .execute(&database.connection)
+            .await?;
+
+        self.draining = draining;
+
+        Ok(())
+    }
+
+    /// Soft delete, the worker and its events stay available for cost accounting
+    pub async fn delete(&mut self, database: &Database) -> Result<(), Error> {
+        let deleted_at = get_time_i64();
+        let mut transaction = database.begin().await?;
+        query("UPDATE worker_servers SET deleted_at = $1, assignment = NULL WHERE id = $2;")
+            .bind(deleted_at)
+            .bind(self.id)
+            .execute(&mut *transaction)
+            .await?;
+        self.record_event(
+            &mut transaction,
+            WorkerEventKind::Undeployed,
+            self.assignment,
+        )
+        .await?;
+        transaction.commit().await?;
+
+        self.deleted_at = Some(deleted_at);
+        self.assignment = None;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+impl DatabaseWorkerServer {
+    /// Set up dynamic worker without assignment since `idle_since`
+    pub fn idle(id: i32, idle_since: i64) -> Self {
+        Self {
+            id,
+            provider: WorkerProviderKind::Mock,
+            handle: Json(Value::Null),
+            coder_deployment: None,
+            imagegen_deployment: None,
+            setup_finished: true,
+            assignment: None,
+            dynamic: true,
+            unreachable_since: None,
+            draining: false,
+            idle_since: Some(idle_since),
+            flavor: "mock".to_string(),
+            hourly_price: 0,
+            deleted_at: None,
+            role: WorkerRole::Both,
+            assigned_at: None,
+            claim_backoff_until: None,
+        }
+    }
+
+    /// Set up dynamic worker working on `assignment`
+    pub fn assigned(id: i32, assignment: i32) -> Self {
+        Self {
+            assignment: Some(assignment),
+            idle_since: None,
+            assigned_at: Some(0),
+            ..Self::idle(id, 0)
+        }
+    }
 }
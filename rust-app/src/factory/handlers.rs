@@ -1,31 +1,36 @@
-use std::{process::Command, time::Duration};
-
 use actix_web::{HttpRequest, HttpResponse, Responder, get, post, web};
 use hex::ToHex;
 use regex::Regex;
-use tokio::time::sleep;
-use xnode_manager_sdk::{
-    file::{ReadFile, ReadFileInput, ReadFilePath},
-    process::{LogQuery, LogsInput, LogsPath},
-    utils::Output,
-};
+use sqlx::types::Json;
 
 use crate::{
     database::{
-        Database, credits::DatabaseCredits, deployments::DatabaseDeployment,
-        projects::DatabaseProject, promo_code::DatabasePromoCode,
+        Database,
+        credits::DatabaseCredits,
+        deployment_logs::DatabaseDeploymentLog,
+        deployments::{DatabaseDeployment, DeploymentPriority, DeploymentStatus},
+        miniapp_hosts::DatabaseMiniappHost,
+        projects::{DatabaseProject, network_of},
+        promo_code::DatabasePromoCode,
+        provisionings::{DatabaseProvisioning, ProvisioningStatus},
+        templates::{DatabaseTemplate, ProjectTemplate},
         worker_servers::DatabaseWorkerServer,
     },
     factory::models::{
-        AccountAssociation, Available, BaseBuild, Change, Create, History, LLMOutput, PromoCode,
-        PromoCodeRedeem, PromoCodessAddition, Queue, Reset,
+        AccountAssociation, Available, BaseBuild, Cancel, Change, ChangePrice, Create,
+        DeploymentStream, History, LLMOutput, PromoCode, PromoCodeRedeem, PromoCodessAddition,
+        Queue, Reset, Status, Transcripts,
     },
+    source_host::{SourceHost, source_host},
     utils::{
-        auth::get_session,
-        env::{gh, ghtoken},
+        auth::is_admin,
         error::ResponseError,
-        price::get_price,
-        runner::coding_server_session,
+        output::{capture_output, latest_live_output, read_live_output, stream_deployment},
+        price::{get_change_price, get_change_price_in_transaction, get_price, refund_deployment},
+        provisioning::provisioning_report,
+        runner::{coding_server_session, stop_and_release_server},
+        scheduler::{queue_estimate, queue_position},
+        sessions::{invalidate_host_session, project_host_session},
         time::get_time_i64,
         wallet::get_signer,
     },
@@ -86,6 +91,17 @@ async fn user_credits(database: web::Data<Database>, req: HttpRequest) -> impl R
     }
 }
 
+#[get("/templates")]
+async fn templates(database: web::Data<Database>) -> impl Responder {
+    match DatabaseTemplate::get_all_enabled(&database).await {
+        Ok(templates) => HttpResponse::Ok().json(templates),
+        Err(e) => {
+            log::error!("Could not get templates from the database: {e}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
 #[get("/project/available")]
 async fn project_available(
     database: web::Data<Database>,
@@ -175,6 +191,37 @@ async fn project_create(
         )));
     }
 
+    let template = match data.template {
+        Some(template) => DatabaseTemplate::get_by_id(&database, template).await,
+        None => DatabaseTemplate::get_by_name(&database, "default").await,
+    };
+    let template = match template {
+        Ok(template) => match template {
+            Some(template) if template.enabled => template,
+            _ => {
+                return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                    "Template {template:?} is not available.",
+                    template = data.template
+                )));
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get template {template:?} from the database: {e}",
+                template = data.template
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let mut transaction = match database.begin().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            log::error!("Could not start database transaction: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
     let price = get_price(&database, user).await;
     if let Err(_e) = (DatabaseCredits {
         account: user.to_string(),
@@ -182,12 +229,43 @@ async fn project_create(
         description: format!("Create project {project}", project = data.project),
         date: get_time_i64(),
     })
-    .insert(&database)
+    .insert_in_transaction(&mut transaction)
     .await
     {
         return HttpResponse::PaymentRequired().finish();
     }
 
+    let host = match DatabaseMiniappHost::place_in_transaction(&mut transaction).await {
+        Ok(host) => match host {
+            Some(host) => host,
+            None => {
+                return HttpResponse::ServiceUnavailable().json(ResponseError::new(
+                    "No mini app host has capacity for new projects.",
+                ));
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not place project {project} on a mini app host: {e}",
+                project = data.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let hosted =
+        match DatabaseProject::get_count_by_miniapp_host_in_transaction(&mut transaction, host.id)
+            .await
+        {
+            Ok(hosted) => hosted,
+            Err(e) => {
+                log::error!(
+                    "Could not get project count of mini app host {id}: {e}",
+                    id = host.id
+                );
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+
     let mut project = DatabaseProject {
         id: 0,
         name: data.project.clone(),
@@ -196,114 +274,98 @@ async fn project_create(
         base_build: None,
         version: None,
         nft_mint: None,
+        template: Some(Json::from(ProjectTemplate::from(&template))),
+        miniapp_host: Some(host.id),
+        network: Some(network_of(hosted)),
+        reconcile_request: None,
+        change_request: None,
+        reconcile_failures: 0,
+        reconcile_failed_at: None,
+        reconcile_error: None,
     };
-    if let Err(e) = project.insert(&database).await {
+    if let Err(e) = project.insert_in_transaction(&mut transaction).await {
         log::error!("Could insert {project:?} into the database: {e}",);
         return HttpResponse::InternalServerError().finish();
     }
 
-    let mut cli_command = Command::new(format!("{}gh", gh()));
-    cli_command
-        .env("GH_TOKEN", ghtoken())
-        .arg("repo")
-        .arg("create")
-        .arg(&data.project)
-        .arg("--public")
-        .arg("--template")
-        .arg("OpenxAI-Network/miniapp-factory-template");
-    if let Err(e) = cli_command.output() {
+    let mut provisioning = DatabaseProvisioning {
+        id: 0,
+        project: data.project.clone(),
+        account: user.to_string(),
+        credits: price,
+        status: ProvisioningStatus::Repository,
+        attempts: 0,
+        error: None,
+        created_at: get_time_i64(),
+        finished_at: None,
+        failed_step: None,
+        rebuild_request: None,
+        container_request: None,
+        step_started_at: None,
+        failing_since: None,
+        retry_at: None,
+    };
+    if let Err(e) = provisioning.insert_in_transaction(&mut transaction).await {
+        log::error!("Could insert {provisioning:?} into the database: {e}",);
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    if let Err(e) = transaction.commit().await {
         log::error!(
-            "Could not create github project {project}: {e}",
+            "Could not commit project {project} into database: {e}",
             project = data.project
         );
         return HttpResponse::InternalServerError().finish();
     }
 
-    match get_session(
-        "https://miniapp-host.xnode-manager.openxai.org",
-        "miniapp-host.xnode-manager.openxai.org",
-    )
-    .await
-    {
-        Ok(session) => {
-            // update os expose file with all projects to expose
-            let projects: Vec<String> = match DatabaseProject::get_all(&database).await {
-                Ok(projects) => projects.into_iter().map(|project| project.name).collect(),
-                Err(e) => {
-                    log::error!("Could not get projects from database: {e}",);
-                    return HttpResponse::InternalServerError().finish();
-                }
-            };
-            if let Err(e) =
-                xnode_manager_sdk::file::write_file(xnode_manager_sdk::file::WriteFileInput {
-                    session: &session,
-                    path: xnode_manager_sdk::file::WriteFilePath {
-                        scope: "host".to_string(),
-                    },
-                    data: xnode_manager_sdk::file::WriteFile {
-                        path: "/etc/nixos/exposed".to_string(),
-                        content: projects.join("\n").into(),
-                    },
-                })
-                .await
-            {
-                log::error!("Could not update mini app host expose file: {e:?}");
-                return HttpResponse::InternalServerError().finish();
-            }
-
-            // rebuild os
-            if let Err(e) =
-                xnode_manager_sdk::os::set(xnode_manager_sdk::os::SetInput::new_with_data(
-                    &session,
-                    xnode_manager_sdk::os::OSChange {
-                        flake: None,
-                        update_inputs: Some(vec![]),
-                        xnode_owner: None,
-                        domain: None,
-                        acme_email: None,
-                        user_passwd: None,
-                    },
-                ))
-                .await
-            {
-                log::error!("Could not update mini app host os: {e:?}");
-                return HttpResponse::InternalServerError().finish();
-            }
+    HttpResponse::Ok().json(provisioning.id)
+}
 
-            sleep(Duration::from_secs(1)).await;
+#[get("/project/status")]
+async fn project_status(
+    database: web::Data<Database>,
+    data: web::Query<Status>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match req
+        .headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(header) => header,
+        _ => {
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
 
-            // deploy project container
-            if let Err(e) = xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
-                session: &session,
-                path: xnode_manager_sdk::config::SetPath {
-                    container: data.project.clone(),
-                },
-                data: xnode_manager_sdk::config::ContainerChange {
-                    settings: {
-                        xnode_manager_sdk::config::ContainerSettings {
-                            flake: project.get_flake(),
-                            network: project.get_network(),
-                            nvidia_gpus: None,
-                        }
-                    },
-                    update_inputs: None,
-                },
-            })
-            .await
-            {
-                log::error!(
-                    "Could not update mini app host project {project}: {e:?}",
-                    project = data.project
-                );
-                return HttpResponse::InternalServerError().finish();
-            }
+    let provisioning = match (data.provisioning, &data.project) {
+        (Some(id), _) => DatabaseProvisioning::get_by_id(&database, id).await,
+        (None, Some(project)) => {
+            DatabaseProvisioning::get_latest_by_project(&database, project).await
+        }
+        (None, None) => {
+            return HttpResponse::BadRequest().json(ResponseError::new(
+                "Either a provisioning id or a project is required.",
+            ));
         }
+    };
+    let provisioning = match provisioning {
+        Ok(provisioning) => match provisioning {
+            Some(provisioning) => provisioning,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
         Err(e) => {
-            return HttpResponse::InternalServerError().json(e);
+            log::error!("Could not get provisioning from the database: {e}");
+            return HttpResponse::InternalServerError().finish();
         }
+    };
+    if provisioning.account != user {
+        return HttpResponse::Unauthorized().finish();
     }
 
-    HttpResponse::Ok().finish()
+    HttpResponse::Ok().json(provisioning_report(&database, provisioning).await)
 }
 
 #[post("/project/change")]
@@ -352,6 +414,27 @@ async fn project_change(
         return HttpResponse::Unauthorized().finish();
     }
 
+    match DatabaseProvisioning::get_latest_by_project(&database, &data.project).await {
+        Ok(provisioning) => {
+            if let Some(provisioning) = provisioning
+                && provisioning.status != ProvisioningStatus::Ready
+            {
+                return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                    "Project {project} is still being provisioned ({status}).",
+                    project = data.project,
+                    status = provisioning.status
+                )));
+            }
+        }
+        Err(e) => {
+            log::error!(
+                "Could not get provisioning of project {project} from the database: {e}",
+                project = data.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
     let unfinished = match DatabaseDeployment::get_all_by_project_unfinished(
         &database,
         &data.project,
@@ -374,6 +457,35 @@ async fn project_change(
         )));
     }
 
+    let priority = if data.express.unwrap_or(false) {
+        DeploymentPriority::Express
+    } else {
+        DeploymentPriority::Standard
+    };
+    let mut transaction = match database.begin().await {
+        Ok(transaction) => transaction,
+        Err(e) => {
+            log::error!("Could not start database transaction: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let credits = match get_change_price_in_transaction(
+        &mut transaction,
+        user,
+        &data.project,
+        priority,
+    )
+    .await
+    {
+        Ok(credits) => credits,
+        Err(e) => {
+            log::error!(
+                "Could not get change price of project {project}: {e}",
+                project = data.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
     let mut deployment = DatabaseDeployment {
         id: 0,
         project: data.project.clone(),
@@ -387,15 +499,79 @@ async fn project_change(
         imagegen_git_hash: None,
         deployment_request: None,
         deleted: false,
+        status: DeploymentStatus::Queued,
+        failure_reason: None,
+        retries: 0,
+        account: Some(user.to_string()),
+        credits,
+        imagegen_mode: data.imagegen.unwrap_or_default(),
+        priority,
+        rollout_finished_at: None,
+        rollout_error: None,
     };
-    if let Err(e) = deployment.insert(&database).await {
+    if let Err(e) = deployment.insert_in_transaction(&mut transaction).await {
         log::error!("Could not insert deployment {deployment:?} into database: {e}");
         return HttpResponse::InternalServerError().finish();
     }
+    if deployment.credits != 0
+        && let Err(_e) = (DatabaseCredits {
+            account: user.to_string(),
+            credits: -deployment.credits,
+            description: format!(
+                "Deployment {deployment} of project {project}",
+                deployment = deployment.id,
+                project = data.project
+            ),
+            date: get_time_i64(),
+        })
+        .insert_in_transaction(&mut transaction)
+        .await
+    {
+        return HttpResponse::PaymentRequired().finish();
+    }
+    if let Err(e) = transaction.commit().await {
+        log::error!(
+            "Could not commit deployment {deployment} into database: {e}",
+            deployment = deployment.id
+        );
+        return HttpResponse::InternalServerError().finish();
+    }
 
     HttpResponse::Ok().json(deployment.id)
 }
 
+#[get("/project/change/price")]
+async fn project_change_price(
+    database: web::Data<Database>,
+    data: web::Query<ChangePrice>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match req
+        .headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(header) => header,
+        _ => {
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+
+    if !valid_project(&data.project) {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "{project} is not a valid project name.",
+            project = data.project
+        )));
+    }
+
+    let priority = if data.express.unwrap_or(false) {
+        DeploymentPriority::Express
+    } else {
+        DeploymentPriority::Standard
+    };
+    HttpResponse::Ok().json(get_change_price(&database, user, &data.project, priority).await)
+}
+
 #[get("/project/history")]
 async fn project_history(
     database: web::Data<Database>,
@@ -507,7 +683,7 @@ async fn project_reset(
     if let Some(deployment_id) = data.deployment {
         version = match DatabaseDeployment::get_by_id(&database, deployment_id).await {
             Ok(deployment) => match deployment {
-                Some(deployment) => {
+                Some(mut deployment) => {
                     if deployment.project != data.project {
                         return HttpResponse::BadRequest().json(ResponseError::new(format!(
                             "Deployment {deployment_id} does not belong to {project}.",
@@ -530,7 +706,34 @@ async fn project_reset(
                         return HttpResponse::InternalServerError().finish();
                     }
 
-                    deployment.imagegen_git_hash
+                    if deployment.status == DeploymentStatus::Superseded {
+                        if let Err(e) = deployment
+                            .update_status(&database, DeploymentStatus::Live, None)
+                            .await
+                        {
+                            log::error!(
+                                "Could not mark deployment {id} as live: {e}",
+                                id = deployment.id
+                            );
+                            return HttpResponse::InternalServerError().finish();
+                        }
+
+                        if let Err(e) = DatabaseDeployment::supersede_all_live(
+                            &database,
+                            &deployment.project,
+                            deployment.id,
+                        )
+                        .await
+                        {
+                            log::error!(
+                                "Could not supersede other deployments of {project}: {e}",
+                                project = deployment.project
+                            );
+                            return HttpResponse::InternalServerError().finish();
+                        }
+                    }
+
+                    deployment.git_hash()
                 }
                 None => {
                     return HttpResponse::BadRequest().json(ResponseError::new(format!(
@@ -552,36 +755,21 @@ async fn project_reset(
             return HttpResponse::InternalServerError().finish();
         }
 
-        let mut cli_command = Command::new(format!("{}gh", gh()));
-        cli_command
-            .env("GH_TOKEN", ghtoken())
-            .arg("repo")
-            .arg("delete")
-            .arg(format!(
-                "miniapp-factory/{project}",
-                project = &data.project
-            ))
-            .arg("--yes");
-        if let Err(e) = cli_command.output() {
+        let source_host = source_host();
+        if let Err(e) = source_host.delete(&data.project).await {
             log::error!(
-                "Could not delete github project {project}: {e}",
+                "Could not delete repository {project}: {e}",
                 project = data.project
             );
             return HttpResponse::InternalServerError().finish();
         }
 
-        let mut cli_command = Command::new(format!("{}gh", gh()));
-        cli_command
-            .env("GH_TOKEN", ghtoken())
-            .arg("repo")
-            .arg("create")
-            .arg(&data.project)
-            .arg("--public")
-            .arg("--template")
-            .arg("OpenxAI-Network/miniapp-factory-template");
-        if let Err(e) = cli_command.output() {
+        if let Err(e) = source_host
+            .create_from_template(&data.project, &project.get_template().repository)
+            .await
+        {
             log::error!(
-                "Could not create github project {project}: {e}",
+                "Could not create repository {project}: {e}",
                 project = data.project
             );
             return HttpResponse::InternalServerError().finish();
@@ -596,13 +784,8 @@ async fn project_reset(
         return HttpResponse::InternalServerError().finish();
     }
 
-    let deployment_request = match get_session(
-        "https://miniapp-host.xnode-manager.openxai.org",
-        "miniapp-host.xnode-manager.openxai.org",
-    )
-    .await
-    {
-        Ok(session) => {
+    let deployment_request = match project_host_session(&database, &project).await {
+        Ok((host, session)) => {
             match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
                 session: &session,
                 path: xnode_manager_sdk::config::SetPath {
@@ -623,6 +806,7 @@ async fn project_reset(
             {
                 Ok(request_response) => request_response.request_id,
                 Err(e) => {
+                    invalidate_host_session(host.id, &session).await;
                     log::error!(
                         "Could not update mini app host project {project}: {e:?}",
                         project = project.name
@@ -632,11 +816,22 @@ async fn project_reset(
             }
         }
         Err(e) => {
-            log::error!("Could not get xnode session with miniapp-host: {e:?}");
+            log::error!("{e}");
             return HttpResponse::InternalServerError().finish();
         }
     };
 
+    // Followed by the container reconciler, which leaves the project alone until then
+    if let Err(e) = project
+        .update_change_request(&database, Some(deployment_request.into()))
+        .await
+    {
+        log::error!(
+            "Could not store change request of project {project}: {e}",
+            project = project.name
+        );
+    }
+
     HttpResponse::Ok().json(deployment_request)
 }
 
@@ -686,6 +881,13 @@ async fn project_account_association(
         return HttpResponse::Unauthorized().finish();
     }
 
+    if project.get_template().options.account_association.is_none() {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "The template of {project} does not support a account association.",
+            project = data.project
+        )));
+    }
+
     if let Err(e) = project
         .update_account_association(&database, data.account_association.clone())
         .await
@@ -698,13 +900,8 @@ async fn project_account_association(
         return HttpResponse::InternalServerError().finish();
     }
 
-    let deployment_request = match get_session(
-        "https://miniapp-host.xnode-manager.openxai.org",
-        "miniapp-host.xnode-manager.openxai.org",
-    )
-    .await
-    {
-        Ok(session) => {
+    let deployment_request = match project_host_session(&database, &project).await {
+        Ok((host, session)) => {
             match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
                 session: &session,
                 path: xnode_manager_sdk::config::SetPath {
@@ -725,6 +922,7 @@ async fn project_account_association(
             {
                 Ok(request_response) => request_response.request_id,
                 Err(e) => {
+                    invalidate_host_session(host.id, &session).await;
                     log::error!(
                         "Could not update mini app host project {project}: {e:?}",
                         project = project.name
@@ -734,11 +932,22 @@ async fn project_account_association(
             }
         }
         Err(e) => {
-            log::error!("Could not get xnode session with miniapp-host: {e:?}");
+            log::error!("{e}");
             return HttpResponse::InternalServerError().finish();
         }
     };
 
+    // Followed by the container reconciler, which leaves the project alone until then
+    if let Err(e) = project
+        .update_change_request(&database, Some(deployment_request.into()))
+        .await
+    {
+        log::error!(
+            "Could not store change request of project {project}: {e}",
+            project = project.name
+        );
+    }
+
     HttpResponse::Ok().json(deployment_request)
 }
 
@@ -788,6 +997,13 @@ async fn project_base_build(
         return HttpResponse::Unauthorized().finish();
     }
 
+    if project.get_template().options.base_builder.is_none() {
+        return HttpResponse::BadRequest().json(ResponseError::new(format!(
+            "The template of {project} does not support a base build.",
+            project = data.project
+        )));
+    }
+
     if let Err(e) = project
         .update_base_build(&database, data.base_build.clone())
         .await
@@ -800,13 +1016,8 @@ async fn project_base_build(
         return HttpResponse::InternalServerError().finish();
     }
 
-    let deployment_request = match get_session(
-        "https://miniapp-host.xnode-manager.openxai.org",
-        "miniapp-host.xnode-manager.openxai.org",
-    )
-    .await
-    {
-        Ok(session) => {
+    let deployment_request = match project_host_session(&database, &project).await {
+        Ok((host, session)) => {
             match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
                 session: &session,
                 path: xnode_manager_sdk::config::SetPath {
@@ -827,6 +1038,7 @@ async fn project_base_build(
             {
                 Ok(request_response) => request_response.request_id,
                 Err(e) => {
+                    invalidate_host_session(host.id, &session).await;
                     log::error!(
                         "Could not update mini app host project {project}: {e:?}",
                         project = project.name
@@ -836,11 +1048,22 @@ async fn project_base_build(
             }
         }
         Err(e) => {
-            log::error!("Could not get xnode session with miniapp-host: {e:?}");
+            log::error!("{e}");
             return HttpResponse::InternalServerError().finish();
         }
     };
 
+    // Followed by the container reconciler, which leaves the project alone until then
+    if let Err(e) = project
+        .update_change_request(&database, Some(deployment_request.into()))
+        .await
+    {
+        log::error!(
+            "Could not store change request of project {project}: {e}",
+            project = project.name
+        );
+    }
+
     HttpResponse::Ok().json(deployment_request)
 }
 
@@ -913,77 +1136,165 @@ async fn deployment_llm_output(
     };
 
     let llm_output = match server {
-        Some(server) => match coding_server_session(&server).await {
-            Some(session) => {
-                if deployment.coding_finished_at.is_none() {
-                    match xnode_manager_sdk::file::read_file(ReadFileInput {
-                        session: &session,
-                        path: ReadFilePath {
-                            scope: "container:miniapp-factory-coder".to_string(),
-                        },
-                        query: ReadFile {
-                            path: format!(
-                                "/var/lib/miniapp-factory-coder/projects/{project}/.aider.chat.history.md",
-                                project = deployment.project
-                            ),
-                        },
-                    }).await {
-                        Ok(chat) => {match chat.content {
-                            Output::UTF8 { output } => output,
-                            Output::Bytes { output } => {
-                                    String::from_utf8_lossy(&output).to_string()
-                            },
-                        }},
-                        Err(e) => {
-                            log::warn!("Error reading {project} chat from {server}: {e:?}", project = deployment.project, server = server.id);
-                            "".to_string()
-                        },
-                    }
-                } else if deployment.imagegen_finished_at.is_none() {
-                    match xnode_manager_sdk::process::logs(LogsInput {
-                        session: &session,
-                        path: LogsPath {
-                            scope: "container:miniapp-factory-imagegen".to_string(),
-                            process: "comfyui.service".to_string(),
-                        },
-                        query: LogQuery {
-                            level: None,
-                            max: None,
-                        },
-                    })
-                    .await
-                    {
-                        Ok(logs) => logs
-                            .into_iter()
-                            .map(|log| match log.message {
-                                Output::UTF8 { output } => output,
-                                Output::Bytes { output } => {
-                                    String::from_utf8_lossy(&output).to_string()
-                                }
-                            })
-                            .collect::<Vec<String>>()
-                            .join("\n"),
-                        Err(e) => {
-                            log::warn!(
-                                "Error reading {project} logs from {server}: {e:?}",
-                                project = deployment.project,
-                                server = server.id
-                            );
-                            "".to_string()
-                        }
-                    }
-                } else {
+        Some(server) => match latest_live_output(deployment.id, deployment.status) {
+            // Already polled for a stream of this deployment
+            Some(output) => output.to_string(),
+            None => match coding_server_session(&server).await {
+                Some(session) => read_live_output(&session, &server, &deployment).await,
+                None => "".to_string(),
+            },
+        },
+        None => {
+            match DatabaseDeploymentLog::get_latest_by_deployment(&database, deployment.id).await {
+                Ok(deployment_log) => deployment_log
+                    .map(|deployment_log| deployment_log.content)
+                    .unwrap_or_default(),
+                Err(e) => {
+                    log::error!(
+                        "Could not get stored output of deployment {deployment} from the database: {e}",
+                        deployment = deployment.id
+                    );
                     "".to_string()
                 }
             }
-            None => "".to_string(),
-        },
-        None => "".to_string(),
+        }
     };
 
     HttpResponse::Ok().json(llm_output)
 }
 
+#[get("/deployment/transcripts")]
+async fn deployment_transcripts(
+    database: web::Data<Database>,
+    data: web::Query<Transcripts>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match req
+        .headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(header) => header,
+        _ => {
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+
+    let deployment = match DatabaseDeployment::get_by_id(&database, data.deployment).await {
+        Ok(deployment) => match deployment {
+            Some(deployment) => deployment,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get deployment {deployment} from the database: {e}",
+                deployment = data.deployment
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let project = match DatabaseProject::get_by_name(&database, &deployment.project).await {
+        Ok(project) => match project {
+            Some(project) => project,
+            None => {
+                return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                    "{project} does not exist.",
+                    project = deployment.project
+                )));
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get project {project} from the database: {e}",
+                project = deployment.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if project.owner != user {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match DatabaseDeploymentLog::get_all_by_deployment(&database, deployment.id).await {
+        Ok(deployment_logs) => HttpResponse::Ok().json(deployment_logs),
+        Err(e) => {
+            log::error!(
+                "Could not get stored output of deployment {deployment} from the database: {e}",
+                deployment = deployment.id
+            );
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/deployment/stream")]
+async fn deployment_stream(
+    database: web::Data<Database>,
+    data: web::Query<DeploymentStream>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match req
+        .headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(header) => header,
+        _ => {
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+
+    let deployment = match DatabaseDeployment::get_by_id(&database, data.deployment).await {
+        Ok(deployment) => match deployment {
+            Some(deployment) => deployment,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get deployment {deployment} from the database: {e}",
+                deployment = data.deployment
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let project = match DatabaseProject::get_by_name(&database, &deployment.project).await {
+        Ok(project) => match project {
+            Some(project) => project,
+            None => {
+                return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                    "{project} does not exist.",
+                    project = deployment.project
+                )));
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get project {project} from the database: {e}",
+                project = deployment.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if project.owner != user {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        .streaming(stream_deployment(
+            database.get_ref().clone(),
+            deployment.id,
+            data.offset.unwrap_or(0),
+        ))
+}
+
 #[get("/deployment/queue")]
 async fn deployment_queue(
     database: web::Data<Database>,
@@ -1039,11 +1350,84 @@ async fn deployment_queue(
         return HttpResponse::Unauthorized().finish();
     }
 
-    match DatabaseDeployment::get_queued_count_before(&database, data.deployment).await {
+    // Deployments that are not queued (anymore) keep the count of before the scheduler
+    let position = match queue_position(&database, data.deployment).await {
+        Ok(Some(position)) => Ok(position),
+        Ok(None) => DatabaseDeployment::get_queued_count_before(&database, data.deployment).await,
+        Err(e) => Err(e),
+    };
+    match position {
         Ok(count) => HttpResponse::Ok().json(count),
         Err(e) => {
             log::error!(
-                "Could not get queued count before {deployment} from the database: {e}",
+                "Could not get queue position of {deployment} from the database: {e}",
+                deployment = data.deployment
+            );
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[get("/deployment/queue/estimate")]
+async fn deployment_queue_estimate(
+    database: web::Data<Database>,
+    data: web::Query<Queue>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match req
+        .headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(header) => header,
+        _ => {
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+
+    let deployment = match DatabaseDeployment::get_by_id(&database, data.deployment).await {
+        Ok(deployment) => match deployment {
+            Some(deployment) => deployment,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get deployment {deployment} from the database: {e}",
+                deployment = data.deployment
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let project = match DatabaseProject::get_by_name(&database, &deployment.project).await {
+        Ok(project) => match project {
+            Some(project) => project,
+            None => {
+                return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                    "{project} does not exist.",
+                    project = deployment.project
+                )));
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get project {project} from the database: {e}",
+                project = deployment.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if project.owner != user {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    match queue_estimate(&database, data.deployment).await {
+        Ok(estimate) => HttpResponse::Ok().json(estimate),
+        Err(e) => {
+            log::error!(
+                "Could not get queue estimate of {deployment} from the database: {e}",
                 deployment = data.deployment
             );
             HttpResponse::InternalServerError().finish()
@@ -1051,6 +1435,113 @@ async fn deployment_queue(
     }
 }
 
+#[post("/deployment/cancel")]
+async fn deployment_cancel(
+    database: web::Data<Database>,
+    data: web::Json<Cancel>,
+    req: HttpRequest,
+) -> impl Responder {
+    let user = match req
+        .headers()
+        .get("xnode-auth-user")
+        .and_then(|header| header.to_str().ok())
+    {
+        Some(header) => header,
+        _ => {
+            return HttpResponse::Unauthorized().finish();
+        }
+    };
+
+    let mut deployment = match DatabaseDeployment::get_by_id(&database, data.deployment).await {
+        Ok(deployment) => match deployment {
+            Some(deployment) => deployment,
+            None => {
+                return HttpResponse::NotFound().finish();
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get deployment {deployment} from the database: {e}",
+                deployment = data.deployment
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let project = match DatabaseProject::get_by_name(&database, &deployment.project).await {
+        Ok(project) => match project {
+            Some(project) => project,
+            None => {
+                return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                    "{project} does not exist.",
+                    project = deployment.project
+                )));
+            }
+        },
+        Err(e) => {
+            log::error!(
+                "Could not get project {project} from the database: {e}",
+                project = deployment.project
+            );
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    if project.owner != user {
+        return HttpResponse::Unauthorized().finish();
+    }
+
+    let phase = deployment.status;
+    let running = match deployment.status {
+        DeploymentStatus::Queued | DeploymentStatus::ImagegenQueued => false,
+        DeploymentStatus::Coding | DeploymentStatus::Imagegen => true,
+        status => {
+            return HttpResponse::BadRequest().json(ResponseError::new(format!(
+                "Deployment {deployment} is {status} and can no longer be cancelled.",
+                deployment = deployment.id
+            )));
+        }
+    };
+
+    // Cancel before touching the worker, so finish_deployment does not pick up its output
+    if let Err(e) = deployment
+        .update_status(&database, DeploymentStatus::Cancelled, None)
+        .await
+    {
+        log::warn!(
+            "Could not cancel deployment {deployment}: {e}",
+            deployment = deployment.id
+        );
+        return HttpResponse::Conflict().json(ResponseError::new(format!(
+            "Deployment {deployment} changed status, please try again.",
+            deployment = deployment.id
+        )));
+    }
+
+    if running {
+        match DatabaseWorkerServer::get_by_assignment(&database, Some(deployment.id)).await {
+            Ok(server) => {
+                if let Some(mut server) = server {
+                    let session = coding_server_session(&server).await;
+                    if let Some(session) = &session {
+                        capture_output(&database, session, &server, &deployment, phase).await;
+                    }
+                    stop_and_release_server(&database, &mut server, session.as_deref()).await;
+                }
+            }
+            Err(e) => {
+                log::error!(
+                    "Could not get server assigned to deployment {deployment}: {e}",
+                    deployment = deployment.id
+                );
+            }
+        }
+    }
+
+    refund_deployment(&database, &deployment).await;
+
+    HttpResponse::Ok().finish()
+}
+
 #[post("/promo_code/redeem")]
 async fn code_redeem(
     database: web::Data<Database>,
@@ -1119,7 +1610,7 @@ async fn code_add(
         }
     };
 
-    if user != "eth:519ce4c129a981b2cbb4c3990b1391da24e8ebf3" {
+    if !is_admin(user) {
         return HttpResponse::Unauthorized().finish();
     }
 
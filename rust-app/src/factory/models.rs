@@ -1,6 +1,6 @@
 use serde::{Deserialize, Serialize};
 
-use crate::database::projects;
+use crate::database::{deployments::ImagegenMode, projects};
 
 #[derive(Serialize, Deserialize)]
 pub struct Available {
@@ -10,12 +10,31 @@ pub struct Available {
 #[derive(Serialize, Deserialize)]
 pub struct Create {
     pub project: String,
+    pub template: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct Status {
+    /// Latest provisioning of the project, when no provisioning id is given
+    pub project: Option<String>,
+    /// Provisioning id returned by project create
+    pub provisioning: Option<i32>,
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct Change {
     pub project: String,
     pub instructions: String,
+    /// Force or skip image generation, by default images are only generated when the change needs new assets
+    pub imagegen: Option<ImagegenMode>,
+    /// Schedule before standard deployments for an extra charge
+    pub express: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ChangePrice {
+    pub project: String,
+    pub express: Option<bool>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -46,11 +65,27 @@ pub struct LLMOutput {
     pub deployment: i32,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Transcripts {
+    pub deployment: i32,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct DeploymentStream {
+    pub deployment: i32,
+    pub offset: Option<usize>,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct Queue {
     pub deployment: i32,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct Cancel {
+    pub deployment: i32,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct PromoCodeRedeem {
     pub code: String,
@@ -7,19 +7,30 @@ use tokio::{spawn, try_join};
 use crate::{
     blockchain::start_event_listeners,
     database::Database,
+    source_host::source_host,
     utils::{
+        container_sync::reconcile_containers,
         env::{datadir, hostname, httprpc, port},
+        host_migration::migrate_projects,
+        host_sync::sync_hosts,
+        leader::singleton,
         nft::mint_nfts,
+        output::prune_logs,
+        provisioning::provision_projects,
         runner::{execute_pending_deployments, finish_deployment, manage_coding_servers},
+        watchdog::watchdog,
     },
 };
 
+mod admin;
 mod blockchain;
 mod database;
 mod factory;
 mod showcase;
+mod source_host;
 mod utils;
 mod waitlist;
+mod worker_provider;
 
 #[tokio::main]
 async fn main() {
@@ -36,6 +47,9 @@ async fn main() {
         };
     }
 
+    // Missing source host configuration should stop startup instead of the loops that use it
+    source_host();
+
     let database = Database::new().await;
     let provider = ProviderBuilder::new()
         .connect(&httprpc())
@@ -43,20 +57,62 @@ async fn main() {
         .unwrap_or_else(|e| panic!("Could not connect to HTTP rpc provider: {e}"));
 
     if let Err(e) = try_join!(
-        spawn(start_event_listeners(database.clone())),
-        spawn(manage_coding_servers(database.clone())),
-        spawn(execute_pending_deployments(database.clone())),
-        spawn(finish_deployment(database.clone())),
-        spawn(mint_nfts(
+        spawn(singleton(database.clone(), "event_listeners", {
+            let database = database.clone();
+            move || start_event_listeners(database.clone())
+        })),
+        spawn(singleton(database.clone(), "manage_coding_servers", {
+            let database = database.clone();
+            move || manage_coding_servers(database.clone())
+        })),
+        spawn(singleton(
             database.clone(),
-            DynProvider::new(provider.clone())
+            "execute_pending_deployments",
+            {
+                let database = database.clone();
+                move || execute_pending_deployments(database.clone())
+            }
         )),
+        spawn(singleton(database.clone(), "finish_deployment", {
+            let database = database.clone();
+            move || finish_deployment(database.clone())
+        })),
+        spawn(singleton(database.clone(), "watchdog", {
+            let database = database.clone();
+            move || watchdog(database.clone())
+        })),
+        spawn(singleton(database.clone(), "provision_projects", {
+            let database = database.clone();
+            move || provision_projects(database.clone())
+        })),
+        spawn(singleton(database.clone(), "sync_hosts", {
+            let database = database.clone();
+            move || sync_hosts(database.clone())
+        })),
+        spawn(singleton(database.clone(), "migrate_projects", {
+            let database = database.clone();
+            move || migrate_projects(database.clone())
+        })),
+        spawn(singleton(database.clone(), "reconcile_containers", {
+            let database = database.clone();
+            move || reconcile_containers(database.clone())
+        })),
+        spawn(singleton(database.clone(), "prune_logs", {
+            let database = database.clone();
+            move || prune_logs(database.clone())
+        })),
+        spawn(singleton(database.clone(), "mint_nfts", {
+            let database = database.clone();
+            let provider = provider.clone();
+            move || mint_nfts(database.clone(), DynProvider::new(provider.clone()))
+        })),
         spawn(
             HttpServer::new(move || {
                 App::new()
                     .app_data(web::Data::new(database.clone()))
                     .app_data(web::Data::new(DynProvider::new(provider.clone())))
                     .service(web::scope("/api/factory").configure(factory::configure))
+                    .service(web::scope("/api/admin").configure(admin::configure))
                     .service(web::scope("/api/waitlist").configure(waitlist::configure))
                     .service(web::scope("/api/showcase").configure(showcase::configure))
             })
@@ -3,7 +3,11 @@ use crate::{
         Database, deployments::DatabaseDeployment, projects::DatabaseProject,
         worker_servers::DatabaseWorkerServer,
     },
-    showcase::models::ProjectShowcase,
+    showcase::models::{ProjectShowcase, QueueEta},
+    utils::{
+        eta::{PhaseStatistics, queue_starts},
+        time::get_time_i64,
+    },
 };
 use actix_web::{HttpResponse, Responder, get, web};
 
@@ -58,3 +62,41 @@ async fn queue_workers(database: web::Data<Database>) -> impl Responder {
         }
     }
 }
+
+#[get("/queue/eta")]
+async fn queue_eta(database: web::Data<Database>) -> impl Responder {
+    let queued = match DatabaseDeployment::get_queued_count(&database).await {
+        Ok(count) => count,
+        Err(e) => {
+            log::warn!("Couldn't get queued count: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+    let statistics = match PhaseStatistics::recent(&database).await {
+        Ok(statistics) => statistics,
+        Err(e) => {
+            log::warn!("Couldn't get phase statistics: {e}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    // A deployment submitted now is scheduled after the current queue
+    let estimated_start =
+        match queue_starts(&database, &statistics, queued as usize + 1, get_time_i64()).await {
+            Ok(starts) => starts.and_then(|starts| starts.last().copied()),
+            Err(e) => {
+                log::warn!("Couldn't estimate queue start times: {e}");
+                return HttpResponse::InternalServerError().finish();
+            }
+        };
+    let estimated_finish = estimated_start
+        .zip(statistics.processing())
+        .map(|(start, processing)| start + processing as i64);
+
+    HttpResponse::Ok().json(QueueEta {
+        queued,
+        estimated_start,
+        estimated_finish,
+        statistics,
+    })
+}
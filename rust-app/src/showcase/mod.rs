@@ -8,4 +8,5 @@ pub fn configure(cfg: &mut ServiceConfig) {
     cfg.service(handlers::projects_all);
     cfg.service(handlers::queue_count);
     cfg.service(handlers::queue_workers);
+    cfg.service(handlers::queue_eta);
 }
@@ -1,7 +1,19 @@
 use serde::{Deserialize, Serialize};
 
+use crate::utils::eta::PhaseStatistics;
+
 #[derive(Serialize, Deserialize)]
 pub struct ProjectShowcase {
     pub id: i32,
     pub name: String,
 }
+
+#[derive(Serialize, Deserialize)]
+pub struct QueueEta {
+    pub queued: i64,
+    /// Unix timestamp a deployment submitted now is expected to start
+    pub estimated_start: Option<i64>,
+    /// Unix timestamp a deployment submitted now is expected to finish its worker phases
+    pub estimated_finish: Option<i64>,
+    pub statistics: PhaseStatistics,
+}
@@ -0,0 +1,142 @@
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    source_host::{RepositoryInfo, SourceHost, SourceHostError, check_status},
+    utils::env::{giteatoken, giteaurl, sourceowner},
+};
+
+#[derive(Deserialize)]
+struct GiteaRepository {
+    name: String,
+    default_branch: String,
+    archived: bool,
+}
+
+/// Gitea and Forgejo (API compatible)
+pub struct Gitea {
+    client: Client,
+    url: String,
+    token: String,
+    owner: String,
+}
+
+impl Gitea {
+    pub fn from_env() -> Self {
+        Self {
+            client: Client::new(),
+            url: giteaurl().trim_end_matches('/').to_string(),
+            token: giteatoken(),
+            owner: sourceowner(),
+        }
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder
+            .header(
+                "Authorization",
+                format!("token {token}", token = self.token),
+            )
+            .header("Accept", "application/json")
+    }
+
+    fn repository_url(&self, name: &str) -> String {
+        format!(
+            "{url}/api/v1/repos/{owner}/{name}",
+            url = self.url,
+            owner = self.owner
+        )
+    }
+}
+
+impl SourceHost for Gitea {
+    async fn create_from_template(
+        &self,
+        name: &str,
+        template: &str,
+    ) -> Result<(), SourceHostError> {
+        let response = self
+            .request(self.client.post(format!(
+                "{url}/api/v1/repos/{template}/generate",
+                url = self.url
+            )))
+            .json(&json!({
+                "owner": self.owner,
+                "name": name,
+                "private": false,
+                "git_content": true,
+            }))
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), SourceHostError> {
+        let response = self
+            .request(self.client.delete(self.repository_url(name)))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn archive(&self, name: &str) -> Result<(), SourceHostError> {
+        let response = self
+            .request(self.client.patch(self.repository_url(name)))
+            .json(&json!({ "archived": true }))
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn transfer(&self, name: &str, new_owner: &str) -> Result<(), SourceHostError> {
+        let response = self
+            .request(
+                self.client
+                    .post(format!("{url}/transfer", url = self.repository_url(name))),
+            )
+            .json(&json!({ "new_owner": new_owner }))
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn info(&self, name: &str) -> Result<Option<RepositoryInfo>, SourceHostError> {
+        let response = self
+            .request(self.client.get(self.repository_url(name)))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let repository: GiteaRepository = check_status(response).await?.json().await?;
+
+        Ok(Some(RepositoryInfo {
+            name: repository.name,
+            default_branch: repository.default_branch,
+            archived: repository.archived,
+        }))
+    }
+
+    fn flake_url(&self, name: &str, version: Option<&str>) -> String {
+        let version = version
+            .map(|version| format!("?rev={version}"))
+            .unwrap_or_default();
+        format!(
+            "git+{url}/{owner}/{name}.git{version}",
+            url = self.url,
+            owner = self.owner
+        )
+    }
+}
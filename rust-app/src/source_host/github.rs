@@ -0,0 +1,133 @@
+use reqwest::{Client, RequestBuilder, StatusCode};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::{
+    source_host::{RepositoryInfo, SourceHost, SourceHostError, check_status},
+    utils::env::{ghtoken, sourceowner},
+};
+
+#[derive(Deserialize)]
+struct GitHubRepository {
+    name: String,
+    default_branch: String,
+    archived: bool,
+}
+
+pub struct GitHub {
+    client: Client,
+    token: String,
+    owner: String,
+}
+
+impl GitHub {
+    pub fn from_env() -> Self {
+        Self {
+            client: Client::new(),
+            token: ghtoken(),
+            owner: sourceowner(),
+        }
+    }
+
+    fn request(&self, builder: RequestBuilder) -> RequestBuilder {
+        builder
+            .bearer_auth(&self.token)
+            .header("Accept", "application/vnd.github+json")
+            .header("X-GitHub-Api-Version", "2022-11-28")
+            .header("User-Agent", "miniapp-factory")
+    }
+
+    fn repository_url(&self, name: &str) -> String {
+        format!(
+            "https://api.github.com/repos/{owner}/{name}",
+            owner = self.owner
+        )
+    }
+}
+
+impl SourceHost for GitHub {
+    async fn create_from_template(
+        &self,
+        name: &str,
+        template: &str,
+    ) -> Result<(), SourceHostError> {
+        let response = self
+            .request(
+                self.client
+                    .post(format!("https://api.github.com/repos/{template}/generate")),
+            )
+            .json(&json!({
+                "owner": self.owner,
+                "name": name,
+                "private": false,
+                "include_all_branches": false,
+            }))
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), SourceHostError> {
+        let response = self
+            .request(self.client.delete(self.repository_url(name)))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(());
+        }
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn archive(&self, name: &str) -> Result<(), SourceHostError> {
+        let response = self
+            .request(self.client.patch(self.repository_url(name)))
+            .json(&json!({ "archived": true }))
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn transfer(&self, name: &str, new_owner: &str) -> Result<(), SourceHostError> {
+        let response = self
+            .request(
+                self.client
+                    .post(format!("{url}/transfer", url = self.repository_url(name))),
+            )
+            .json(&json!({ "new_owner": new_owner }))
+            .send()
+            .await?;
+        check_status(response).await?;
+
+        Ok(())
+    }
+
+    async fn info(&self, name: &str) -> Result<Option<RepositoryInfo>, SourceHostError> {
+        let response = self
+            .request(self.client.get(self.repository_url(name)))
+            .send()
+            .await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        let repository: GitHubRepository = check_status(response).await?.json().await?;
+
+        Ok(Some(RepositoryInfo {
+            name: repository.name,
+            default_branch: repository.default_branch,
+            archived: repository.archived,
+        }))
+    }
+
+    fn flake_url(&self, name: &str, version: Option<&str>) -> String {
+        let version = version
+            .map(|version| format!("/{version}"))
+            .unwrap_or_default();
+        format!("github:{owner}/{name}{version}", owner = self.owner)
+    }
+}
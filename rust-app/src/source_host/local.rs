@@ -0,0 +1,263 @@
+use std::path::PathBuf;
+
+use tokio::{fs, process::Command};
+
+use crate::{
+    source_host::{RepositoryInfo, SourceHost, SourceHostError},
+    utils::env::{localgitdir, sourceowner},
+};
+
+/// Bare git repositories on the local filesystem, meant for development and testing
+pub struct Local {
+    dir: PathBuf,
+    owner: String,
+}
+
+impl Local {
+    pub fn from_env() -> Self {
+        Self {
+            dir: localgitdir(),
+            owner: sourceowner(),
+        }
+    }
+
+    fn repository_path(&self, owner: &str, name: &str) -> PathBuf {
+        self.dir.join(owner).join(format!("{name}.git"))
+    }
+
+    /// Templates are resolved as local repository when one exists, otherwise cloned from GitHub
+    fn template_url(&self, template: &str) -> String {
+        if template.contains("://") || template.starts_with('/') {
+            return template.to_string();
+        }
+
+        let path = self.dir.join(format!("{template}.git"));
+        if path.exists() {
+            path.display().to_string()
+        } else {
+            format!("https://github.com/{template}.git")
+        }
+    }
+}
+
+async fn git(args: &[&str]) -> Result<String, SourceHostError> {
+    let output = Command::new("git").args(args).output().await?;
+    if !output.status.success() {
+        return Err(SourceHostError::Git {
+            args: args.join(" "),
+            stderr: String::from_utf8_lossy(&output.stderr).to_string(),
+        });
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+impl SourceHost for Local {
+    async fn create_from_template(
+        &self,
+        name: &str,
+        template: &str,
+    ) -> Result<(), SourceHostError> {
+        let path = self.repository_path(&self.owner, name);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        git(&[
+            "clone",
+            "--bare",
+            "--single-branch",
+            &self.template_url(template),
+            &path.display().to_string(),
+        ])
+        .await?;
+
+        Ok(())
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), SourceHostError> {
+        let path = self.repository_path(&self.owner, name);
+        if !path.exists() {
+            return Ok(());
+        }
+
+        fs::remove_dir_all(path).await?;
+
+        Ok(())
+    }
+
+    async fn archive(&self, name: &str) -> Result<(), SourceHostError> {
+        let path = self.repository_path(&self.owner, name);
+        git(&[
+            "--git-dir",
+            &path.display().to_string(),
+            "config",
+            "miniapp-factory.archived",
+            "true",
+        ])
+        .await?;
+
+        Ok(())
+    }
+
+    async fn transfer(&self, name: &str, new_owner: &str) -> Result<(), SourceHostError> {
+        let new_path = self.repository_path(new_owner, name);
+        if let Some(parent) = new_path.parent() {
+            fs::create_dir_all(parent).await?;
+        }
+
+        fs::rename(self.repository_path(&self.owner, name), new_path).await?;
+
+        Ok(())
+    }
+
+    async fn info(&self, name: &str) -> Result<Option<RepositoryInfo>, SourceHostError> {
+        let path = self.repository_path(&self.owner, name);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let git_dir = path.display().to_string();
+        let default_branch =
+            git(&["--git-dir", &git_dir, "symbolic-ref", "--short", "HEAD"]).await?;
+        // git config exits with 1 when the key is not set
+        let archived = git(&[
+            "--git-dir",
+            &git_dir,
+            "config",
+            "--get",
+            "miniapp-factory.archived",
+        ])
+        .await
+        .is_ok_and(|archived| archived == "true");
+
+        Ok(Some(RepositoryInfo {
+            name: name.to_string(),
+            default_branch,
+            archived,
+        }))
+    }
+
+    fn flake_url(&self, name: &str, version: Option<&str>) -> String {
+        let version = version
+            .map(|version| format!("?rev={version}"))
+            .unwrap_or_default();
+        format!(
+            "git+file://{path}{version}",
+            path = self.repository_path(&self.owner, name).display()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use tokio::fs;
+
+    use crate::source_host::SourceHost;
+
+    use super::{Local, git};
+
+    /// Local source host in a fresh directory with a template repository (on branch main) at `<dir>/template`
+    async fn local(test: &str) -> (Local, PathBuf) {
+        let dir = std::env::temp_dir().join(format!(
+            "miniapp-factory-local-{test}-{pid}",
+            pid = std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir).await;
+        let template = dir.join("template");
+        fs::create_dir_all(&template).await.unwrap();
+
+        let template_dir = template.display().to_string();
+        git(&["init", "--initial-branch", "main", &template_dir])
+            .await
+            .unwrap();
+        git(&[
+            "-C",
+            &template_dir,
+            "-c",
+            "user.name=test",
+            "-c",
+            "user.email=test@localhost",
+            "commit",
+            "--allow-empty",
+            "--message",
+            "template",
+        ])
+        .await
+        .unwrap();
+
+        (
+            Local {
+                dir: dir.clone(),
+                owner: "factory".to_string(),
+            },
+            dir,
+        )
+    }
+
+    #[tokio::test]
+    async fn creates_repository_from_template() {
+        let (local, dir) = local("create").await;
+        let template = dir.join("template").display().to_string();
+
+        local.create_from_template("app", &template).await.unwrap();
+
+        let info = local.info("app").await.unwrap().unwrap();
+        assert_eq!(info.name, "app");
+        assert_eq!(info.default_branch, "main");
+        assert!(!info.archived);
+        assert!(local.info("other").await.unwrap().is_none());
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn archives_and_deletes_repository() {
+        let (local, dir) = local("archive").await;
+        let template = dir.join("template").display().to_string();
+        local.create_from_template("app", &template).await.unwrap();
+
+        local.archive("app").await.unwrap();
+        assert!(local.info("app").await.unwrap().unwrap().archived);
+
+        local.delete("app").await.unwrap();
+        assert!(local.info("app").await.unwrap().is_none());
+        // Deleting a repository that does not exist succeeds
+        local.delete("app").await.unwrap();
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn transfers_repository_to_new_owner() {
+        let (local, dir) = local("transfer").await;
+        let template = dir.join("template").display().to_string();
+        local.create_from_template("app", &template).await.unwrap();
+
+        local.transfer("app", "user").await.unwrap();
+
+        assert!(local.info("app").await.unwrap().is_none());
+        assert!(dir.join("user").join("app.git").exists());
+
+        fs::remove_dir_all(dir).await.unwrap();
+    }
+
+    #[test]
+    fn flake_url_pins_version() {
+        let local = Local {
+            dir: PathBuf::from("/var/lib/git"),
+            owner: "factory".to_string(),
+        };
+
+        assert_eq!(
+            local.flake_url("app", None),
+            "git+file:///var/lib/git/factory/app.git"
+        );
+        assert_eq!(
+            local.flake_url("app", Some("abc123")),
+            "git+file:///var/lib/git/factory/app.git?rev=abc123"
+        );
+    }
+}
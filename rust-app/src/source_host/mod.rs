@@ -0,0 +1,169 @@
+use std::{fmt::Display, sync::LazyLock};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    source_host::{gitea::Gitea, github::GitHub, local::Local},
+    utils::env::sourcehost,
+};
+
+pub mod gitea;
+pub mod github;
+pub mod local;
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RepositoryInfo {
+    pub name: String,
+    pub default_branch: String,
+    pub archived: bool,
+}
+
+#[derive(Debug)]
+pub enum SourceHostError {
+    Request(reqwest::Error),
+    Status { status: u16, body: String },
+    Io(std::io::Error),
+    Git { args: String, stderr: String },
+}
+
+impl Display for SourceHostError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SourceHostError::Request(e) => write!(f, "request error: {e}"),
+            SourceHostError::Status { status, body } => {
+                write!(f, "unexpected status {status}: {body}")
+            }
+            SourceHostError::Io(e) => write!(f, "io error: {e}"),
+            SourceHostError::Git { args, stderr } => write!(f, "git {args} failed: {stderr}"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for SourceHostError {
+    fn from(value: reqwest::Error) -> Self {
+        SourceHostError::Request(value)
+    }
+}
+
+impl From<std::io::Error> for SourceHostError {
+    fn from(value: std::io::Error) -> Self {
+        SourceHostError::Io(value)
+    }
+}
+
+async fn check_status(response: reqwest::Response) -> Result<reqwest::Response, SourceHostError> {
+    if response.status().is_success() {
+        return Ok(response);
+    }
+
+    Err(SourceHostError::Status {
+        status: response.status().as_u16(),
+        body: response.text().await.unwrap_or_default(),
+    })
+}
+
+/// Hosts the source code repositories of projects, all repositories live under a single owner
+pub trait SourceHost {
+    /// Create repository `name` with the content of `template` (owner/repo)
+    fn create_from_template(
+        &self,
+        name: &str,
+        template: &str,
+    ) -> impl Future<Output = Result<(), SourceHostError>> + Send;
+
+    /// Deleting a repository that does not exist succeeds
+    fn delete(&self, name: &str) -> impl Future<Output = Result<(), SourceHostError>> + Send;
+
+    #[allow(dead_code)]
+    fn archive(&self, name: &str) -> impl Future<Output = Result<(), SourceHostError>> + Send;
+
+    #[allow(dead_code)]
+    fn transfer(
+        &self,
+        name: &str,
+        new_owner: &str,
+    ) -> impl Future<Output = Result<(), SourceHostError>> + Send;
+
+    /// Returns None in case the repository does not exist
+    fn info(
+        &self,
+        name: &str,
+    ) -> impl Future<Output = Result<Option<RepositoryInfo>, SourceHostError>> + Send;
+
+    /// Nix flake url of the repository, optionally pinned to a git revision
+    fn flake_url(&self, name: &str, version: Option<&str>) -> String;
+}
+
+pub enum SourceHostBackend {
+    GitHub(GitHub),
+    Gitea(Gitea),
+    Local(Local),
+}
+
+static SOURCE_HOST: LazyLock<SourceHostBackend> = LazyLock::new(|| match sourcehost().as_str() {
+    "gitea" => SourceHostBackend::Gitea(Gitea::from_env()),
+    "local" => SourceHostBackend::Local(Local::from_env()),
+    "github" => SourceHostBackend::GitHub(GitHub::from_env()),
+    other => {
+        panic!("Unknown source host {other}, expected github, gitea or local.")
+    }
+});
+
+/// Source host selected by the SOURCEHOST env var (github, gitea or local), built once and shared by all callers
+pub fn source_host() -> &'static SourceHostBackend {
+    &SOURCE_HOST
+}
+
+impl SourceHost for SourceHostBackend {
+    async fn create_from_template(
+        &self,
+        name: &str,
+        template: &str,
+    ) -> Result<(), SourceHostError> {
+        match self {
+            SourceHostBackend::GitHub(host) => host.create_from_template(name, template).await,
+            SourceHostBackend::Gitea(host) => host.create_from_template(name, template).await,
+            SourceHostBackend::Local(host) => host.create_from_template(name, template).await,
+        }
+    }
+
+    async fn delete(&self, name: &str) -> Result<(), SourceHostError> {
+        match self {
+            SourceHostBackend::GitHub(host) => host.delete(name).await,
+            SourceHostBackend::Gitea(host) => host.delete(name).await,
+            SourceHostBackend::Local(host) => host.delete(name).await,
+        }
+    }
+
+    async fn archive(&self, name: &str) -> Result<(), SourceHostError> {
+        match self {
+            SourceHostBackend::GitHub(host) => host.archive(name).await,
+            SourceHostBackend::Gitea(host) => host.archive(name).await,
+            SourceHostBackend::Local(host) => host.archive(name).await,
+        }
+    }
+
+    async fn transfer(&self, name: &str, new_owner: &str) -> Result<(), SourceHostError> {
+        match self {
+            SourceHostBackend::GitHub(host) => host.transfer(name, new_owner).await,
+            SourceHostBackend::Gitea(host) => host.transfer(name, new_owner).await,
+            SourceHostBackend::Local(host) => host.transfer(name, new_owner).await,
+        }
+    }
+
+    async fn info(&self, name: &str) -> Result<Option<RepositoryInfo>, SourceHostError> {
+        match self {
+            SourceHostBackend::GitHub(host) => host.info(name).await,
+            SourceHostBackend::Gitea(host) => host.info(name).await,
+            SourceHostBackend::Local(host) => host.info(name).await,
+        }
+    }
+
+    fn flake_url(&self, name: &str, version: Option<&str>) -> String {
+        match self {
+            SourceHostBackend::GitHub(host) => host.flake_url(name, version),
+            SourceHostBackend::Gitea(host) => host.flake_url(name, version),
+            SourceHostBackend::Local(host) => host.flake_url(name, version),
+        }
+    }
+}
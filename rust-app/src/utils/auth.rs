@@ -1,7 +1,7 @@
 use hex::ToHex;
 use xnode_manager_sdk::utils::Session;
 
-use crate::utils::{error::ResponseError, time::get_time_u64};
+use crate::utils::{env::admins, error::ResponseError, time::get_time_u64};
 
 use super::{keccak::hash_message, wallet::get_signer};
 
@@ -37,3 +37,7 @@ pub async fn get_session(url: &str, domain: &str) -> Result<Session, ResponseErr
         ResponseError::new("Couldn't sign authentication message.")
     })
 }
+
+pub fn is_admin(user: &str) -> bool {
+    admins().iter().any(|admin| admin == user)
+}
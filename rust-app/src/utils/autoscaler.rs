@@ -0,0 +1,415 @@
+use hex::ToHex;
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+
+use crate::{
+    database::{
+        Database,
+        deployments::DatabaseDeployment,
+        scaling_decisions::DatabaseScalingDecision,
+        worker_servers::{DatabaseWorkerServer, WorkerProviderKind, WorkerRole},
+    },
+    utils::{
+        env::{idlecooldown, maxpaidworkers, maxworkers, minworkers, queueperworker, warmstandby},
+        runner::{deploy_server, undeploy_server},
+        time::get_time_i64,
+        wallet::get_signer,
+    },
+    worker_provider::{WorkerProvider, worker_provider},
+};
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScalingPolicy {
+    /// Workers (including static ones) that are kept, even when there is no work
+    pub min_workers: i64,
+    pub max_workers: i64,
+    /// Queued deployments a single worker is expected to handle
+    pub queue_per_worker: i64,
+    /// Seconds a dynamic worker has to be idle before it is undeployed
+    pub idle_cooldown: i64,
+    /// Idle workers kept ready on top of the ones needed for the queue
+    pub warm_standby: i64,
+    /// Maximum amount of dynamic (paid) workers at the same time
+    pub max_paid_workers: i64,
+}
+
+impl ScalingPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            min_workers: minworkers(),
+            max_workers: maxworkers(),
+            queue_per_worker: queueperworker(),
+            idle_cooldown: idlecooldown(),
+            warm_standby: warmstandby(),
+            max_paid_workers: maxpaidworkers(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ScalingDecision {
+    pub desired: i64,
+    pub deploy: i64,
+    /// Role of the workers to deploy
+    pub role: WorkerRole,
+    pub undeploy: Vec<i32>,
+    pub reason: String,
+}
+
+/// Decide how the worker pool should change, without side effects
+pub fn decide(
+    policy: &ScalingPolicy,
+    queued: i64,
+    imagegen_queued: i64,
+    workers: &[DatabaseWorkerServer],
+    can_deploy: bool,
+    now: i64,
+) -> ScalingDecision {
+    let active: Vec<&DatabaseWorkerServer> =
+        workers.iter().filter(|worker| !worker.draining).collect();
+    let busy = active
+        .iter()
+        .filter(|worker| worker.assignment.is_some())
+        .count() as i64;
+    // Draining workers still cost money until they are undeployed
+    let paid = workers.iter().filter(|worker| worker.dynamic).count() as i64;
+    let current = active.len() as i64;
+
+    let queue_per_worker = policy.queue_per_worker.max(1);
+    let needed = |count: i64| (count + queue_per_worker - 1) / queue_per_worker;
+    let maximum = policy.max_workers.max(policy.min_workers);
+    let desired = (busy + needed(queued + imagegen_queued) + policy.warm_standby)
+        .clamp(policy.min_workers, maximum);
+
+    // Idle workers only take deployments from the queues their role serves
+    let idle_serving = |serves: fn(&WorkerRole) -> bool| {
+        active
+            .iter()
+            .filter(|worker| worker.assignment.is_none() && serves(&worker.role))
+            .count() as i64
+    };
+    let coder_short = needed(queued) - idle_serving(WorkerRole::is_coder);
+    let imagegen_short = needed(imagegen_queued) - idle_serving(WorkerRole::is_imagegen);
+
+    let mut reasons = vec![];
+    let mut undeploy: Vec<i32> = workers
+        .iter()
+        .filter(|worker| worker.draining && worker.dynamic && worker.assignment.is_none())
+        .map(|worker| worker.id)
+        .collect();
+    if !undeploy.is_empty() {
+        reasons.push(format!("{count} drained", count = undeploy.len()));
+    }
+
+    // Single role workers only when the other queue is not short and still served by an existing worker
+    let role = if coder_short > 0
+        && imagegen_short <= 0
+        && active.iter().any(|worker| worker.role.is_imagegen())
+    {
+        WorkerRole::Coder
+    } else if imagegen_short > 0
+        && coder_short <= 0
+        && active.iter().any(|worker| worker.role.is_coder())
+    {
+        WorkerRole::Imagegen
+    } else {
+        WorkerRole::Both
+    };
+
+    let mut deploy = 0;
+    let short = (desired - current)
+        .max(coder_short)
+        .max(imagegen_short)
+        .min(maximum - current);
+    if short > 0 {
+        deploy = short;
+        reasons.push(format!("{deploy} {role} short"));
+
+        if !can_deploy {
+            deploy = 0;
+            reasons.push("provider can not deploy".to_string());
+        }
+
+        let budget = (policy.max_paid_workers - paid).max(0);
+        if deploy > budget {
+            deploy = budget;
+            reasons.push(format!(
+                "budget cap of {max} paid workers",
+                max = policy.max_paid_workers
+            ));
+        }
+    } else if current > desired {
+        let mut idle: Vec<&DatabaseWorkerServer> = active
+            .into_iter()
+            .filter(|worker| {
+                worker.dynamic
+                    && worker.setup_finished
+                    && worker.assignment.is_none()
+                    && worker
+                        .idle_since
+                        .is_some_and(|idle_since| now - idle_since >= policy.idle_cooldown)
+            })
+            .collect();
+        idle.sort_by_key(|worker| worker.idle_since);
+
+        let excess = idle
+            .into_iter()
+            .take((current - desired) as usize)
+            .map(|worker| worker.id)
+            .collect::<Vec<i32>>();
+        if !excess.is_empty() {
+            reasons.push(format!(
+                "{count} idle for over {cooldown}s",
+                count = excess.len(),
+                cooldown = policy.idle_cooldown
+            ));
+            undeploy.extend(excess);
+        }
+    }
+
+    ScalingDecision {
+        desired,
+        deploy,
+        role,
+        undeploy,
+        reason: reasons.join(", "),
+    }
+}
+
+/// Apply the scaling policy to the worker pool, decisions that change the pool are logged and stored
+pub async fn autoscale(database: &Database) {
+    let queued = match DatabaseDeployment::get_queued_count(database).await {
+        Ok(queued) => queued,
+        Err(e) => {
+            log::error!("Could not get deployments queued count: {e}");
+            return;
+        }
+    };
+    let imagegen_queued = match DatabaseDeployment::get_imagegen_queued_count(database).await {
+        Ok(queued) => queued,
+        Err(e) => {
+            log::error!("Could not get deployments imagegen queued count: {e}");
+            return;
+        }
+    };
+    let workers = match DatabaseWorkerServer::get_all(database).await {
+        Ok(workers) => workers,
+        Err(e) => {
+            log::error!("Could not get coding servers: {e}");
+            return;
+        }
+    };
+
+    let current = workers.iter().filter(|worker| !worker.draining).count() as i64;
+    let decision = decide(
+        &ScalingPolicy::from_env(),
+        queued,
+        imagegen_queued,
+        &workers,
+        worker_provider().kind() != WorkerProviderKind::Static,
+        get_time_i64(),
+    );
+    if decision.deploy == 0 && decision.undeploy.is_empty() {
+        return;
+    }
+
+    log::info!(
+        "Scaling worker pool from {current} to {desired} workers ({queued} queued, {imagegen_queued} imagegen queued): deploying {deploy} {role}, undeploying {undeploy:?} ({reason})",
+        desired = decision.desired,
+        deploy = decision.deploy,
+        role = decision.role,
+        undeploy = decision.undeploy,
+        reason = decision.reason
+    );
+    let mut record = DatabaseScalingDecision {
+        id: 0,
+        decided_at: get_time_i64(),
+        queued: queued + imagegen_queued,
+        workers: current,
+        desired: decision.desired,
+        deploy: decision.deploy,
+        undeploy: Json::from(decision.undeploy.clone()),
+        reason: decision.reason.clone(),
+    };
+    if let Err(e) = record.insert(database).await {
+        log::error!("Could not store scaling decision {record:?}: {e}");
+    }
+
+    for mut server in workers
+        .into_iter()
+        .filter(|worker| decision.undeploy.contains(&worker.id))
+    {
+        undeploy_server(database, &mut server).await;
+    }
+
+    if decision.deploy > 0 {
+        let addr: String = get_signer().public().address().encode_hex();
+        for _ in 0..decision.deploy {
+            deploy_server(database, format!("eth:{addr}"), decision.role).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::worker_servers::{DatabaseWorkerServer, WorkerRole};
+
+    use super::{ScalingPolicy, decide};
+
+    const NOW: i64 = 1_000_000;
+
+    fn policy() -> ScalingPolicy {
+        ScalingPolicy {
+            min_workers: 1,
+            max_workers: 10,
+            queue_per_worker: 2,
+            idle_cooldown: 600,
+            warm_standby: 0,
+            max_paid_workers: 5,
+        }
+    }
+
+    #[test]
+    fn deploys_for_the_queue() {
+        let decision = decide(
+            &policy(),
+            3,
+            0,
+            &[DatabaseWorkerServer::assigned(1, 1)],
+            true,
+            NOW,
+        );
+
+        assert_eq!((decision.desired, decision.deploy), (3, 2));
+        assert!(decision.undeploy.is_empty());
+    }
+
+    #[test]
+    fn keeps_min_workers_and_warm_standby() {
+        let decision = decide(&policy(), 0, 0, &[], true, NOW);
+        assert_eq!((decision.desired, decision.deploy), (1, 1));
+
+        let policy = ScalingPolicy {
+            warm_standby: 2,
+            ..policy()
+        };
+        let decision = decide(&policy, 0, 0, &[], true, NOW);
+        assert_eq!((decision.desired, decision.deploy), (2, 2));
+    }
+
+    #[test]
+    fn desired_is_capped_at_max_workers() {
+        let policy = ScalingPolicy {
+            max_workers: 2,
+            ..policy()
+        };
+
+        assert_eq!(decide(&policy, 100, 0, &[], true, NOW).desired, 2);
+    }
+
+    #[test]
+    fn paid_workers_are_capped_by_budget() {
+        let workers: Vec<DatabaseWorkerServer> = (1..=4)
+            .map(|id| DatabaseWorkerServer::assigned(id, id))
+            .collect();
+        let decision = decide(&policy(), 6, 0, &workers, true, NOW);
+
+        assert_eq!((decision.desired, decision.deploy), (7, 1));
+        assert!(decision.reason.contains("budget cap"));
+    }
+
+    #[test]
+    fn static_pools_do_not_deploy() {
+        let decision = decide(&policy(), 4, 0, &[], false, NOW);
+
+        assert_eq!(decision.deploy, 0);
+        assert!(decision.reason.contains("provider can not deploy"));
+    }
+
+    #[test]
+    fn undeploys_dynamic_workers_idle_past_cooldown() {
+        let idle_static = DatabaseWorkerServer {
+            dynamic: false,
+            ..DatabaseWorkerServer::idle(3, NOW - 600)
+        };
+        let workers = [
+            DatabaseWorkerServer::idle(1, NOW - 600),
+            DatabaseWorkerServer::idle(2, NOW - 10),
+            idle_static,
+        ];
+        let policy = ScalingPolicy {
+            min_workers: 0,
+            ..policy()
+        };
+
+        let decision = decide(&policy, 0, 0, &workers, true, NOW);
+
+        assert_eq!(decision.desired, 0);
+        assert_eq!(decision.undeploy, vec![1]);
+    }
+
+    #[test]
+    fn undeploys_drained_workers_once_finished() {
+        let workers = [
+            DatabaseWorkerServer {
+                draining: true,
+                ..DatabaseWorkerServer::idle(1, NOW)
+            },
+            DatabaseWorkerServer {
+                draining: true,
+                ..DatabaseWorkerServer::assigned(2, 1)
+            },
+        ];
+
+        assert_eq!(
+            decide(&policy(), 0, 0, &workers, true, NOW).undeploy,
+            vec![1]
+        );
+    }
+
+    #[test]
+    fn deploys_the_role_of_the_queue_that_is_short() {
+        let imagegen = DatabaseWorkerServer {
+            role: WorkerRole::Imagegen,
+            ..DatabaseWorkerServer::assigned(1, 1)
+        };
+        assert_eq!(
+            decide(&policy(), 4, 0, &[imagegen], true, NOW).role,
+            WorkerRole::Coder
+        );
+
+        let coder = DatabaseWorkerServer {
+            role: WorkerRole::Coder,
+            ..DatabaseWorkerServer::assigned(1, 1)
+        };
+        assert_eq!(
+            decide(&policy(), 0, 4, &[coder], true, NOW).role,
+            WorkerRole::Imagegen
+        );
+
+        let both = DatabaseWorkerServer::assigned(1, 1);
+        assert_eq!(
+            decide(&policy(), 4, 4, &[both], true, NOW).role,
+            WorkerRole::Both
+        );
+    }
+
+    #[test]
+    fn deploys_for_the_queue_idle_workers_can_not_serve() {
+        let coders: Vec<DatabaseWorkerServer> = (1..=2)
+            .map(|id| DatabaseWorkerServer {
+                role: WorkerRole::Coder,
+                ..DatabaseWorkerServer::idle(id, NOW)
+            })
+            .collect();
+        let decision = decide(&policy(), 0, 2, &coders, true, NOW);
+        assert_eq!((decision.deploy, decision.role), (1, WorkerRole::Imagegen));
+
+        let imagegen = [DatabaseWorkerServer {
+            role: WorkerRole::Imagegen,
+            ..DatabaseWorkerServer::idle(1, NOW)
+        }];
+        let decision = decide(&policy(), 1, 0, &imagegen, true, NOW);
+        assert_eq!((decision.deploy, decision.role), (1, WorkerRole::Coder));
+    }
+}
@@ -0,0 +1,336 @@
+use std::{collections::HashSet, time::Duration};
+
+use futures_util::{StreamExt, stream};
+use tokio::time;
+use xnode_manager_sdk::{
+    config::{
+        ContainerChange, ContainerSettings, ContainersInput, GetInput, GetPath, SetInput, SetPath,
+    },
+    utils::Session,
+};
+
+use crate::{
+    database::{
+        Database, deployments::DatabaseDeployment, host_migrations::DatabaseHostMigration,
+        miniapp_hosts::DatabaseMiniappHost, projects::DatabaseProject,
+        provisionings::DatabaseProvisioning,
+    },
+    utils::{
+        env::workerparallelism,
+        provisioning::request_finished,
+        sessions::{host_session, invalidate_host_session},
+        time::get_time_i64,
+    },
+};
+
+/// Re-applies of a container in a row before it is left to an admin
+const RECONCILE_ATTEMPTS: i32 = 5;
+const RECONCILE_BACKOFF: i64 = 60;
+const MAX_RECONCILE_BACKOFF: i64 = 3600;
+
+/// Keeps the containers on every mini app host in line with the flake and network of their project
+///
+/// Projects that another job is still changing (provisioning, host migration, deployment rollout or a container change like a reset) are left alone until that job finished. Re-applies that keep failing are backed off and given up on after a few attempts, they are listed for admins instead.
+pub async fn reconcile_containers(database: Database) {
+    let mut interval = time::interval(Duration::from_secs(60));
+
+    loop {
+        interval.tick().await;
+
+        let busy = match busy_projects(&database).await {
+            Ok(busy) => busy,
+            Err(e) => {
+                log::error!("Could not get projects that are being changed: {e}");
+                continue;
+            }
+        };
+
+        let hosts = match DatabaseMiniappHost::get_all(&database).await {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                log::error!("Could not get mini app hosts: {e}");
+                continue;
+            }
+        };
+
+        for host in hosts {
+            if let Err(e) = reconcile_host(&database, &host, &busy).await {
+                log::error!(
+                    "Could not reconcile containers of mini app host {id}: {e}",
+                    id = host.id
+                );
+            }
+        }
+    }
+}
+
+/// Projects with an unfinished provisioning, host migration, deployment rollout or container change
+async fn busy_projects(database: &Database) -> Result<HashSet<String>, String> {
+    let mut busy = HashSet::new();
+
+    busy.extend(
+        DatabaseProvisioning::get_all_unfinished(database)
+            .await
+            .map_err(|e| format!("Could not get unfinished provisionings: {e}"))?
+            .into_iter()
+            .map(|provisioning| provisioning.project),
+    );
+    busy.extend(
+        DatabaseHostMigration::get_all_unfinished(database)
+            .await
+            .map_err(|e| format!("Could not get unfinished host migrations: {e}"))?
+            .into_iter()
+            .map(|migration| migration.project),
+    );
+    busy.extend(
+        DatabaseDeployment::get_all_rolling_out(database)
+            .await
+            .map_err(|e| format!("Could not get rolling out deployments: {e}"))?
+            .into_iter()
+            .map(|deployment| deployment.project),
+    );
+    busy.extend(
+        DatabaseProject::get_all_changing(database)
+            .await
+            .map_err(|e| format!("Could not get changing projects: {e}"))?
+            .into_iter()
+            .map(|project| project.name),
+    );
+
+    Ok(busy)
+}
+
+async fn reconcile_host(
+    database: &Database,
+    host: &DatabaseMiniappHost,
+    busy: &HashSet<String>,
+) -> Result<(), String> {
+    let session = host_session(host).await?;
+
+    let mut projects = DatabaseProject::get_all_by_miniapp_host(database, host.id)
+        .await
+        .map_err(|e| format!("Could not get projects from database: {e}"))?;
+    let containers: HashSet<String> =
+        match xnode_manager_sdk::config::containers(ContainersInput::new(&session)).await {
+            Ok(containers) => containers.into_iter().collect(),
+            Err(e) => {
+                invalidate_host_session(host.id, &session).await;
+                return Err(format!("Could not list containers: {e:?}"));
+            }
+        };
+
+    // Reconciled again from the next round on, once the change finished
+    for project in projects
+        .iter_mut()
+        .filter(|project| project.change_request.is_some())
+    {
+        if let Err(e) = follow_change_request(database, &session, project).await {
+            log::error!(
+                "Could not follow container change of project {project}: {e}",
+                project = project.name
+            );
+        }
+    }
+
+    // Left in place, removing containers is up to an admin
+    for container in &containers {
+        if !busy.contains(container) && !projects.iter().any(|project| project.name == *container) {
+            log::warn!(
+                "Container {container} on mini app host {id} does not belong to any of its projects",
+                id = host.id
+            );
+        }
+    }
+
+    stream::iter(
+        projects
+            .into_iter()
+            .filter(|project| !busy.contains(&project.name)),
+    )
+    .for_each_concurrent(workerparallelism(), |mut project| {
+        let session = &session;
+        let containers = &containers;
+        async move {
+            if let Err(e) =
+                reconcile_project(database, session, host, containers, &mut project).await
+            {
+                log::error!(
+                    "Could not reconcile container of project {project}: {e}",
+                    project = project.name
+                );
+            }
+        }
+    })
+    .await;
+
+    Ok(())
+}
+
+/// Clear the change request of a handler once it finished, a successful change also re-applied the container
+async fn follow_change_request(
+    database: &Database,
+    session: &Session,
+    project: &mut DatabaseProject,
+) -> Result<(), String> {
+    let Some(request) = project.change_request else {
+        return Ok(());
+    };
+
+    let finished = request_finished(session, request).await;
+    if matches!(finished, Ok(false)) {
+        return Ok(());
+    }
+    if finished.is_ok() && project.reconcile_failures > 0 {
+        project
+            .reset_reconcile_failures(database)
+            .await
+            .map_err(|e| format!("Could not reset reconcile failures: {e}"))?;
+    }
+
+    project
+        .update_change_request(database, None)
+        .await
+        .map_err(|e| format!("Could not clear change request: {e}"))
+}
+
+/// Seconds to wait before re-applying again after `failures` failed re-applies in a row
+fn reconcile_backoff(failures: i32) -> i64 {
+    (RECONCILE_BACKOFF << (failures - 1).clamp(0, 16)).min(MAX_RECONCILE_BACKOFF)
+}
+
+async fn reconcile_failed(database: &Database, project: &mut DatabaseProject, error: String) {
+    if let Err(e) = project
+        .update_reconcile_failed(database, error, get_time_i64())
+        .await
+    {
+        log::error!(
+            "Could not store reconcile failure of project {project}: {e}",
+            project = project.name
+        );
+        return;
+    }
+
+    if project.reconcile_failures >= RECONCILE_ATTEMPTS {
+        log::error!(
+            "Giving up on reconciling container of project {project} after {failures} failures: {error:?}",
+            project = project.name,
+            failures = project.reconcile_failures,
+            error = project.reconcile_error
+        );
+    }
+}
+
+async fn reconcile_project(
+    database: &Database,
+    session: &Session,
+    host: &DatabaseMiniappHost,
+    containers: &HashSet<String>,
+    project: &mut DatabaseProject,
+) -> Result<(), String> {
+    if let Some(request) = project.reconcile_request {
+        match request_finished(session, request).await {
+            Ok(true) => {
+                log::info!(
+                    "Reconciled container of project {project} on mini app host {id}",
+                    project = project.name,
+                    id = host.id
+                );
+                if project.reconcile_failures > 0 {
+                    project
+                        .reset_reconcile_failures(database)
+                        .await
+                        .map_err(|e| format!("Could not reset reconcile failures: {e}"))?;
+                }
+            }
+            Ok(false) => {
+                return Ok(());
+            }
+            Err(e) => {
+                log::warn!(
+                    "Reconciling container of project {project} failed: {e}",
+                    project = project.name
+                );
+                reconcile_failed(database, project, e).await;
+            }
+        }
+        project
+            .update_reconcile_request(database, None)
+            .await
+            .map_err(|e| format!("Could not clear reconcile request: {e}"))?;
+        // Checked again next round, after the container settled
+        return Ok(());
+    }
+
+    let flake = project.get_flake();
+    let network = project.get_network();
+    if containers.contains(&project.name) {
+        let config = xnode_manager_sdk::config::get(GetInput::new_with_path(
+            session,
+            GetPath {
+                container: project.name.clone(),
+            },
+        ))
+        .await
+        .map_err(|e| format!("Could not get container config: {e:?}"))?;
+
+        if config.flake.trim() == flake.trim() && config.network == network {
+            // E.g. fixed by an admin
+            if project.reconcile_failures > 0 {
+                project
+                    .reset_reconcile_failures(database)
+                    .await
+                    .map_err(|e| format!("Could not reset reconcile failures: {e}"))?;
+            }
+            return Ok(());
+        }
+        log::warn!(
+            "Container of project {project} on mini app host {id} diverged, reapplying",
+            project = project.name,
+            id = host.id
+        );
+    } else {
+        log::warn!(
+            "Container of project {project} is missing on mini app host {id}, reapplying",
+            project = project.name,
+            id = host.id
+        );
+    }
+
+    if project.reconcile_failures >= RECONCILE_ATTEMPTS
+        || project.reconcile_failed_at.is_some_and(|failed_at| {
+            get_time_i64() - failed_at < reconcile_backoff(project.reconcile_failures)
+        })
+    {
+        return Ok(());
+    }
+
+    let request_response = match xnode_manager_sdk::config::set(SetInput {
+        session,
+        path: SetPath {
+            container: project.name.clone(),
+        },
+        data: ContainerChange {
+            settings: ContainerSettings {
+                flake,
+                network,
+                nvidia_gpus: None,
+            },
+            update_inputs: Some(vec![]),
+        },
+    })
+    .await
+    {
+        Ok(request_response) => request_response,
+        Err(e) => {
+            let error = format!("Could not reapply container: {e:?}");
+            reconcile_failed(database, project, error.clone()).await;
+            return Err(error);
+        }
+    };
+    project
+        .update_reconcile_request(database, Some(request_response.request_id.into()))
+        .await
+        .map_err(|e| format!("Could not store reconcile request: {e}"))?;
+
+    Ok(())
+}
@@ -0,0 +1,328 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::Error;
+
+use crate::database::{
+    Database,
+    deployments::{DatabaseDeployment, DeploymentStatus},
+    worker_events::{DatabaseWorkerEvent, WorkerEventKind},
+};
+
+const DAY: i64 = 24 * 60 * 60;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeploymentCost {
+    pub deployment: i32,
+    pub project: String,
+    pub status: DeploymentStatus,
+    pub coding_seconds: Option<i64>,
+    pub imagegen_seconds: Option<i64>,
+    /// Seconds a worker was assigned to the deployment (including retries)
+    pub worker_seconds: i64,
+    /// USD
+    pub cost: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ProjectCost {
+    pub project: String,
+    pub deployments: i64,
+    pub worker_seconds: i64,
+    /// USD
+    pub cost: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DailyCost {
+    /// Start of the day (UTC)
+    pub day: i64,
+    /// Seconds workers were deployed
+    pub worker_seconds: i64,
+    /// USD
+    pub cost: f64,
+    /// USD spent on workers while they were assigned a deployment, the rest is idle time
+    pub assigned_cost: f64,
+}
+
+struct Interval {
+    deployment: Option<i32>,
+    start: i64,
+    end: i64,
+    hourly_price: i64,
+}
+
+impl Interval {
+    fn seconds(&self, since: i64, until: i64) -> i64 {
+        (self.end.min(until) - self.start.max(since)).max(0)
+    }
+
+    fn cost(&self, since: i64, until: i64) -> f64 {
+        (self.seconds(since, until) * self.hourly_price) as f64 / 3600.0 / 100.0
+    }
+}
+
+/// Deployed intervals and assigned intervals of all workers, intervals that are still open end at `until`
+fn intervals(events: &[DatabaseWorkerEvent], until: i64) -> (Vec<Interval>, Vec<Interval>) {
+    let mut deployed: HashMap<i32, (i64, i64)> = HashMap::new();
+    let mut assigned: HashMap<i32, (i32, i64, i64)> = HashMap::new();
+    let mut deployed_intervals = vec![];
+    let mut assigned_intervals = vec![];
+
+    for event in events {
+        // Workers from before event recording start with their first event
+        deployed
+            .entry(event.worker)
+            .or_insert((event.at, event.hourly_price));
+
+        if matches!(
+            event.event,
+            WorkerEventKind::Assigned | WorkerEventKind::Unassigned | WorkerEventKind::Undeployed
+        ) && let Some((deployment, start, hourly_price)) = assigned.remove(&event.worker)
+        {
+            assigned_intervals.push(Interval {
+                deployment: Some(deployment),
+                start,
+                end: event.at,
+                hourly_price,
+            });
+        }
+
+        match event.event {
+            WorkerEventKind::Assigned => {
+                if let Some(deployment) = event.deployment {
+                    assigned.insert(event.worker, (deployment, event.at, event.hourly_price));
+                }
+            }
+            WorkerEventKind::Undeployed => {
+                if let Some((start, hourly_price)) = deployed.remove(&event.worker) {
+                    deployed_intervals.push(Interval {
+                        deployment: None,
+                        start,
+                        end: event.at,
+                        hourly_price,
+                    });
+                }
+            }
+            _ => {}
+        }
+    }
+
+    deployed_intervals.extend(
+        deployed
+            .into_values()
+            .map(|(start, hourly_price)| Interval {
+                deployment: None,
+                start,
+                end: until,
+                hourly_price,
+            }),
+    );
+    assigned_intervals.extend(
+        assigned
+            .into_values()
+            .map(|(deployment, start, hourly_price)| Interval {
+                deployment: Some(deployment),
+                start,
+                end: until,
+                hourly_price,
+            }),
+    );
+
+    (deployed_intervals, assigned_intervals)
+}
+
+/// Worker cost of each deployment that had a worker assigned between `since` and `until`
+pub async fn deployment_costs(
+    database: &Database,
+    since: i64,
+    until: i64,
+) -> Result<Vec<DeploymentCost>, Error> {
+    let events = DatabaseWorkerEvent::get_all_before(database, until).await?;
+    let (_, assigned) = intervals(&events, until);
+
+    let mut usage: HashMap<i32, (i64, f64)> = HashMap::new();
+    for interval in assigned
+        .iter()
+        .filter(|interval| interval.seconds(since, until) > 0)
+    {
+        if let Some(deployment) = interval.deployment {
+            let (seconds, cost) = usage.entry(deployment).or_default();
+            *seconds += interval.seconds(since, until);
+            *cost += interval.cost(since, until);
+        }
+    }
+
+    let ids: Vec<i32> = usage.keys().copied().collect();
+    let mut costs: Vec<DeploymentCost> = DatabaseDeployment::get_all_by_ids(database, &ids)
+        .await?
+        .into_iter()
+        .map(|deployment| {
+            let (worker_seconds, cost) = usage.get(&deployment.id).copied().unwrap_or_default();
+            DeploymentCost {
+                deployment: deployment.id,
+                project: deployment.project,
+                status: deployment.status,
+                coding_seconds: deployment
+                    .coding_started_at
+                    .zip(deployment.coding_finished_at)
+                    .map(|(started, finished)| finished - started),
+                imagegen_seconds: deployment
+                    .imagegen_started_at
+                    .zip(deployment.imagegen_finished_at)
+                    .map(|(started, finished)| finished - started),
+                worker_seconds,
+                cost,
+            }
+        })
+        .collect();
+    costs.sort_by_key(|cost| cost.deployment);
+
+    Ok(costs)
+}
+
+pub async fn project_costs(
+    database: &Database,
+    since: i64,
+    until: i64,
+) -> Result<Vec<ProjectCost>, Error> {
+    let mut projects: HashMap<String, ProjectCost> = HashMap::new();
+    for deployment in deployment_costs(database, since, until).await? {
+        let project = projects
+            .entry(deployment.project.clone())
+            .or_insert(ProjectCost {
+                project: deployment.project,
+                deployments: 0,
+                worker_seconds: 0,
+                cost: 0.0,
+            });
+        project.deployments += 1;
+        project.worker_seconds += deployment.worker_seconds;
+        project.cost += deployment.cost;
+    }
+
+    let mut costs: Vec<ProjectCost> = projects.into_values().collect();
+    costs.sort_by(|a, b| b.cost.total_cmp(&a.cost));
+
+    Ok(costs)
+}
+
+/// Worker spend per (UTC) day between `since` and `until`
+pub async fn daily_costs(
+    database: &Database,
+    since: i64,
+    until: i64,
+) -> Result<Vec<DailyCost>, Error> {
+    let events = DatabaseWorkerEvent::get_all_before(database, until).await?;
+    let (deployed, assigned) = intervals(&events, until);
+
+    let mut costs = vec![];
+    let mut day = since - since.rem_euclid(DAY);
+    while day < until {
+        let start = day.max(since);
+        let end = (day + DAY).min(until);
+        costs.push(DailyCost {
+            day,
+            worker_seconds: deployed
+                .iter()
+                .map(|interval| interval.seconds(start, end))
+                .sum(),
+            cost: deployed
+                .iter()
+                .map(|interval| interval.cost(start, end))
+                .sum(),
+            assigned_cost: assigned
+                .iter()
+                .map(|interval| interval.cost(start, end))
+                .sum(),
+        });
+        day += DAY;
+    }
+
+    Ok(costs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::worker_events::{
+        DatabaseWorkerEvent,
+        WorkerEventKind::{self, *},
+    };
+
+    use super::{Interval, intervals};
+
+    /// Events of (worker, event, deployment, at), all at 2 USD per hour
+    fn events(events: &[(i32, WorkerEventKind, Option<i32>, i64)]) -> Vec<DatabaseWorkerEvent> {
+        events
+            .iter()
+            .map(|&(worker, event, deployment, at)| DatabaseWorkerEvent {
+                id: 0,
+                worker,
+                event,
+                deployment,
+                hourly_price: 200,
+                at,
+            })
+            .collect()
+    }
+
+    fn spans(intervals: &[Interval]) -> Vec<(Option<i32>, i64, i64)> {
+        let mut spans: Vec<(Option<i32>, i64, i64)> = intervals
+            .iter()
+            .map(|interval| (interval.deployment, interval.start, interval.end))
+            .collect();
+        spans.sort();
+        spans
+    }
+
+    #[test]
+    fn interval_is_clipped_to_the_period() {
+        let interval = Interval {
+            deployment: None,
+            start: 100,
+            end: 3700,
+            hourly_price: 200,
+        };
+
+        assert_eq!(interval.seconds(0, 10_000), 3600);
+        assert_eq!(interval.seconds(1900, 10_000), 1800);
+        assert_eq!(interval.seconds(5000, 10_000), 0);
+        assert_eq!(interval.cost(0, 10_000), 2.0);
+        assert_eq!(interval.cost(1900, 10_000), 1.0);
+    }
+
+    #[test]
+    fn assignments_end_when_released_or_undeployed() {
+        let events = events(&[
+            (1, Deployed, None, 0),
+            (1, Assigned, Some(10), 100),
+            (1, Unassigned, Some(10), 400),
+            (1, Assigned, Some(11), 500),
+            (1, Undeployed, None, 700),
+        ]);
+        let (deployed, assigned) = intervals(&events, 1000);
+
+        assert_eq!(spans(&deployed), vec![(None, 0, 700)]);
+        assert_eq!(
+            spans(&assigned),
+            vec![(Some(10), 100, 400), (Some(11), 500, 700)]
+        );
+    }
+
+    #[test]
+    fn open_intervals_end_at_until() {
+        let events = events(&[
+            (1, Deployed, None, 0),
+            (1, Assigned, Some(10), 100),
+            // Worker from before event recording
+            (2, Assigned, Some(11), 300),
+        ]);
+        let (deployed, assigned) = intervals(&events, 1000);
+
+        assert_eq!(spans(&deployed), vec![(None, 0, 1000), (None, 300, 1000)]);
+        assert_eq!(
+            spans(&assigned),
+            vec![(Some(10), 100, 1000), (Some(11), 300, 1000)]
+        );
+    }
+}
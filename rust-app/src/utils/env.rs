@@ -1,4 +1,7 @@
-use std::path::{Path, PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use alloy::primitives::Address;
 
@@ -14,6 +17,11 @@ pub fn hostname() -> String {
     env_var("HOSTNAME").unwrap_or(String::from("0.0.0.0"))
 }
 
+/// Identifies this factory instance when multiple are running
+pub fn instanceid() -> Option<String> {
+    env_var("INSTANCEID")
+}
+
 pub fn port() -> String {
     env_var("PORT").unwrap_or(String::from("54428"))
 }
@@ -28,8 +36,61 @@ pub fn ghtoken() -> String {
     env_var("GH_TOKEN").expect("No GH_TOKEN supplied.")
 }
 
-pub fn gh() -> String {
-    env_var("GH").unwrap_or("".to_string())
+pub fn admins() -> Vec<String> {
+    env_var("ADMINS")
+        .unwrap_or("eth:519ce4c129a981b2cbb4c3990b1391da24e8ebf3".to_string())
+        .split(',')
+        .map(|admin| admin.trim().to_lowercase())
+        .filter(|admin| !admin.is_empty())
+        .collect()
+}
+
+pub fn sourcehost() -> String {
+    env_var("SOURCEHOST").unwrap_or("github".to_string())
+}
+
+pub fn sourceowner() -> String {
+    env_var("SOURCEOWNER").unwrap_or("miniapp-factory".to_string())
+}
+
+pub fn sourcetemplate() -> String {
+    env_var("SOURCETEMPLATE").unwrap_or("OpenxAI-Network/miniapp-factory-template".to_string())
+}
+
+/// Mini app host the host registry starts with
+pub fn miniapphosturl() -> String {
+    env_var("MINIAPPHOSTURL")
+        .unwrap_or("https://miniapp-host.xnode-manager.openxai.org".to_string())
+}
+
+pub fn miniapphostdomain() -> String {
+    env_var("MINIAPPHOSTDOMAIN").unwrap_or("miniapp-host.xnode-manager.openxai.org".to_string())
+}
+
+pub fn miniapphostcapacity() -> i32 {
+    env_var("MINIAPPHOSTCAPACITY")
+        .and_then(|capacity| capacity.parse().ok())
+        .unwrap_or(10000)
+}
+
+pub fn exposetimeout() -> i64 {
+    env_var("EXPOSETIMEOUT")
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(3600)
+}
+
+pub fn giteaurl() -> String {
+    env_var("GITEAURL").expect("No GITEAURL supplied.")
+}
+
+pub fn giteatoken() -> String {
+    env_var("GITEATOKEN").expect("No GITEATOKEN supplied.")
+}
+
+pub fn localgitdir() -> PathBuf {
+    env_var("LOCALGITDIR")
+        .map(|d| Path::new(&d).to_path_buf())
+        .unwrap_or(datadir().join("git"))
 }
 
 pub fn database() -> String {
@@ -75,3 +136,175 @@ pub fn nft() -> Address {
 pub fn hyperstackapikey() -> String {
     env_var("HYPERSTACKAPIKEY").expect("No HYPERSTACKAPIKEY provided.")
 }
+
+pub fn workerprovider() -> String {
+    env_var("WORKERPROVIDER").unwrap_or(String::from("hyperstack"))
+}
+
+pub fn hyperstackenvironment() -> String {
+    env_var("HYPERSTACKENVIRONMENT").unwrap_or(String::from("default-NORWAY-1"))
+}
+
+pub fn hyperstackflavor() -> String {
+    env_var("HYPERSTACKFLAVOR").unwrap_or(String::from("n3-RTX-A4000x1"))
+}
+
+/// USD cents per hour of each worker flavor, formatted as flavor=price,flavor=price
+pub fn flavorprices() -> HashMap<String, i64> {
+    env_var("FLAVORPRICES")
+        .unwrap_or("n3-RTX-A4000x1=15".to_string())
+        .split(',')
+        .filter_map(|price| {
+            let (flavor, price) = price.split_once('=')?;
+            Some((flavor.trim().to_string(), price.trim().parse().ok()?))
+        })
+        .collect()
+}
+
+pub fn mockworkerurl() -> String {
+    env_var("MOCKWORKERURL").unwrap_or(String::from("http://127.0.0.1:34391"))
+}
+
+pub fn codingtimeout() -> i64 {
+    env_var("CODINGTIMEOUT")
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(3600)
+}
+
+pub fn imagegentimeout() -> i64 {
+    env_var("IMAGEGENTIMEOUT")
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(1800)
+}
+
+pub fn imagegenqueuetimeout() -> i64 {
+    env_var("IMAGEGENQUEUETIMEOUT")
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(7200)
+}
+
+pub fn rollouttimeout() -> i64 {
+    env_var("ROLLOUTTIMEOUT")
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(1800)
+}
+
+pub fn setuptimeout() -> i64 {
+    env_var("SETUPTIMEOUT")
+        .and_then(|timeout| timeout.parse().ok())
+        .unwrap_or(3600)
+}
+
+pub fn unreachablegrace() -> i64 {
+    env_var("UNREACHABLEGRACE")
+        .and_then(|grace| grace.parse().ok())
+        .unwrap_or(300)
+}
+
+pub fn maxretries() -> i32 {
+    env_var("MAXRETRIES")
+        .and_then(|retries| retries.parse().ok())
+        .unwrap_or(2)
+}
+
+pub fn changeprice() -> i64 {
+    env_var("CHANGEPRICE")
+        .and_then(|price| price.parse().ok())
+        .unwrap_or(1_000_000)
+}
+
+pub fn workerparallelism() -> usize {
+    env_var("WORKERPARALLELISM")
+        .and_then(|parallelism| parallelism.parse().ok())
+        .unwrap_or(16)
+}
+
+pub fn sessionlifetime() -> i64 {
+    env_var("SESSIONLIFETIME")
+        .and_then(|lifetime| lifetime.parse().ok())
+        .unwrap_or(1800)
+}
+
+pub fn expressprice() -> i64 {
+    env_var("EXPRESSPRICE")
+        .and_then(|price| price.parse().ok())
+        .unwrap_or(2_000_000)
+}
+
+pub fn maxaccountdeployments() -> i64 {
+    env_var("MAXACCOUNTDEPLOYMENTS")
+        .and_then(|deployments| deployments.parse().ok())
+        .unwrap_or(2)
+}
+
+pub fn freechanges() -> i64 {
+    env_var("FREECHANGES")
+        .and_then(|changes| changes.parse().ok())
+        .unwrap_or(3)
+}
+
+pub fn dailyfreechanges() -> i64 {
+    env_var("DAILYFREECHANGES")
+        .and_then(|changes| changes.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn migrationattempts() -> i32 {
+    env_var("MIGRATIONATTEMPTS")
+        .and_then(|attempts| attempts.parse().ok())
+        .unwrap_or(5)
+}
+
+pub fn provisioningbudget() -> i64 {
+    env_var("PROVISIONINGBUDGET")
+        .and_then(|budget| budget.parse().ok())
+        .unwrap_or(600)
+}
+
+pub fn logmaxbytes() -> usize {
+    env_var("LOGMAXBYTES")
+        .and_then(|bytes| bytes.parse().ok())
+        .unwrap_or(1_048_576)
+}
+
+pub fn logretention() -> i64 {
+    env_var("LOGRETENTION")
+        .and_then(|retention| retention.parse().ok())
+        .unwrap_or(30 * 24 * 60 * 60)
+}
+
+pub fn minworkers() -> i64 {
+    env_var("MINWORKERS")
+        .and_then(|workers| workers.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn maxworkers() -> i64 {
+    env_var("MAXWORKERS")
+        .and_then(|workers| workers.parse().ok())
+        .unwrap_or(10)
+}
+
+pub fn queueperworker() -> i64 {
+    env_var("QUEUEPERWORKER")
+        .and_then(|queue| queue.parse().ok())
+        .unwrap_or(3)
+}
+
+pub fn idlecooldown() -> i64 {
+    env_var("IDLECOOLDOWN")
+        .and_then(|cooldown| cooldown.parse().ok())
+        .unwrap_or(900)
+}
+
+pub fn warmstandby() -> i64 {
+    env_var("WARMSTANDBY")
+        .and_then(|standby| standby.parse().ok())
+        .unwrap_or(0)
+}
+
+pub fn maxpaidworkers() -> i64 {
+    env_var("MAXPAIDWORKERS")
+        .and_then(|workers| workers.parse().ok())
+        .unwrap_or(10)
+}
@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
+use sqlx::Error;
+
+use crate::database::{
+    Database,
+    deployments::{DatabaseDeployment, DeploymentStatus},
+    worker_events::DatabaseWorkerEvent,
+    worker_servers::{DatabaseWorkerServer, WorkerRole},
+};
+
+/// Recent deployments (and worker setups) the statistics are based on
+const SAMPLES: i64 = 50;
+
+fn mean(values: impl Iterator<Item = i64>) -> Option<f64> {
+    let (sum, count) = values.fold((0, 0), |(sum, count), value| (sum + value, count + 1));
+    (count != 0).then(|| sum as f64 / count as f64)
+}
+
+/// Rolling averages (in seconds) of each deployment phase
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct PhaseStatistics {
+    pub samples: i64,
+    /// From submission until a coder picked it up
+    pub waiting: Option<f64>,
+    pub coding: Option<f64>,
+    pub imagegen: Option<f64>,
+    /// Fraction of deployments that generated images
+    pub imagegen_ratio: f64,
+    /// From deploying a worker until it can take assignments
+    pub setup: Option<f64>,
+}
+
+impl PhaseStatistics {
+    pub async fn recent(database: &Database) -> Result<Self, Error> {
+        let deployments = DatabaseDeployment::get_recent_finished(database, SAMPLES).await?;
+        let setup = DatabaseWorkerEvent::get_average_setup_duration(database, SAMPLES).await?;
+
+        let imagegen_count = deployments
+            .iter()
+            .filter(|deployment| deployment.imagegen_finished_at.is_some())
+            .count();
+        Ok(Self {
+            samples: deployments.len() as i64,
+            waiting: mean(deployments.iter().filter_map(|deployment| {
+                deployment
+                    .coding_started_at
+                    .map(|started| started - deployment.submitted_at)
+            })),
+            coding: mean(deployments.iter().filter_map(|deployment| {
+                deployment
+                    .coding_started_at
+                    .zip(deployment.coding_finished_at)
+                    .map(|(started, finished)| finished - started)
+            })),
+            imagegen: mean(deployments.iter().filter_map(|deployment| {
+                deployment
+                    .imagegen_started_at
+                    .zip(deployment.imagegen_finished_at)
+                    .map(|(started, finished)| finished - started)
+            })),
+            imagegen_ratio: if deployments.is_empty() {
+                1.0
+            } else {
+                imagegen_count as f64 / deployments.len() as f64
+            },
+            setup,
+        })
+    }
+
+    /// Expected seconds of imagegen for a deployment, taking skipped imagegen into account
+    fn expected_imagegen(&self) -> f64 {
+        self.imagegen.unwrap_or_default() * self.imagegen_ratio
+    }
+
+    /// Expected seconds a worker is busy with one queued deployment
+    fn occupancy(&self, coding: f64, role: WorkerRole) -> f64 {
+        if role.is_imagegen() {
+            coding + self.expected_imagegen()
+        } else {
+            coding
+        }
+    }
+
+    /// Expected seconds from starting coding until the worker phases are finished
+    pub fn processing(&self) -> Option<f64> {
+        self.coding.map(|coding| coding + self.expected_imagegen())
+    }
+}
+
+/// Estimated start time of the first `count` queued deployments (in scheduling order)
+///
+/// Every coder worker is free once its current assignment is expected to finish (or its setup for new workers), queued deployments take the first free worker.
+/// Without any worker, one is expected to be deployed by the autoscaler.
+pub fn estimate_starts(
+    statistics: &PhaseStatistics,
+    workers: &[DatabaseWorkerServer],
+    assigned: &[DatabaseDeployment],
+    count: usize,
+    now: i64,
+) -> Option<Vec<i64>> {
+    let coding = statistics.coding?;
+    let setup = statistics.setup.unwrap_or_default();
+    let assigned: HashMap<i32, &DatabaseDeployment> = assigned
+        .iter()
+        .map(|deployment| (deployment.id, deployment))
+        .collect();
+
+    let mut free: Vec<(f64, WorkerRole)> = workers
+        .iter()
+        .filter(|worker| !worker.draining && worker.role.is_coder())
+        .map(|worker| {
+            let available = if !worker.setup_finished {
+                worker.idle_since.unwrap_or(now) as f64 + setup
+            } else if let Some(deployment) = worker
+                .assignment
+                .and_then(|assignment| assigned.get(&assignment))
+            {
+                match deployment.status {
+                    DeploymentStatus::Coding => {
+                        deployment.coding_started_at.unwrap_or(now) as f64
+                            + statistics.occupancy(coding, worker.role)
+                    }
+                    DeploymentStatus::Imagegen => {
+                        deployment.imagegen_started_at.unwrap_or(now) as f64
+                            + statistics.imagegen.unwrap_or_default()
+                    }
+                    _ => now as f64,
+                }
+            } else {
+                now as f64
+            };
+            (available.max(now as f64), worker.role)
+        })
+        .collect();
+    if free.is_empty() {
+        free.push((now as f64 + setup, WorkerRole::Both));
+    }
+
+    let mut starts = Vec::with_capacity(count);
+    for _ in 0..count {
+        if let Some((available, role)) = free.iter_mut().min_by(|(a, _), (b, _)| a.total_cmp(b)) {
+            starts.push(*available as i64);
+            *available += statistics.occupancy(coding, *role);
+        }
+    }
+
+    Some(starts)
+}
+
+/// Estimated start time of the first `count` queued deployments, based on the current workers and their assignments
+pub async fn queue_starts(
+    database: &Database,
+    statistics: &PhaseStatistics,
+    count: usize,
+    now: i64,
+) -> Result<Option<Vec<i64>>, Error> {
+    let workers = DatabaseWorkerServer::get_all(database).await?;
+    let assignments: Vec<i32> = workers
+        .iter()
+        .filter_map(|worker| worker.assignment)
+        .collect();
+    let assigned = DatabaseDeployment::get_all_by_ids(database, &assignments).await?;
+
+    Ok(estimate_starts(statistics, &workers, &assigned, count, now))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::database::{
+        deployments::{DatabaseDeployment, DeploymentStatus},
+        worker_servers::{DatabaseWorkerServer, WorkerRole},
+    };
+
+    use super::{PhaseStatistics, estimate_starts};
+
+    const NOW: i64 = 1_000_000;
+
+    fn statistics() -> PhaseStatistics {
+        PhaseStatistics {
+            samples: 10,
+            waiting: Some(30.0),
+            coding: Some(600.0),
+            imagegen: Some(300.0),
+            imagegen_ratio: 0.5,
+            setup: Some(900.0),
+        }
+    }
+
+    fn idle(id: i32, role: WorkerRole) -> DatabaseWorkerServer {
+        DatabaseWorkerServer {
+            role,
+            ..DatabaseWorkerServer::idle(id, NOW)
+        }
+    }
+
+    #[test]
+    fn processing_weighs_imagegen_by_its_ratio() {
+        assert_eq!(statistics().processing(), Some(750.0));
+    }
+
+    #[test]
+    fn no_estimate_without_coding_history() {
+        let statistics = PhaseStatistics {
+            coding: None,
+            ..statistics()
+        };
+
+        assert_eq!(estimate_starts(&statistics, &[], &[], 1, NOW), None);
+    }
+
+    #[test]
+    fn without_workers_one_is_expected_to_be_deployed() {
+        assert_eq!(
+            estimate_starts(&statistics(), &[], &[], 2, NOW),
+            Some(vec![NOW + 900, NOW + 900 + 750])
+        );
+    }
+
+    #[test]
+    fn queued_deployments_take_the_first_free_worker() {
+        let workers = [
+            DatabaseWorkerServer {
+                role: WorkerRole::Coder,
+                ..DatabaseWorkerServer::assigned(1, 10)
+            },
+            idle(2, WorkerRole::Coder),
+        ];
+        let assigned = [DatabaseDeployment {
+            status: DeploymentStatus::Coding,
+            coding_started_at: Some(NOW - 200),
+            ..DatabaseDeployment::queued(10, "project")
+        }];
+
+        assert_eq!(
+            estimate_starts(&statistics(), &workers, &assigned, 3, NOW),
+            Some(vec![NOW, NOW + 400, NOW + 600])
+        );
+    }
+
+    #[test]
+    fn imagegen_only_workers_do_not_start_queued_deployments() {
+        let workers = [idle(1, WorkerRole::Imagegen), idle(2, WorkerRole::Both)];
+
+        assert_eq!(
+            estimate_starts(&statistics(), &workers, &[], 2, NOW),
+            Some(vec![NOW, NOW + 750])
+        );
+    }
+
+    #[test]
+    fn workers_in_setup_are_free_once_set_up() {
+        let starting = DatabaseWorkerServer {
+            setup_finished: false,
+            ..DatabaseWorkerServer::idle(1, NOW - 300)
+        };
+
+        assert_eq!(
+            estimate_starts(&statistics(), &[starting], &[], 1, NOW),
+            Some(vec![NOW + 600])
+        );
+    }
+}
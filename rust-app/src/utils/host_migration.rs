@@ -0,0 +1,259 @@
+use std::time::Duration;
+
+use sqlx::Error;
+use tokio::time;
+
+use crate::{
+    database::{
+        Database,
+        host_migrations::{DatabaseHostMigration, MigrationStatus},
+        projects::DatabaseProject,
+    },
+    utils::{
+        env::migrationattempts,
+        provisioning::{expose_deadline, request_finished},
+        sessions::{invalidate_host_session, miniapp_host, miniapp_host_session},
+        time::get_time_i64,
+    },
+};
+
+pub async fn migrate_projects(database: Database) {
+    let mut interval = time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let migrations = match DatabaseHostMigration::get_all_unfinished(&database).await {
+            Ok(migrations) => migrations,
+            Err(e) => {
+                log::error!("Could not get unfinished host migrations: {e}");
+                continue;
+            }
+        };
+
+        for mut migration in migrations {
+            advance_migration(&database, &mut migration).await;
+        }
+    }
+}
+
+/// Executes the current migration step, a step that keeps failing marks the migration as failed so it can be resumed later
+async fn advance_migration(database: &Database, migration: &mut DatabaseHostMigration) {
+    match migration_step(database, migration).await {
+        Ok(true) => {
+            let next = migration.status.next();
+            let finished_at = next.is_finished().then(get_time_i64);
+            if let Err(e) = migration.update_status(database, next, finished_at).await {
+                log::error!(
+                    "Could not update host migration {id} to {next}: {e}",
+                    id = migration.id
+                );
+                return;
+            }
+            if next == MigrationStatus::Done {
+                log::info!(
+                    "Moved project {project} from mini app host {source} to {target}",
+                    project = migration.project,
+                    source = migration.source,
+                    target = migration.target
+                );
+            }
+        }
+        Ok(false) => {
+            // Waiting on a mini app host
+        }
+        Err(error) => {
+            log::warn!(
+                "Host migration step {status} of project {project} failed: {error}",
+                status = migration.status,
+                project = migration.project
+            );
+            if let Err(e) = migration.update_error(database, error).await {
+                log::error!(
+                    "Could not update host migration {id} error: {e}",
+                    id = migration.id
+                );
+                return;
+            }
+
+            if migration.attempts >= migrationattempts() {
+                log::error!(
+                    "Host migration {id} of project {project} failed at step {status}: {error:?}",
+                    id = migration.id,
+                    project = migration.project,
+                    status = migration.status,
+                    error = migration.error
+                );
+                if let Err(e) = migration.update_failed(database, get_time_i64()).await {
+                    log::error!(
+                        "Could not mark host migration {id} as failed: {e}",
+                        id = migration.id
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// Returns whether the step is completed, steps that submit a request to a mini app host complete once the request succeeded
+async fn migration_step(
+    database: &Database,
+    migration: &mut DatabaseHostMigration,
+) -> Result<bool, String> {
+    match migration.status {
+        MigrationStatus::Container => {
+            let project = migration_project(database, migration).await?;
+            let (host, session) = miniapp_host_session(database, migration.target).await?;
+            if let Some(request) = migration.container_request {
+                let finished = request_finished(&session, request).await;
+                if finished.is_err()
+                    && let Err(e) = migration
+                        .update_container_request(database, None, None)
+                        .await
+                {
+                    log::error!(
+                        "Could not clear container request of host migration {id}: {e}",
+                        id = migration.id
+                    );
+                }
+                return finished.map_err(|e| format!("Target mini app host container failed: {e}"));
+            }
+
+            let flake = project.get_flake();
+            let request_response =
+                match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
+                    session: &session,
+                    path: xnode_manager_sdk::config::SetPath {
+                        container: project.name.clone(),
+                    },
+                    data: xnode_manager_sdk::config::ContainerChange {
+                        settings: xnode_manager_sdk::config::ContainerSettings {
+                            flake: flake.clone(),
+                            network: Some(migration.network.clone()),
+                            nvidia_gpus: None,
+                        },
+                        update_inputs: None,
+                    },
+                })
+                .await
+                {
+                    Ok(request_response) => request_response,
+                    Err(e) => {
+                        invalidate_host_session(host.id, &session).await;
+                        return Err(format!(
+                            "Could not create project on target mini app host: {e:?}"
+                        ));
+                    }
+                };
+            migration
+                .update_container_request(
+                    database,
+                    Some(request_response.request_id.into()),
+                    Some(flake),
+                )
+                .await
+                .map_err(|e| format!("Could not store container request: {e}"))?;
+
+            Ok(false)
+        }
+        MigrationStatus::Assign => {
+            let mut project = migration_project(database, migration).await?;
+
+            // Changes made to the project on the source host while the target container was created
+            if migration.flake.as_ref() != Some(&project.get_flake()) {
+                migration
+                    .update_container_request(database, None, None)
+                    .await
+                    .map_err(|e| format!("Could not clear container request: {e}"))?;
+                migration
+                    .update_status(database, MigrationStatus::Container, None)
+                    .await
+                    .map_err(|e| format!("Could not redeploy target container: {e}"))?;
+                return Ok(false);
+            }
+
+            // The rollout request of a deployment can only be followed on the host it was submitted to
+            match project
+                .update_miniapp_host(database, migration.target, migration.network.clone())
+                .await
+            {
+                Ok(()) => Ok(true),
+                Err(Error::RowNotFound) => Ok(false),
+                Err(e) => Err(format!("Could not update project mini app host: {e}")),
+            }
+        }
+        MigrationStatus::Expose => {
+            let target = miniapp_host(database, migration.target).await?;
+            let source = miniapp_host(database, migration.source).await?;
+
+            if target.is_listed(&migration.project) && !source.is_listed(&migration.project) {
+                return Ok(true);
+            }
+
+            expose_deadline(migration.get_step_started_at())?;
+            Ok(false)
+        }
+        MigrationStatus::Rebuild => {
+            let target = miniapp_host(database, migration.target).await?;
+            let source = miniapp_host(database, migration.source).await?;
+
+            if target.is_exposed(&migration.project) && !source.is_exposed(&migration.project) {
+                return Ok(true);
+            }
+
+            expose_deadline(migration.get_step_started_at())?;
+            Ok(false)
+        }
+        MigrationStatus::Remove => {
+            let (host, session) = miniapp_host_session(database, migration.source).await?;
+            if let Some(request) = migration.remove_request {
+                let finished = request_finished(&session, request).await;
+                if finished.is_err()
+                    && let Err(e) = migration.update_remove_request(database, None).await
+                {
+                    log::error!(
+                        "Could not clear remove request of host migration {id}: {e}",
+                        id = migration.id
+                    );
+                }
+                return finished.map_err(|e| format!("Source mini app host removal failed: {e}"));
+            }
+
+            let request_response = match xnode_manager_sdk::config::remove(
+                xnode_manager_sdk::config::RemoveInput::new_with_path(
+                    &session,
+                    xnode_manager_sdk::config::RemovePath {
+                        container: migration.project.clone(),
+                    },
+                ),
+            )
+            .await
+            {
+                Ok(request_response) => request_response,
+                Err(e) => {
+                    invalidate_host_session(host.id, &session).await;
+                    return Err(format!(
+                        "Could not remove project from source mini app host: {e:?}"
+                    ));
+                }
+            };
+            migration
+                .update_remove_request(database, Some(request_response.request_id.into()))
+                .await
+                .map_err(|e| format!("Could not store remove request: {e}"))?;
+
+            Ok(false)
+        }
+        MigrationStatus::Done | MigrationStatus::Failed => Ok(true),
+    }
+}
+
+async fn migration_project(
+    database: &Database,
+    migration: &DatabaseHostMigration,
+) -> Result<DatabaseProject, String> {
+    DatabaseProject::get_by_name(database, &migration.project)
+        .await
+        .map_err(|e| format!("Could not get project from the database: {e}"))?
+        .ok_or("Project does not exist".to_string())
+}
@@ -0,0 +1,238 @@
+use std::{collections::HashSet, time::Duration};
+
+use tokio::time;
+use xnode_manager_sdk::{
+    file::{ReadFile, ReadFileInput, ReadFilePath, WriteFile, WriteFileInput, WriteFilePath},
+    utils::Session,
+};
+
+use crate::{
+    database::{Database, miniapp_hosts::DatabaseMiniappHost, projects::DatabaseProject},
+    utils::{
+        output::output_to_string,
+        provisioning::request_finished,
+        sessions::{host_session, invalidate_host_session},
+        time::get_time_i64,
+    },
+};
+
+const EXPOSE_FILE: &str = "/etc/nixos/exposed";
+const REBUILD_BACKOFF: i64 = 30;
+const MAX_REBUILD_BACKOFF: i64 = 3600;
+
+/// Keeps the expose file of every mini app host in line with the projects placed on it
+///
+/// This is the only writer of the expose files, so updates never race. Projects added while an os rebuild is running are applied together by the next rebuild.
+pub async fn sync_hosts(database: Database) {
+    let mut interval = time::interval(Duration::from_secs(10));
+
+    loop {
+        interval.tick().await;
+
+        let hosts = match DatabaseMiniappHost::get_all(&database).await {
+            Ok(hosts) => hosts,
+            Err(e) => {
+                log::error!("Could not get mini app hosts: {e}");
+                continue;
+            }
+        };
+
+        for mut host in hosts {
+            if let Err(e) = sync_host(&database, &mut host).await {
+                log::error!("Could not sync mini app host {id}: {e}", id = host.id);
+            }
+        }
+    }
+}
+
+async fn sync_host(database: &Database, host: &mut DatabaseMiniappHost) -> Result<(), String> {
+    let session = host_session(host).await?;
+
+    if let Some(request) = host.rebuild_request {
+        match request_finished(&session, request).await {
+            Ok(true) => {
+                host.update_exposed(database)
+                    .await
+                    .map_err(|e| format!("Could not store applied expose file: {e}"))?;
+            }
+            Ok(false) => {
+                // Changes in the meantime are batched into the next rebuild
+                return Ok(());
+            }
+            Err(e) => {
+                log::error!(
+                    "Os rebuild of mini app host {id} failed ({failures} in a row): {e}",
+                    id = host.id,
+                    failures = host.rebuild_failures + 1
+                );
+                host.update_rebuild_failed(database, get_time_i64())
+                    .await
+                    .map_err(|e| format!("Could not store rebuild failure: {e}"))?;
+            }
+        }
+    }
+
+    let exposed = desired_exposed(database, host).await?;
+    let current = match read_exposed(&session).await {
+        Ok(current) => Some(current),
+        Err(e) => {
+            log::warn!(
+                "Could not read expose file of mini app host {id}: {e}",
+                id = host.id
+            );
+            None
+        }
+    };
+
+    // The file was changed by something other than this reconciler
+    let written = host.rebuild_exposed.as_ref().or(host.exposed.as_ref());
+    if let (Some(current), Some(written)) = (&current, written)
+        && current != written
+    {
+        let (missing, unexpected) = difference(current, written);
+        log::warn!(
+            "Expose file of mini app host {id} drifted, {missing} projects missing and {unexpected} unexpected",
+            id = host.id
+        );
+        host.update_drifted_at(database, get_time_i64())
+            .await
+            .map_err(|e| format!("Could not store drift: {e}"))?;
+    }
+
+    if current.as_ref() != Some(&exposed) {
+        if let Err(e) = write_exposed(&session, &exposed).await {
+            invalidate_host_session(host.id, &session).await;
+            return Err(e);
+        }
+    }
+    host.update_synced_at(database, get_time_i64())
+        .await
+        .map_err(|e| format!("Could not store sync time: {e}"))?;
+
+    if host.exposed.as_ref() != Some(&exposed) {
+        if let Some(failed_at) = host.rebuild_failed_at
+            && host.rebuild_failures > 0
+            && get_time_i64() - failed_at < rebuild_backoff(host.rebuild_failures)
+        {
+            // Retried once the backoff passed, the expose file is already up to date
+            return Ok(());
+        }
+
+        let request = rebuild_host(&session).await?;
+        host.update_rebuild_request(database, Some(request), Some(exposed))
+            .await
+            .map_err(|e| format!("Could not store rebuild request: {e}"))?;
+    }
+
+    Ok(())
+}
+
+/// Seconds to wait before rebuilding again after `failures` failed rebuilds in a row, doubling up to an hour
+fn rebuild_backoff(failures: i32) -> i64 {
+    (REBUILD_BACKOFF << (failures - 1).clamp(0, 16)).min(MAX_REBUILD_BACKOFF)
+}
+
+/// Sorted so the content only changes when the projects on the host change
+async fn desired_exposed(
+    database: &Database,
+    host: &DatabaseMiniappHost,
+) -> Result<String, String> {
+    let mut projects: Vec<String> = DatabaseProject::get_all_by_miniapp_host(database, host.id)
+        .await
+        .map_err(|e| format!("Could not get projects from database: {e}"))?
+        .into_iter()
+        .map(|project| project.name)
+        .collect();
+    projects.sort();
+
+    Ok(projects.join("\n"))
+}
+
+/// Amount of projects in `written` that are missing from `current` and the other way around
+fn difference(current: &str, written: &str) -> (usize, usize) {
+    let current: HashSet<&str> = current.lines().collect();
+    let written: HashSet<&str> = written.lines().collect();
+
+    (
+        written.difference(&current).count(),
+        current.difference(&written).count(),
+    )
+}
+
+async fn read_exposed(session: &Session) -> Result<String, String> {
+    xnode_manager_sdk::file::read_file(ReadFileInput {
+        session,
+        path: ReadFilePath {
+            scope: "host".to_string(),
+        },
+        query: ReadFile {
+            path: EXPOSE_FILE.to_string(),
+        },
+    })
+    .await
+    .map(|file| output_to_string(file.content))
+    .map_err(|e| format!("{e:?}"))
+}
+
+async fn write_exposed(session: &Session, exposed: &str) -> Result<(), String> {
+    xnode_manager_sdk::file::write_file(WriteFileInput {
+        session,
+        path: WriteFilePath {
+            scope: "host".to_string(),
+        },
+        data: WriteFile {
+            path: EXPOSE_FILE.to_string(),
+            content: exposed.to_string().into(),
+        },
+    })
+    .await
+    .map_err(|e| format!("Could not update mini app host expose file: {e:?}"))?;
+
+    Ok(())
+}
+
+/// Rebuild the os of a mini app host to apply its expose file, returns the request id
+async fn rebuild_host(session: &Session) -> Result<i64, String> {
+    let request_response =
+        xnode_manager_sdk::os::set(xnode_manager_sdk::os::SetInput::new_with_data(
+            session,
+            xnode_manager_sdk::os::OSChange {
+                flake: None,
+                update_inputs: Some(vec![]),
+                xnode_owner: None,
+                domain: None,
+                acme_email: None,
+                user_passwd: None,
+            },
+        ))
+        .await
+        .map_err(|e| format!("Could not update mini app host os: {e:?}"))?;
+
+    Ok(request_response.request_id.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_REBUILD_BACKOFF, REBUILD_BACKOFF, difference, rebuild_backoff};
+
+    #[test]
+    fn difference_counts_both_ways() {
+        assert_eq!(difference("a\nb\nc", "a\nb\nc"), (0, 0));
+        assert_eq!(difference("a\nb", "a\nb\nc"), (1, 0));
+        assert_eq!(difference("a\nb\nc\nd", "a\nb"), (0, 2));
+        assert_eq!(difference("", "a"), (1, 0));
+    }
+
+    #[test]
+    fn difference_ignores_order() {
+        assert_eq!(difference("c\nb\na", "a\nb\nc"), (0, 0));
+    }
+
+    #[test]
+    fn rebuild_backoff_doubles_up_to_the_max() {
+        assert_eq!(rebuild_backoff(1), REBUILD_BACKOFF);
+        assert_eq!(rebuild_backoff(2), REBUILD_BACKOFF * 2);
+        assert_eq!(rebuild_backoff(3), REBUILD_BACKOFF * 4);
+        assert_eq!(rebuild_backoff(100), MAX_REBUILD_BACKOFF);
+    }
+}
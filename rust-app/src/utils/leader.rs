@@ -0,0 +1,111 @@
+use std::{sync::LazyLock, time::Duration};
+
+use rand::{Rng, distr::Alphanumeric};
+use sqlx::{Connection, postgres::PgConnection, query_scalar};
+use tokio::{spawn, time};
+
+use crate::{
+    database::{Database, leaders::DatabaseLeader},
+    utils::{env::instanceid, time::get_time_i64},
+};
+
+/// How often the lock connection is checked and the heartbeat recorded
+const HEARTBEAT: Duration = Duration::from_secs(10);
+
+static INSTANCE: LazyLock<String> = LazyLock::new(|| {
+    instanceid().unwrap_or_else(|| {
+        format!(
+            "miniapp-factory-{random}",
+            random = rand::rng()
+                .sample_iter(&Alphanumeric)
+                .take(10)
+                .map(char::from)
+                .collect::<String>()
+        )
+    })
+});
+
+pub fn instance() -> String {
+    INSTANCE.clone()
+}
+
+async fn try_lock(connection: &mut PgConnection, role: &str) -> Result<bool, sqlx::Error> {
+    query_scalar("SELECT pg_try_advisory_lock(hashtext('miniapp-factory:' || $1))")
+        .bind(role)
+        .fetch_one(connection)
+        .await
+}
+
+/// Run a background loop on only one of the factory instances
+///
+/// The instance holding the Postgres advisory lock of `role` runs the loop. The lock is bound to a dedicated connection, when the instance dies (or loses its connection) the lock is released and another instance takes over.
+pub async fn singleton<F, Fut>(database: Database, role: &'static str, run: F)
+where
+    F: Fn() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut interval = time::interval(HEARTBEAT);
+
+    loop {
+        interval.tick().await;
+
+        let mut connection = match database.detached().await {
+            Ok(connection) => connection,
+            Err(e) => {
+                log::error!("Could not get lock connection for {role}: {e}");
+                continue;
+            }
+        };
+        match try_lock(&mut connection, role).await {
+            Ok(true) => {}
+            Ok(false) => {
+                // Another instance is the leader
+                continue;
+            }
+            Err(e) => {
+                log::error!("Could not try to acquire {role} lock: {e}");
+                continue;
+            }
+        }
+
+        log::info!(
+            "Instance {instance} became leader of {role}",
+            instance = instance()
+        );
+        let now = get_time_i64();
+        let mut leader = DatabaseLeader {
+            role: role.to_string(),
+            instance: instance(),
+            acquired_at: now,
+            heartbeat_at: now,
+        };
+        if let Err(e) = leader.upsert(&database).await {
+            log::error!("Could not record leader {leader:?}: {e}");
+        }
+
+        let task = spawn(run());
+        loop {
+            interval.tick().await;
+
+            if task.is_finished() {
+                log::error!("{role} stopped unexpectedly, releasing leadership");
+                break;
+            }
+
+            if let Err(e) = connection.ping().await {
+                log::error!("Lost {role} lock connection, stopping: {e}");
+                task.abort();
+                break;
+            }
+
+            if let Err(e) = leader.update_heartbeat_at(&database, get_time_i64()).await {
+                log::warn!("Could not update heartbeat of {role} leader: {e}");
+            }
+        }
+
+        // Closing the connection releases the lock
+        if let Err(e) = connection.close().await {
+            log::warn!("Could not close {role} lock connection: {e}");
+        }
+    }
+}
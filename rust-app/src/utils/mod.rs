@@ -1,9 +1,21 @@
 pub mod auth;
+pub mod autoscaler;
+pub mod container_sync;
+pub mod costs;
 pub mod env;
 pub mod error;
+pub mod eta;
+pub mod host_migration;
+pub mod host_sync;
 pub mod keccak;
+pub mod leader;
 pub mod nft;
+pub mod output;
 pub mod price;
+pub mod provisioning;
 pub mod runner;
+pub mod scheduler;
+pub mod sessions;
 pub mod time;
 pub mod wallet;
+pub mod watchdog;
@@ -0,0 +1,518 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+    time::Duration,
+};
+
+use actix_web::web::Bytes;
+use futures_util::{Stream, stream};
+use serde::Serialize;
+use serde_json::json;
+use tokio::{
+    spawn,
+    sync::watch,
+    time::{self, sleep},
+};
+use xnode_manager_sdk::{
+    file::{ReadFile, ReadFileInput, ReadFilePath},
+    process::{LogQuery, LogsInput, LogsPath},
+    utils::{Output, Session},
+};
+
+use crate::{
+    database::{
+        Database,
+        deployment_logs::DatabaseDeploymentLog,
+        deployments::{DatabaseDeployment, DeploymentStatus},
+        worker_servers::DatabaseWorkerServer,
+    },
+    utils::{
+        env::{logmaxbytes, logretention},
+        runner::coding_server_session,
+        sessions::invalidate_worker_session,
+        time::get_time_i64,
+    },
+};
+
+pub fn output_to_string(output: Output) -> String {
+    match output {
+        Output::UTF8 { output } => output,
+        Output::Bytes { output } => String::from_utf8_lossy(&output).to_string(),
+    }
+}
+
+pub async fn read_coder_chat(
+    session: &Session,
+    server: &DatabaseWorkerServer,
+    project: &str,
+) -> Option<String> {
+    match xnode_manager_sdk::file::read_file(ReadFileInput {
+        session,
+        path: ReadFilePath {
+            scope: "container:miniapp-factory-coder".to_string(),
+        },
+        query: ReadFile {
+            path: format!(
+                "/var/lib/miniapp-factory-coder/projects/{project}/.aider.chat.history.md"
+            ),
+        },
+    })
+    .await
+    {
+        Ok(chat) => Some(output_to_string(chat.content)),
+        Err(e) => {
+            log::warn!(
+                "Error reading {project} chat from {server}: {e:?}",
+                server = server.id
+            );
+            None
+        }
+    }
+}
+
+pub async fn read_imagegen_logs(
+    session: &Session,
+    server: &DatabaseWorkerServer,
+    project: &str,
+) -> Option<String> {
+    read_imagegen_log_lines(session, server, project, false)
+        .await
+        .map(|lines| lines.join("\n"))
+}
+
+/// Lines of the comfyui log, only the last 200 when `tail` is set
+async fn read_imagegen_log_lines(
+    session: &Session,
+    server: &DatabaseWorkerServer,
+    project: &str,
+    tail: bool,
+) -> Option<Vec<String>> {
+    match xnode_manager_sdk::process::logs(LogsInput {
+        session,
+        path: LogsPath {
+            scope: "container:miniapp-factory-imagegen".to_string(),
+            process: "comfyui.service".to_string(),
+        },
+        query: LogQuery {
+            level: None,
+            max: tail.then_some(200),
+        },
+    })
+    .await
+    {
+        Ok(logs) => Some(
+            logs.into_iter()
+                .map(|log| output_to_string(log.message))
+                .collect(),
+        ),
+        Err(e) => {
+            log::warn!(
+                "Error reading {project} logs from {server}: {e:?}",
+                server = server.id
+            );
+            None
+        }
+    }
+}
+
+/// Append the lines of `tail` that come after its overlap with the end of `lines`
+fn append_new_lines(lines: &mut Vec<String>, tail: Vec<String>) {
+    let overlap = (0..=tail.len().min(lines.len()))
+        .rev()
+        .find(|&overlap| lines[lines.len() - overlap..] == tail[..overlap])
+        .unwrap_or_default();
+    lines.extend(tail.into_iter().skip(overlap));
+}
+
+/// Output of the phase the deployment is currently in, empty when no worker is working on it
+pub async fn read_live_output(
+    session: &Session,
+    server: &DatabaseWorkerServer,
+    deployment: &DatabaseDeployment,
+) -> String {
+    match deployment.status {
+        DeploymentStatus::Coding => read_coder_chat(session, server, &deployment.project).await,
+        DeploymentStatus::Imagegen => {
+            read_imagegen_logs(session, server, &deployment.project).await
+        }
+        _ => None,
+    }
+    .unwrap_or_default()
+}
+
+/// Store the output of a phase, so it stays available after the worker is released
+pub async fn capture_output(
+    database: &Database,
+    session: &Session,
+    server: &DatabaseWorkerServer,
+    deployment: &DatabaseDeployment,
+    phase: DeploymentStatus,
+) {
+    let output = match phase {
+        DeploymentStatus::Coding => read_coder_chat(session, server, &deployment.project).await,
+        DeploymentStatus::Imagegen => {
+            read_imagegen_logs(session, server, &deployment.project).await
+        }
+        _ => None,
+    };
+    let mut content = match output {
+        Some(content) => content,
+        None => {
+            return;
+        }
+    };
+
+    let max_bytes = logmaxbytes();
+    let truncated = content.len() > max_bytes;
+    if truncated {
+        // The end of the output contains the result
+        let mut start = content.len() - max_bytes;
+        while !content.is_char_boundary(start) {
+            start += 1;
+        }
+        content = content.split_off(start);
+    }
+
+    let mut deployment_log = DatabaseDeploymentLog {
+        id: 0,
+        deployment: deployment.id,
+        phase,
+        content,
+        truncated,
+        captured_at: get_time_i64(),
+    };
+    if let Err(e) = deployment_log.insert(database).await {
+        log::error!(
+            "Could not store {phase} output of deployment {id}: {e}",
+            id = deployment.id
+        );
+    }
+}
+
+pub async fn prune_logs(database: Database) {
+    let mut interval = time::interval(Duration::from_secs(60 * 60));
+
+    loop {
+        interval.tick().await;
+
+        match DatabaseDeploymentLog::delete_all_before(&database, get_time_i64() - logretention())
+            .await
+        {
+            Ok(deleted) => {
+                if deleted > 0 {
+                    log::info!("Pruned {deleted} deployment logs");
+                }
+            }
+            Err(e) => {
+                log::error!("Could not prune deployment logs: {e}");
+            }
+        }
+    }
+}
+
+/// Output of the phase a deployment is in, shared by all streams of the deployment
+#[derive(Clone, Default)]
+struct LiveOutput {
+    status: Option<DeploymentStatus>,
+    content: Arc<String>,
+}
+
+static LIVE_OUTPUTS: LazyLock<Mutex<HashMap<i32, watch::Receiver<LiveOutput>>>> =
+    LazyLock::new(Default::default);
+
+/// Live output of a deployment, a single task polls the worker for all subscribers
+fn subscribe(database: &Database, deployment: i32) -> watch::Receiver<LiveOutput> {
+    let mut outputs = LIVE_OUTPUTS.lock().unwrap_or_else(PoisonError::into_inner);
+    // Errors once the poller stopped
+    if let Some(output) = outputs.get(&deployment)
+        && output.has_changed().is_ok()
+    {
+        return output.clone();
+    }
+
+    let (sender, output) = watch::channel(LiveOutput::default());
+    outputs.insert(deployment, output.clone());
+    spawn(poll_live_output(database.clone(), deployment, sender));
+    output
+}
+
+/// Latest polled output of `status`, none when no stream is following the deployment
+pub fn latest_live_output(deployment: i32, status: DeploymentStatus) -> Option<Arc<String>> {
+    let outputs = LIVE_OUTPUTS.lock().unwrap_or_else(PoisonError::into_inner);
+    outputs
+        .get(&deployment)
+        .map(|output| output.borrow().clone())
+        .filter(|output| output.status == Some(status))
+        .map(|output| output.content)
+}
+
+/// Whether all streams of the deployment are gone, in which case the poller is removed
+fn unsubscribed(deployment: i32, sender: &watch::Sender<LiveOutput>) -> bool {
+    let mut outputs = LIVE_OUTPUTS.lock().unwrap_or_else(PoisonError::into_inner);
+    // One receiver is kept for new subscribers
+    if sender.receiver_count() > 1 {
+        return false;
+    }
+
+    outputs.remove(&deployment);
+    true
+}
+
+async fn poll_live_output(database: Database, deployment: i32, sender: watch::Sender<LiveOutput>) {
+    let mut interval = time::interval(Duration::from_secs(2));
+    let mut session: Option<(i32, Arc<Session>)> = None;
+    // Phase and server the imagegen log lines were read from
+    let mut source: Option<(DeploymentStatus, i32)> = None;
+    let mut lines: Vec<String> = vec![];
+
+    loop {
+        interval.tick().await;
+        if unsubscribed(deployment, &sender) {
+            return;
+        }
+
+        let deployment = match DatabaseDeployment::get_by_id(&database, deployment).await {
+            Ok(Some(deployment)) => deployment,
+            Ok(None) => {
+                continue;
+            }
+            Err(e) => {
+                log::error!("Could not get deployment {deployment} from the database: {e}");
+                continue;
+            }
+        };
+        if !matches!(
+            deployment.status,
+            DeploymentStatus::Coding | DeploymentStatus::Imagegen
+        ) {
+            continue;
+        }
+
+        let server = match DatabaseWorkerServer::get_by_assignment(&database, Some(deployment.id))
+            .await
+        {
+            Ok(Some(server)) => server,
+            Ok(None) => {
+                continue;
+            }
+            Err(e) => {
+                log::error!(
+                    "Could not get coding server assigned deployment {deployment} from the database: {e}",
+                    deployment = deployment.id
+                );
+                continue;
+            }
+        };
+        if session
+            .as_ref()
+            .is_none_or(|(server_id, _)| *server_id != server.id)
+        {
+            session = coding_server_session(&server)
+                .await
+                .map(|session| (server.id, session));
+        }
+        let Some((_, worker_session)) = &session else {
+            continue;
+        };
+
+        let content = match deployment.status {
+            // The file api has no ranged reads
+            DeploymentStatus::Coding => {
+                read_coder_chat(worker_session, &server, &deployment.project).await
+            }
+            _ => {
+                if source != Some((deployment.status, server.id)) {
+                    source = Some((deployment.status, server.id));
+                    lines.clear();
+                }
+                match read_imagegen_log_lines(worker_session, &server, &deployment.project, true)
+                    .await
+                {
+                    Some(tail) => {
+                        append_new_lines(&mut lines, tail);
+                        Some(lines.join("\n"))
+                    }
+                    None => None,
+                }
+            }
+        };
+        let Some(content) = content else {
+            // Logged in again next tick, e.g. the session expired on the worker
+            if let Some((server_id, session)) = session.take() {
+                invalidate_worker_session(server_id, &session).await;
+            }
+            continue;
+        };
+        sender.send_replace(LiveOutput {
+            status: Some(deployment.status),
+            content: Arc::new(content),
+        });
+    }
+}
+
+#[derive(Serialize)]
+struct OutputEvent<'a> {
+    status: DeploymentStatus,
+    offset: usize,
+    content: &'a str,
+}
+
+struct StreamState {
+    database: Database,
+    deployment: i32,
+    status: Option<DeploymentStatus>,
+    offset: usize,
+    output: watch::Receiver<LiveOutput>,
+    first: bool,
+    finished: bool,
+}
+
+fn event(event: &str, data: impl Serialize) -> String {
+    format!(
+        "event: {event}\ndata: {data}\n\n",
+        data = serde_json::to_string(&data).unwrap_or_default()
+    )
+}
+
+/// Server-Sent Events of a deployment: phase changes, new output (with byte offset into the phase output) and done once it is no longer in progress
+pub fn stream_deployment(
+    database: Database,
+    deployment: i32,
+    offset: usize,
+) -> impl Stream<Item = Result<Bytes, actix_web::Error>> {
+    let state = StreamState {
+        output: subscribe(&database, deployment),
+        database,
+        deployment,
+        status: None,
+        offset,
+        first: true,
+        finished: false,
+    };
+
+    stream::unfold(state, |mut state| async move {
+        if state.finished {
+            return None;
+        }
+
+        if !state.first {
+            sleep(Duration::from_secs(2)).await;
+        }
+        state.first = false;
+
+        let deployment =
+            match DatabaseDeployment::get_by_id(&state.database, state.deployment).await {
+                Ok(Some(deployment)) => deployment,
+                Ok(None) => {
+                    state.finished = true;
+                    return Some((Ok(Bytes::from(event("done", json!({})))), state));
+                }
+                Err(e) => {
+                    log::error!(
+                        "Could not get deployment {deployment} from the database: {e}",
+                        deployment = state.deployment
+                    );
+                    // Keep-alive comment, retry next tick
+                    return Some((Ok(Bytes::from(": retry\n\n")), state));
+                }
+            };
+
+        let mut events = String::new();
+        if state.status != Some(deployment.status) {
+            if state.status.is_some() {
+                // Every phase has its own output
+                state.offset = 0;
+            }
+            state.status = Some(deployment.status);
+            events.push_str(&event(
+                "phase",
+                json!({
+                    "status": deployment.status,
+                    "failure_reason": deployment.failure_reason,
+                }),
+            ));
+        }
+
+        if matches!(
+            deployment.status,
+            DeploymentStatus::Coding | DeploymentStatus::Imagegen
+        ) {
+            let output = state.output.borrow_and_update().clone();
+            if output.status == Some(deployment.status) {
+                let output = output.content;
+                if output.len() < state.offset {
+                    // Output was rewritten (e.g. a retry on another worker)
+                    state.offset = 0;
+                }
+                while !output.is_char_boundary(state.offset) {
+                    state.offset -= 1;
+                }
+                if output.len() > state.offset {
+                    events.push_str(&event(
+                        "output",
+                        OutputEvent {
+                            status: deployment.status,
+                            offset: state.offset,
+                            content: &output[state.offset..],
+                        },
+                    ));
+                    state.offset = output.len();
+                }
+            }
+        }
+
+        if !matches!(
+            deployment.status,
+            DeploymentStatus::Queued
+                | DeploymentStatus::Coding
+                | DeploymentStatus::ImagegenQueued
+                | DeploymentStatus::Imagegen
+                | DeploymentStatus::Deploying
+        ) {
+            state.finished = true;
+            events.push_str(&event("done", json!({ "status": deployment.status })));
+        }
+
+        if events.is_empty() {
+            events.push_str(": keep-alive\n\n");
+        }
+
+        Some((Ok(Bytes::from(events)), state))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::append_new_lines;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn appends_lines_after_the_overlap() {
+        let mut read = lines(&["a", "b", "c"]);
+        append_new_lines(&mut read, lines(&["b", "c", "d", "e"]));
+
+        assert_eq!(read, lines(&["a", "b", "c", "d", "e"]));
+    }
+
+    #[test]
+    fn unchanged_tail_appends_nothing() {
+        let mut read = lines(&["a", "b", "c"]);
+        append_new_lines(&mut read, lines(&["b", "c"]));
+
+        assert_eq!(read, lines(&["a", "b", "c"]));
+    }
+
+    #[test]
+    fn tail_without_overlap_is_appended_whole() {
+        let mut read = lines(&["a"]);
+        append_new_lines(&mut read, lines(&["x", "y"]));
+        assert_eq!(read, lines(&["a", "x", "y"]));
+
+        let mut read = vec![];
+        append_new_lines(&mut read, lines(&["x"]));
+        assert_eq!(read, lines(&["x"]));
+    }
+}
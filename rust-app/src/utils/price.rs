@@ -1,4 +1,17 @@
-use crate::database::{Database, projects::DatabaseProject};
+use sqlx::Error;
+
+use crate::{
+    database::{
+        Database, DatabaseTransaction,
+        credits::DatabaseCredits,
+        deployments::{DatabaseDeployment, DeploymentPriority},
+        projects::DatabaseProject,
+    },
+    utils::{
+        env::{changeprice, dailyfreechanges, expressprice, freechanges},
+        time::get_time_i64,
+    },
+};
 
 pub async fn get_price(database: &Database, user: &str) -> i64 {
     if let Ok(projects) = DatabaseProject::get_all_by_owner(database, user).await
@@ -11,3 +24,94 @@ pub async fn get_price(database: &Database, user: &str) -> i64 {
 
     20_000_000
 }
+
+/// Total charged for a change, a quote as the counts can change until the change is submitted
+pub async fn get_change_price(
+    database: &Database,
+    user: &str,
+    project: &str,
+    priority: DeploymentPriority,
+) -> i64 {
+    let project_count = DatabaseDeployment::get_billable_count_by_project(database, project)
+        .await
+        .unwrap_or(i64::MAX);
+    let account_count =
+        DatabaseDeployment::get_billable_count_by_account_since(database, user, free_since())
+            .await
+            .unwrap_or(i64::MAX);
+
+    base_change_price(project_count, account_count) + priority_price(priority)
+}
+
+/// Total charged for a change, counted in the transaction that inserts it so concurrent changes of the account can not use the same free change
+pub async fn get_change_price_in_transaction(
+    transaction: &mut DatabaseTransaction,
+    user: &str,
+    project: &str,
+    priority: DeploymentPriority,
+) -> Result<i64, Error> {
+    DatabaseCredits::lock_account_in_transaction(transaction, user).await?;
+    let project_count =
+        DatabaseDeployment::get_billable_count_by_project_in_transaction(transaction, project)
+            .await?;
+    let account_count = DatabaseDeployment::get_billable_count_by_account_since_in_transaction(
+        transaction,
+        user,
+        free_since(),
+    )
+    .await?;
+
+    Ok(base_change_price(project_count, account_count) + priority_price(priority))
+}
+
+fn priority_price(priority: DeploymentPriority) -> i64 {
+    match priority {
+        DeploymentPriority::Express => expressprice(),
+        DeploymentPriority::Standard => 0,
+    }
+}
+
+/// Start of the window the daily free changes are counted in
+fn free_since() -> i64 {
+    get_time_i64() - 24 * 60 * 60
+}
+
+fn base_change_price(project_count: i64, account_count: i64) -> i64 {
+    if project_count < freechanges() || account_count < dailyfreechanges() {
+        return 0;
+    }
+
+    changeprice()
+}
+
+pub async fn refund_deployment(database: &Database, deployment: &DatabaseDeployment) {
+    if deployment.credits == 0 {
+        return;
+    }
+
+    let account = match &deployment.account {
+        Some(account) => account.clone(),
+        None => {
+            log::error!(
+                "COULD NOT REFUND DEPLOYMENT {id}: NO ACCOUNT CHARGED",
+                id = deployment.id
+            );
+            return;
+        }
+    };
+
+    let credits = DatabaseCredits {
+        account,
+        credits: deployment.credits,
+        description: format!(
+            "Refund of {status} deployment {id} of project {project}",
+            status = deployment.status,
+            id = deployment.id,
+            project = deployment.project
+        ),
+        date: get_time_i64(),
+    };
+    if let Err(e) = credits.insert(database).await {
+        log::error!("COULD NOT INSERT REFUND CREDITS {credits:?}: {e}");
+    }
+}
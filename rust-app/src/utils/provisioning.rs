@@ -0,0 +1,511 @@
+use std::{sync::Arc, time::Duration};
+
+use serde::Serialize;
+use tokio::time;
+use xnode_manager_sdk::{
+    config::{RemoveInput, RemovePath},
+    request::{RequestIdResult, RequestInfoInput, RequestInfoPath},
+    utils::Session,
+};
+
+use crate::{
+    database::{
+        Database,
+        credits::DatabaseCredits,
+        miniapp_hosts::DatabaseMiniappHost,
+        projects::DatabaseProject,
+        provisionings::{DatabaseProvisioning, ProvisioningStatus},
+    },
+    source_host::{SourceHost, source_host},
+    utils::{
+        env::{exposetimeout, provisioningbudget},
+        sessions::{invalidate_host_session, miniapp_host, project_host_session},
+        time::get_time_i64,
+    },
+};
+
+/// Seconds before a failed step is attempted again, doubling with every failure
+const RETRY_BACKOFF: i64 = 5;
+const MAX_RETRY_BACKOFF: i64 = 300;
+
+pub async fn provision_projects(database: Database) {
+    let mut interval = time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let provisionings = match DatabaseProvisioning::get_all_unfinished(&database).await {
+            Ok(provisionings) => provisionings,
+            Err(e) => {
+                log::error!("Could not get unfinished provisionings: {e}");
+                continue;
+            }
+        };
+
+        let now = get_time_i64();
+        for mut provisioning in provisionings {
+            if provisioning.retry_at.is_some_and(|retry_at| now < retry_at) {
+                continue;
+            }
+
+            advance_provisioning(&database, &mut provisioning).await;
+        }
+    }
+}
+
+/// Executes the current provisioning step, rolling back the project if the step keeps failing for longer than the provisioning budget
+async fn advance_provisioning(database: &Database, provisioning: &mut DatabaseProvisioning) {
+    match provisioning_step(database, provisioning).await {
+        Ok(true) => {
+            let next = provisioning.status.next();
+            let finished_at = next.is_finished().then(get_time_i64);
+            if let Err(e) = provisioning
+                .update_status(database, next, finished_at)
+                .await
+            {
+                log::error!(
+                    "Could not update provisioning {id} to {next}: {e}",
+                    id = provisioning.id
+                );
+            }
+        }
+        Ok(false) => {
+            // Waiting on the mini app host
+        }
+        Err(error) => {
+            log::warn!(
+                "Provisioning step {status} of project {project} failed: {error}",
+                status = provisioning.status,
+                project = provisioning.project
+            );
+            let now = get_time_i64();
+            let retry_at = now + retry_backoff(provisioning.attempts + 1);
+            if let Err(e) = provisioning.update_error(database, error, retry_at).await {
+                log::error!(
+                    "Could not update provisioning {id} error: {e}",
+                    id = provisioning.id
+                );
+                return;
+            }
+
+            if provisioning
+                .failing_since
+                .is_some_and(|failing_since| now - failing_since > provisioningbudget())
+            {
+                rollback_provisioning(database, provisioning).await;
+            }
+        }
+    }
+}
+
+/// Returns whether the step is completed, steps that submit a request to the mini app host complete once the request succeeded
+async fn provisioning_step(
+    database: &Database,
+    provisioning: &mut DatabaseProvisioning,
+) -> Result<bool, String> {
+    match provisioning.status {
+        ProvisioningStatus::Repository => {
+            let source_host = source_host();
+            if source_host
+                .info(&provisioning.project)
+                .await
+                .map_err(|e| format!("Could not get repository info: {e}"))?
+                .is_some()
+            {
+                return Ok(true);
+            }
+
+            let project = DatabaseProject::get_by_name(database, &provisioning.project)
+                .await
+                .map_err(|e| format!("Could not get project from the database: {e}"))?
+                .ok_or("Project does not exist".to_string())?;
+            source_host
+                .create_from_template(&provisioning.project, &project.get_template().repository)
+                .await
+                .map_err(|e| format!("Could not create repository: {e}"))?;
+
+            Ok(true)
+        }
+        ProvisioningStatus::Expose => {
+            // The expose file is written by the host sync
+            let host = provisioning_host(database, provisioning).await?;
+            link_rebuild(database, provisioning, &host).await;
+            if host.is_listed(&provisioning.project) {
+                return Ok(true);
+            }
+
+            expose_deadline(provisioning.get_step_started_at())?;
+            Ok(false)
+        }
+        ProvisioningStatus::Rebuild => {
+            let host = provisioning_host(database, provisioning).await?;
+            link_rebuild(database, provisioning, &host).await;
+            if host.is_exposed(&provisioning.project) {
+                return Ok(true);
+            }
+
+            expose_deadline(provisioning.get_step_started_at())?;
+            Ok(false)
+        }
+        ProvisioningStatus::Container => {
+            let project = DatabaseProject::get_by_name(database, &provisioning.project)
+                .await
+                .map_err(|e| format!("Could not get project from the database: {e}"))?
+                .ok_or("Project does not exist".to_string())?;
+            let (host, session) = project_host_session(database, &project).await?;
+            if let Some(request) = provisioning.container_request {
+                let finished = request_finished(&session, request).await;
+                if finished.is_err()
+                    && let Err(e) = provisioning.update_container_request(database, None).await
+                {
+                    log::error!(
+                        "Could not clear container request of provisioning {id}: {e}",
+                        id = provisioning.id
+                    );
+                }
+                return finished.map_err(|e| format!("Mini app host container failed: {e}"));
+            }
+
+            let request_response =
+                match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
+                    session: &session,
+                    path: xnode_manager_sdk::config::SetPath {
+                        container: project.name.clone(),
+                    },
+                    data: xnode_manager_sdk::config::ContainerChange {
+                        settings: xnode_manager_sdk::config::ContainerSettings {
+                            flake: project.get_flake(),
+                            network: project.get_network(),
+                            nvidia_gpus: None,
+                        },
+                        update_inputs: None,
+                    },
+                })
+                .await
+                {
+                    Ok(request_response) => request_response,
+                    Err(e) => {
+                        invalidate_host_session(host.id, &session).await;
+                        return Err(format!("Could not update mini app host project: {e:?}"));
+                    }
+                };
+            provisioning
+                .update_container_request(database, Some(request_response.request_id.into()))
+                .await
+                .map_err(|e| format!("Could not store container request: {e}"))?;
+
+            Ok(false)
+        }
+        ProvisioningStatus::Ready | ProvisioningStatus::Failed => Ok(true),
+    }
+}
+
+/// Seconds to wait before attempting a step again after `failures` failed attempts in a row
+fn retry_backoff(failures: i32) -> i64 {
+    (RETRY_BACKOFF << (failures - 1).clamp(0, 16)).min(MAX_RETRY_BACKOFF)
+}
+
+async fn rollback_provisioning(database: &Database, provisioning: &mut DatabaseProvisioning) {
+    log::error!(
+        "Rolling back provisioning of project {project} at step {status}: {error:?}",
+        project = provisioning.project,
+        status = provisioning.status,
+        error = provisioning.error
+    );
+
+    // Marked as failed first, so a crash during cleanup can never refund twice
+    let reached = provisioning.status;
+    if let Err(e) = provisioning.update_failed(database, get_time_i64()).await {
+        log::error!(
+            "Could not mark provisioning {id} as failed: {e}",
+            id = provisioning.id
+        );
+        return;
+    }
+
+    if provisioning.credits != 0 {
+        let credits = DatabaseCredits {
+            account: provisioning.account.clone(),
+            credits: provisioning.credits,
+            description: format!(
+                "Refund of create project {project}",
+                project = provisioning.project
+            ),
+            date: get_time_i64(),
+        };
+        if let Err(e) = credits.insert(database).await {
+            log::error!("COULD NOT INSERT REFUND CREDITS {credits:?}: {e}");
+        }
+    }
+
+    // The request might have been applied even when following it failed
+    if reached == ProvisioningStatus::Container
+        && let Err(e) = remove_container(database, provisioning).await
+    {
+        log::error!(
+            "Could not remove container of project {project}: {e}",
+            project = provisioning.project
+        );
+    }
+
+    if reached != ProvisioningStatus::Repository
+        && let Err(e) = source_host().delete(&provisioning.project).await
+    {
+        log::error!(
+            "Could not delete repository {project}: {e}",
+            project = provisioning.project
+        );
+    }
+
+    // The host sync removes the deleted project from the expose file of its host
+    match DatabaseProject::get_by_name(database, &provisioning.project).await {
+        Ok(project) => {
+            if let Some(project) = project
+                && let Err(e) = project.delete(database).await
+            {
+                log::error!(
+                    "Could not delete project {project} from the database: {e}",
+                    project = provisioning.project
+                );
+            }
+        }
+        Err(e) => {
+            log::error!(
+                "Could not get project {project} from the database: {e}",
+                project = provisioning.project
+            );
+        }
+    }
+}
+
+/// Remove the container of the project from its mini app host, must happen before the project is deleted
+async fn remove_container(
+    database: &Database,
+    provisioning: &DatabaseProvisioning,
+) -> Result<(), String> {
+    let (host, session) = provisioning_host_session(database, provisioning).await?;
+    if let Err(e) = xnode_manager_sdk::config::remove(RemoveInput::new_with_path(
+        &session,
+        RemovePath {
+            container: provisioning.project.clone(),
+        },
+    ))
+    .await
+    {
+        invalidate_host_session(host.id, &session).await;
+        return Err(format!("{e:?}"));
+    }
+
+    Ok(())
+}
+
+/// Fails a step waiting on the host sync once it takes longer than the expose timeout, e.g. because the os rebuild of the host keeps failing
+pub fn expose_deadline(step_started_at: i64) -> Result<(), String> {
+    let timeout = exposetimeout();
+    if get_time_i64() - step_started_at > timeout {
+        return Err(format!(
+            "Mini app host did not expose the project within {timeout} seconds"
+        ));
+    }
+
+    Ok(())
+}
+
+/// Remember the os rebuild of the host that applies the project, so the status report can follow it
+async fn link_rebuild(
+    database: &Database,
+    provisioning: &mut DatabaseProvisioning,
+    host: &DatabaseMiniappHost,
+) {
+    let request = match host.rebuilding(&provisioning.project) {
+        Some(request) => request,
+        None => {
+            return;
+        }
+    };
+    if provisioning.rebuild_request == Some(request) {
+        return;
+    }
+
+    if let Err(e) = provisioning
+        .update_rebuild_request(database, Some(request))
+        .await
+    {
+        log::error!(
+            "Could not store rebuild request of provisioning {id}: {e}",
+            id = provisioning.id
+        );
+    }
+}
+
+/// Mini app host the provisioned project is placed on
+async fn provisioning_host(
+    database: &Database,
+    provisioning: &DatabaseProvisioning,
+) -> Result<DatabaseMiniappHost, String> {
+    let project = DatabaseProject::get_by_name(database, &provisioning.project)
+        .await
+        .map_err(|e| format!("Could not get project from the database: {e}"))?
+        .ok_or("Project does not exist".to_string())?;
+    let id = project
+        .miniapp_host
+        .ok_or("Project is not placed on a mini app host".to_string())?;
+
+    miniapp_host(database, id).await
+}
+
+async fn provisioning_host_session(
+    database: &Database,
+    provisioning: &DatabaseProvisioning,
+) -> Result<(DatabaseMiniappHost, Arc<Session>), String> {
+    let project = DatabaseProject::get_by_name(database, &provisioning.project)
+        .await
+        .map_err(|e| format!("Could not get project from the database: {e}"))?
+        .ok_or("Project does not exist".to_string())?;
+
+    project_host_session(database, &project).await
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case", tag = "status")]
+pub enum RequestResult {
+    Pending,
+    Success,
+    Error { error: String },
+    Unknown { error: String },
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum StepState {
+    Pending,
+    Running,
+    Done,
+    Failed,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProvisioningStep {
+    pub step: ProvisioningStatus,
+    pub state: StepState,
+    pub request_id: Option<i64>,
+    pub request_result: Option<RequestResult>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ProvisioningReport {
+    pub id: i32,
+    pub project: String,
+    pub status: ProvisioningStatus,
+    pub attempts: i32,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub finished_at: Option<i64>,
+    pub steps: Vec<ProvisioningStep>,
+}
+
+pub async fn provisioning_report(
+    database: &Database,
+    provisioning: DatabaseProvisioning,
+) -> ProvisioningReport {
+    let session =
+        if provisioning.rebuild_request.is_some() || provisioning.container_request.is_some() {
+            provisioning_host_session(database, &provisioning)
+                .await
+                .map(|(_, session)| session)
+                .map_err(|e| {
+                    log::error!("{e}");
+                    e
+                })
+        } else {
+            Err("No mini app host requests".to_string())
+        };
+
+    // The step the provisioning is currently at (or failed at)
+    let current = provisioning.failed_step.unwrap_or(provisioning.status);
+    let mut steps = vec![];
+    let mut step = ProvisioningStatus::Repository;
+    while step != ProvisioningStatus::Ready {
+        let state = if provisioning.status == ProvisioningStatus::Ready || step < current {
+            StepState::Done
+        } else if step == current {
+            if provisioning.status == ProvisioningStatus::Failed {
+                StepState::Failed
+            } else {
+                StepState::Running
+            }
+        } else {
+            StepState::Pending
+        };
+
+        let request_id = match step {
+            ProvisioningStatus::Rebuild => provisioning.rebuild_request,
+            ProvisioningStatus::Container => provisioning.container_request,
+            _ => None,
+        };
+        let request_result = match (request_id, &session) {
+            (Some(request), Ok(session)) => Some(match request_finished(session, request).await {
+                Ok(true) => RequestResult::Success,
+                Ok(false) => RequestResult::Pending,
+                Err(error) => RequestResult::Error { error },
+            }),
+            (Some(_), Err(error)) => Some(RequestResult::Unknown {
+                error: error.clone(),
+            }),
+            (None, _) => None,
+        };
+
+        steps.push(ProvisioningStep {
+            step,
+            state,
+            request_id,
+            request_result,
+        });
+        step = step.next();
+    }
+
+    ProvisioningReport {
+        id: provisioning.id,
+        project: provisioning.project,
+        status: provisioning.status,
+        attempts: provisioning.attempts,
+        error: provisioning.error,
+        created_at: provisioning.created_at,
+        finished_at: provisioning.finished_at,
+        steps,
+    }
+}
+
+/// Returns whether the mini app host request succeeded, or the error it failed with
+pub async fn request_finished(session: &Session, request: i64) -> Result<bool, String> {
+    let request_id = request
+        .try_into()
+        .map_err(|e| format!("Could not convert request id from i64 to u32: {e}"))?;
+
+    match xnode_manager_sdk::request::request_info(RequestInfoInput::new_with_path(
+        session,
+        RequestInfoPath { request_id },
+    ))
+    .await
+    {
+        Ok(request_info) => match request_info.result {
+            Some(RequestIdResult::Success { body: _ }) => Ok(true),
+            Some(RequestIdResult::Error { error }) => Err(error),
+            None => Ok(false),
+        },
+        Err(e) => Err(format!("Could not get request info of {request_id}: {e:?}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MAX_RETRY_BACKOFF, RETRY_BACKOFF, retry_backoff};
+
+    #[test]
+    fn retry_backoff_doubles_up_to_the_max() {
+        assert_eq!(retry_backoff(1), RETRY_BACKOFF);
+        assert_eq!(retry_backoff(2), RETRY_BACKOFF * 2);
+        assert_eq!(retry_backoff(3), RETRY_BACKOFF * 4);
+        assert_eq!(retry_backoff(100), MAX_RETRY_BACKOFF);
+    }
+}
@@ -1,14 +1,9 @@
-use std::{fs::read, time::Duration};
+use std::{fs::read, sync::Arc, time::Duration};
 
-use hex::ToHex;
-use rand::{Rng, distr::Alphanumeric};
+use futures_util::{StreamExt, stream};
 use serde::{Deserialize, Serialize};
-use sqlx::types::Json;
+use sqlx::{Error, types::Json};
 use tokio::time;
-use xnode_deployer::{
-    DeployInput, OptionalSupport, XnodeDeployer,
-    hyperstack::{HyperstackDeployer, HyperstackHardware},
-};
 use xnode_manager_sdk::{
     config::{ContainerChange, ContainerSettings, SetInput, SetPath},
     file::{
@@ -19,21 +14,38 @@ use xnode_manager_sdk::{
     info::{GroupsInput, GroupsPath, UsersInput, UsersPath},
     process::{ExecuteInput, ExecutePath, ListInput, ListPath, ProcessCommand},
     request::{RequestIdResult, RequestInfoInput, RequestInfoPath},
+    utils::Session,
 };
 
 use crate::{
     database::{
-        Database, deployments::DatabaseDeployment, projects::DatabaseProject,
-        worker_servers::DatabaseWorkerServer,
+        Database,
+        deployments::{DatabaseDeployment, DeploymentStatus, ImagegenMode},
+        projects::DatabaseProject,
+        worker_servers::{DatabaseWorkerServer, WorkerRole},
     },
     utils::{
-        auth::get_session,
-        env::{datadir, hyperstackapikey},
+        autoscaler::autoscale,
+        env::{datadir, flavorprices, workerparallelism},
+        output::capture_output,
+        price::refund_deployment,
+        scheduler::next_deployment,
+        sessions::{
+            forget_worker, invalidate_host_session, invalidate_worker_session,
+            project_host_session, worker_session,
+        },
         time::get_time_i64,
-        wallet::get_signer,
     },
+    worker_provider::{WorkerProvider, provider_of, worker_provider},
 };
 
+/// Postgres notification channel for queued deployments and available workers
+const DISPATCH_CHANNEL: &str = "dispatch";
+/// How long to wait for a worker to stop its processes before releasing it anyway
+const STOP_TIMEOUT: Duration = Duration::from_secs(60);
+/// Seconds a worker is not claimed after an assignment could not be started on it
+const CLAIM_BACKOFF: i64 = 120;
+
 #[derive(Serialize, Debug)]
 pub struct CoderAssignment {
     project: String,
@@ -44,6 +56,8 @@ pub struct CoderAssignment {
 #[derive(Deserialize, Debug)]
 pub struct CoderOutput {
     git_hash: String,
+    /// Whether the change needs (re)generated images, assumed when the coder does not report it
+    images_changed: Option<bool>,
 }
 
 #[derive(Serialize, Debug)]
@@ -56,25 +70,6 @@ pub struct ImagegenOutput {
     git_hash: String,
 }
 
-pub fn new_deployer() -> HyperstackDeployer {
-    HyperstackDeployer::new(
-        hyperstackapikey(),
-        HyperstackHardware::VirtualMachine {
-            name: format!(
-                "miniapp-factory-coder-{random}",
-                random = rand::rng()
-                    .sample_iter(&Alphanumeric)
-                    .take(10)
-                    .map(char::from)
-                    .collect::<String>()
-            ),
-            environment_name: "default-NORWAY-1".to_string(),
-            flavor_name: "n3-RTX-A4000x1".to_string(),
-            key_name: "NixOS".to_string(),
-        },
-    )
-}
-
 pub async fn manage_coding_servers(database: Database) {
     let mut interval = time::interval(Duration::from_secs(10));
 
@@ -83,175 +78,251 @@ pub async fn manage_coding_servers(database: Database) {
 
         match DatabaseWorkerServer::get_all_no_setup_finished(&database).await {
             Ok(servers) => {
-                for mut server in servers {
-                    let session = match coding_server_session(&server).await {
-                        Some(session) => session,
-                        None => {
-                            continue;
-                        }
-                    };
-
-                    if let Some(request) = server.imagegen_deployment {
-                        let request_id = match request.try_into() {
-                            Ok(request_id) => request_id,
-                            Err(e) => {
-                                log::error!("Could not convert request id from i64 to u32: {e}");
-                                continue;
-                            }
-                        };
-
-                        match xnode_manager_sdk::request::request_info(
-                            RequestInfoInput::new_with_path(
-                                &session,
-                                RequestInfoPath { request_id },
-                            ),
-                        )
-                        .await
-                        {
-                            Ok(request_info) => {
-                                if request_info.result.is_some_and(|result| {
-                                    matches!(result, RequestIdResult::Success { body: _ })
-                                }) {
-                                    finalize_setup(&database, &mut server, &session).await;
-                                }
-                            }
-                            Err(e) => {
-                                log::error!(
-                                    "Could not get imagegen deployment request info on server {server}: {e:?}",
-                                    server = server.id
-                                );
-                            }
-                        };
-                    } else if let Some(request) = server.coder_deployment {
-                        let request_id = match request.try_into() {
-                            Ok(request_id) => request_id,
-                            Err(e) => {
-                                log::error!("Could not convert request id from i64 to u32: {e}");
-                                continue;
-                            }
-                        };
-
-                        match xnode_manager_sdk::request::request_info(
-                            RequestInfoInput::new_with_path(
-                                &session,
-                                RequestInfoPath { request_id },
-                            ),
-                        )
-                        .await
-                        {
-                            Ok(request_info) => {
-                                if request_info.result.is_some_and(|result| {
-                                    matches!(result, RequestIdResult::Success { body: _ })
-                                }) {
-                                    deploy_imagegen_app(&database, &mut server, &session).await;
-                                }
-                            }
-                            Err(e) => {
-                                log::error!(
-                                    "Could not get coder deployment request info on server {server}: {e:?}",
-                                    server = server.id
-                                );
-                            }
-                        };
-                    } else {
-                        deploy_coder_app(&database, &mut server, &session).await;
-                    }
-                }
+                stream::iter(servers)
+                    .for_each_concurrent(workerparallelism(), |server| {
+                        setup_server(&database, server)
+                    })
+                    .await;
             }
             Err(e) => {
                 log::error!("Could not get no setup finished coding servers: {e}");
             }
         }
 
-        match DatabaseDeployment::get_queued_count(&database).await {
-            Ok(queued) => {
-                if queued == 0 {
-                    // undeploy all servers that aren't assigned anything
-                    match DatabaseWorkerServer::get_all_dynamic_unassigned(&database).await {
-                        Ok(servers) => {
-                            for server in servers {
-                                if let Err(e) = new_deployer()
-                                    .undeploy(server.hardware.as_ref().clone())
-                                    .await
-                                {
-                                    log::error!(
-                                        "Error undeploying coding server {server}: {e:?}",
-                                        server = server.id
-                                    );
-                                    continue;
-                                }
-
-                                let id = server.id;
-                                if let Err(e) = server.delete(&database).await {
-                                    log::error!(
-                                        "Could not remove coding server {server} from database: {e}",
-                                        server = id
-                                    );
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Could not get dynamic unassigned coding servers: {e}");
-                        }
-                    }
-                } else {
-                    // deploy more servers if exceeds 3*current servers
-                    match DatabaseWorkerServer::get_count(&database).await {
-                        Ok(coding_servers) => {
-                            let extra_servers = ((2 + queued) / 3) - (coding_servers);
-                            if extra_servers > 0 {
-                                let addr: String = get_signer().public().address().encode_hex();
-                                for _ in 0..extra_servers {
-                                    deploy_server(&database, format!("eth:{addr}")).await;
-                                }
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Could not get coding servers count: {e}");
-                        }
+        autoscale(&database).await;
+    }
+}
+
+/// Advance the setup of a server that did not finish setup yet
+async fn setup_server(database: &Database, mut server: DatabaseWorkerServer) {
+    let session = match coding_server_session(&server).await {
+        Some(session) => session,
+        None => {
+            return;
+        }
+    };
+
+    if let Some(request) = server.imagegen_deployment {
+        let request_id = match request.try_into() {
+            Ok(request_id) => request_id,
+            Err(e) => {
+                log::error!("Could not convert request id from i64 to u32: {e}");
+                return;
+            }
+        };
+
+        match xnode_manager_sdk::request::request_info(RequestInfoInput::new_with_path(
+            &session,
+            RequestInfoPath { request_id },
+        ))
+        .await
+        {
+            Ok(request_info) => {
+                if request_info
+                    .result
+                    .is_some_and(|result| matches!(result, RequestIdResult::Success { body: _ }))
+                {
+                    finalize_setup(database, &mut server, &session).await;
+                }
+            }
+            Err(e) => {
+                invalidate_worker_session(server.id, &session).await;
+                log::error!(
+                    "Could not get imagegen deployment request info on server {server}: {e:?}",
+                    server = server.id
+                );
+            }
+        };
+    } else if let Some(request) = server.coder_deployment {
+        let request_id = match request.try_into() {
+            Ok(request_id) => request_id,
+            Err(e) => {
+                log::error!("Could not convert request id from i64 to u32: {e}");
+                return;
+            }
+        };
+
+        match xnode_manager_sdk::request::request_info(RequestInfoInput::new_with_path(
+            &session,
+            RequestInfoPath { request_id },
+        ))
+        .await
+        {
+            Ok(request_info) => {
+                if request_info
+                    .result
+                    .is_some_and(|result| matches!(result, RequestIdResult::Success { body: _ }))
+                {
+                    if server.role.is_imagegen() {
+                        deploy_imagegen_app(database, &mut server, &session).await;
+                    } else {
+                        finalize_setup(database, &mut server, &session).await;
                     }
                 }
             }
             Err(e) => {
-                log::error!("Could not get deployments unfinished count: {e}");
+                invalidate_worker_session(server.id, &session).await;
+                log::error!(
+                    "Could not get coder deployment request info on server {server}: {e:?}",
+                    server = server.id
+                );
             }
-        }
+        };
+    } else if server.role.is_coder() {
+        deploy_coder_app(database, &mut server, &session).await;
+    } else {
+        deploy_imagegen_app(database, &mut server, &session).await;
     }
 }
 
 pub async fn execute_pending_deployments(database: Database) {
-    let mut interval = time::interval(Duration::from_millis(500));
+    let mut listener = match database.listen(DISPATCH_CHANNEL).await {
+        Ok(listener) => Some(listener),
+        Err(e) => {
+            log::error!(
+                "Could not listen for dispatch notifications, falling back to polling: {e}"
+            );
+            None
+        }
+    };
 
     loop {
-        interval.tick().await;
+        dispatch(&database).await;
+
+        // Wake up when work is queued or a worker becomes available, polling covers missed notifications
+        match &mut listener {
+            Some(listener) => {
+                if let Ok(Err(e)) = time::timeout(Duration::from_secs(5), listener.recv()).await {
+                    log::error!("Could not receive dispatch notification: {e}");
+                    time::sleep(Duration::from_millis(500)).await;
+                }
+                while listener.next_buffered().is_some() {}
+            }
+            None => {
+                time::sleep(Duration::from_millis(500)).await;
+            }
+        }
+    }
+}
+
+/// Claim available workers for the scheduled deployments and start the claimed assignments concurrently
+///
+/// Claiming is race-free between factory instances: workers locked by another instance are skipped and the deployment status only changes if it is still queued.
+async fn dispatch(database: &Database) {
+    let mut claims = vec![];
 
-        let deployment = match DatabaseDeployment::get_next_unfinished(&database).await {
-            Ok(deployment) => deployment,
+    for _ in 0..workerparallelism() {
+        let mut deployment = match next_deployment(database).await {
+            Ok(Some(deployment)) => deployment,
+            Ok(None) => break,
             Err(e) => {
-                log::error!("Could not get next unfinished deployment: {e}");
-                continue;
+                log::error!("Could not get next scheduled deployment: {e}");
+                break;
             }
         };
+        match claim(
+            database,
+            &mut deployment,
+            WorkerRole::Coder,
+            DeploymentStatus::Coding,
+        )
+        .await
+        {
+            Some(Some(server)) => claims.push((deployment, server)),
+            Some(None) => {}
+            None => break,
+        }
+    }
 
-        if let Some(mut deployment) = deployment {
-            let mut server = match DatabaseWorkerServer::get_available(&database).await {
-                Ok(server) => match server {
-                    Some(server) => server,
-                    None => {
-                        continue;
-                    }
-                },
-                Err(e) => {
-                    log::error!("Could not get next available coding server: {e}");
-                    continue;
+    for _ in 0..workerparallelism() {
+        let mut deployment = match DatabaseDeployment::get_next_imagegen_queued(database).await {
+            Ok(Some(deployment)) => deployment,
+            Ok(None) => break,
+            Err(e) => {
+                log::error!("Could not get next imagegen queued deployment: {e}");
+                break;
+            }
+        };
+        match claim(
+            database,
+            &mut deployment,
+            WorkerRole::Imagegen,
+            DeploymentStatus::Imagegen,
+        )
+        .await
+        {
+            Some(Some(server)) => claims.push((deployment, server)),
+            Some(None) => {}
+            None => break,
+        }
+    }
+
+    stream::iter(claims)
+        .for_each_concurrent(
+            workerparallelism(),
+            |(mut deployment, mut server)| async move {
+                if deployment.status == DeploymentStatus::Coding {
+                    coding_assignment(database, &mut deployment, &mut server).await;
+                } else {
+                    imagegen_assignment(database, &mut deployment, &mut server).await;
                 }
-            };
+            },
+        )
+        .await;
+}
 
-            coding_assignment(&database, &mut deployment, &mut server).await;
+/// Claim a worker of `role` and move the deployment to `status`
+///
+/// None when no worker is available, Some(None) when the deployment was claimed by someone else in the meantime.
+async fn claim(
+    database: &Database,
+    deployment: &mut DatabaseDeployment,
+    role: WorkerRole,
+    status: DeploymentStatus,
+) -> Option<Option<DatabaseWorkerServer>> {
+    match DatabaseWorkerServer::claim_available(database, role, deployment, status).await {
+        Ok(Some(server)) => Some(Some(server)),
+        Ok(None) => None,
+        Err(Error::RowNotFound) => Some(None),
+        Err(e) => {
+            log::error!(
+                "Could not claim {role} server for deployment {id}: {e}",
+                id = deployment.id
+            );
+            None
         }
     }
 }
 
+/// Put a claimed deployment back in its queue and free the worker, for assignments that could not be started
+async fn unclaim(
+    database: &Database,
+    deployment: &mut DatabaseDeployment,
+    server: &mut DatabaseWorkerServer,
+) {
+    let status = match deployment.status {
+        DeploymentStatus::Imagegen => DeploymentStatus::ImagegenQueued,
+        _ => DeploymentStatus::Queued,
+    };
+    if let Err(e) = deployment.update_status(database, status, None).await {
+        log::error!(
+            "Could not put deployment {id} back as {status}: {e}",
+            id = deployment.id
+        );
+    }
+    // Keeps the worker from being claimed again right away
+    if let Err(e) = server
+        .update_claim_backoff_until(database, Some(get_time_i64() + CLAIM_BACKOFF))
+        .await
+    {
+        log::error!(
+            "Could not back off claims of coding server {server}: {e}",
+            server = server.id
+        );
+    }
+    release_server(database, server).await;
+}
+
 pub async fn finish_deployment(database: Database) {
     let mut interval = time::interval(Duration::from_secs(2));
 
@@ -266,391 +337,777 @@ pub async fn finish_deployment(database: Database) {
             }
         };
 
-        for mut server in servers {
-            let deployment_id = match server.assignment {
-                Some(deployment_id) => deployment_id,
-                None => {
-                    log::error!(
-                        "Server {server} has no deployment anymore.",
-                        server = server.id
-                    );
-                    continue;
-                }
-            };
-            let deployment = match DatabaseDeployment::get_by_id(&database, deployment_id).await {
-                Ok(deployment) => deployment,
-                Err(e) => {
-                    log::error!("Error getting deployment by id {deployment_id}: {e}");
-                    continue;
-                }
-            };
-            let mut deployment = match deployment {
-                Some(deployment) => deployment,
-                None => {
-                    log::error!("Deployment with id {deployment_id} not found");
-                    continue;
-                }
-            };
+        stream::iter(servers)
+            .for_each_concurrent(workerparallelism(), |server| {
+                finish_server(&database, server)
+            })
+            .await;
 
-            let session = match coding_server_session(&server).await {
-                Some(session) => session,
-                None => {
-                    continue;
-                }
-            };
+        follow_rollouts(&database).await;
+    }
+}
 
-            if deployment.coding_finished_at.is_none() {
-                let processes = match xnode_manager_sdk::process::list(ListInput::new_with_path(
-                    &session,
-                    ListPath {
-                        scope: "container:miniapp-factory-coder".to_string(),
-                    },
-                ))
-                .await
-                {
-                    Ok(processes) => processes,
-                    Err(e) => {
-                        log::error!(
-                            "Could not get coder process list of coding server {server}: {e:?}",
-                            server = server.id
-                        );
-                        continue;
-                    }
-                };
-                if processes
-                    .iter()
-                    .any(|process| process.name == "miniapp-factory-coder.service")
-                {
-                    // Still running
-                    continue;
+/// Follow the mini app host requests of deployments until they succeed or fail, deploying deployments go live or fail with them
+async fn follow_rollouts(database: &Database) {
+    match DatabaseDeployment::get_all_by_status(database, DeploymentStatus::Deploying).await {
+        Ok(deployments) => {
+            for mut deployment in deployments {
+                if deployment.deployment_request.is_none() {
+                    fail_deployment(
+                        database,
+                        &mut deployment,
+                        "Deployment has no mini app host request".to_string(),
+                    )
+                    .await;
                 }
+            }
+        }
+        Err(e) => {
+            log::error!("Could not get all deploying deployments: {e}");
+        }
+    }
 
-                let output = match xnode_manager_sdk::file::read_file(ReadFileInput {
-                    session: &session,
-                    path: ReadFilePath {
-                        scope: "container:miniapp-factory-coder".to_string(),
-                    },
-                    query: ReadFile {
-                        path: "/var/lib/miniapp-factory-coder/assignment.json".to_string(),
-                    },
-                })
-                .await
-                {
-                    Ok(output) => output,
-                    Err(e) => {
-                        log::error!(
-                            "Could not get coding server {server} coder output file content: {e:?}",
-                            server = server.id
-                        );
-                        continue;
-                    }
-                };
-                let output = match output.content {
-                    xnode_manager_sdk::utils::Output::UTF8 { output } => output,
-                    xnode_manager_sdk::utils::Output::Bytes { output: _ } => {
-                        log::error!("Output file content is not in UTF8");
-                        continue;
-                    }
-                };
-                let output = match serde_json::from_str::<CoderOutput>(&output) {
-                    Ok(output) => output,
-                    Err(e) => {
-                        log::error!(
-                            "Could not convert coding server {server} coder {output} to output struct: {e}",
-                            server = server.id
-                        );
-                        continue;
-                    }
-                };
+    let deployments = match DatabaseDeployment::get_all_rolling_out(database).await {
+        Ok(deployments) => deployments,
+        Err(e) => {
+            log::error!("Could not get all rolling out deployments: {e}");
+            return;
+        }
+    };
 
-                let coding_finished_at = get_time_i64();
-                if let Err(e) = deployment
-                    .update_coding_finished_at(&database, Some(coding_finished_at))
-                    .await
-                {
-                    log::error!(
-                        "Could not set coding finished at to {coding_finished_at} for deployment {id}: {e}",
-                        id = deployment.id
-                    );
-                };
-                log::info!(
-                    "Finished processing deployment {id} coding at {coding_finished_at}",
-                    id = deployment.id
+    for mut deployment in deployments {
+        let request_id = match deployment
+            .deployment_request
+            .map(|request| request.try_into())
+        {
+            Some(Ok(request_id)) => request_id,
+            Some(Err(e)) => {
+                log::error!("Could not convert request id from i64 to u32: {e}");
+                continue;
+            }
+            None => {
+                continue;
+            }
+        };
+
+        let project = match DatabaseProject::get_by_name(database, &deployment.project).await {
+            Ok(Some(project)) => project,
+            Ok(None) => {
+                finish_rollout(
+                    database,
+                    &mut deployment,
+                    Some(format!(
+                        "Project {project} does not exist",
+                        project = deployment.project
+                    )),
+                )
+                .await;
+                continue;
+            }
+            Err(e) => {
+                log::error!(
+                    "Could not get project {project} from the database: {e}",
+                    project = deployment.project
                 );
+                continue;
+            }
+        };
+        let (host, session) = match project_host_session(database, &project).await {
+            Ok(host_session) => host_session,
+            Err(e) => {
+                log::error!("{e}");
+                continue;
+            }
+        };
 
-                if let Err(e) = deployment
-                    .update_coding_git_hash(&database, Some(output.git_hash.clone()))
-                    .await
-                {
+        match xnode_manager_sdk::request::request_info(RequestInfoInput::new_with_path(
+            &session,
+            RequestInfoPath { request_id },
+        ))
+        .await
+        {
+            Ok(request_info) => match request_info.result {
+                Some(RequestIdResult::Success { body: _ }) => {
+                    finish_rollout(database, &mut deployment, None).await;
+                }
+                Some(RequestIdResult::Error { error }) => {
+                    finish_rollout(database, &mut deployment, Some(error)).await;
+                }
+                None => {
+                    // Still rolling out
+                }
+            },
+            Err(e) => {
+                invalidate_host_session(host.id, &session).await;
+                if deployment.status == DeploymentStatus::Deploying {
                     log::error!(
-                        "Could not set coding git hash to {git_hash} for deployment {id}: {e}",
-                        git_hash = output.git_hash,
+                        "Could not get mini app host request info of deployment {id}: {e:?}",
                         id = deployment.id
                     );
-                };
-
-                // Clear RAM
-                if let Err(e) = xnode_manager_sdk::process::execute(ExecuteInput {
-                    session: &session,
-                    path: ExecutePath {
-                        process: "ollama.service".to_string(),
-                        scope: "container:miniapp-factory-coder".to_string(),
-                    },
-                    data: ProcessCommand::Restart,
-                })
-                .await
-                {
-                    log::error!(
-                        "Could not restart coder process ollama on server {server}: {e:?}",
-                        server = server.id
-                    );
+                } else {
+                    // Nothing is waiting on the outcome anymore (e.g. the rollout timed out), the request might be gone
+                    finish_rollout(
+                        database,
+                        &mut deployment,
+                        Some(format!("Rollout outcome unknown: {e:?}")),
+                    )
+                    .await;
                 }
-
-                imagegen_assignment(&database, &mut deployment, &mut server).await;
             }
+        }
+    }
+}
 
-            if deployment.imagegen_finished_at.is_none() {
-                let processes = match xnode_manager_sdk::process::list(ListInput::new_with_path(
-                    &session,
-                    ListPath {
-                        scope: "container:miniapp-factory-imagegen".to_string(),
-                    },
-                ))
-                .await
-                {
-                    Ok(processes) => processes,
-                    Err(e) => {
-                        log::error!(
-                            "Could not get imagegen process list of coding server {server}: {e:?}",
-                            server = server.id
-                        );
-                        continue;
-                    }
-                };
-                if processes
-                    .iter()
-                    .any(|process| process.name == "miniapp-factory-imagegen.service")
-                {
-                    // Still running
-                    continue;
-                }
+/// Record the outcome of the mini app host request of a deployment
+async fn finish_rollout(
+    database: &Database,
+    deployment: &mut DatabaseDeployment,
+    error: Option<String>,
+) {
+    if let Err(e) = deployment
+        .update_rollout(database, get_time_i64(), error.clone())
+        .await
+    {
+        log::error!(
+            "Could not record rollout outcome of deployment {id}: {e}",
+            id = deployment.id
+        );
+        return;
+    }
 
-                let output = match xnode_manager_sdk::file::read_file(ReadFileInput {
-                    session: &session,
-                    path: ReadFilePath {
-                        scope: "container:miniapp-factory-imagegen".to_string(),
-                    },
-                    query: ReadFile {
-                        path: "/var/lib/miniapp-factory-imagegen/assignment.json".to_string(),
-                    },
-                })
-                .await
-                {
-                    Ok(output) => output,
-                    Err(e) => {
-                        log::error!(
-                            "Could not get coding server {server} imagegen output file content: {e:?}",
-                            server = server.id
-                        );
-                        continue;
-                    }
-                };
-                let output = match output.content {
-                    xnode_manager_sdk::utils::Output::UTF8 { output } => output,
-                    xnode_manager_sdk::utils::Output::Bytes { output: _ } => {
-                        log::error!("Output file content is not in UTF8");
-                        continue;
-                    }
-                };
-                let output = match serde_json::from_str::<ImagegenOutput>(&output) {
-                    Ok(output) => output,
-                    Err(e) => {
-                        log::error!(
-                            "Could not convert coding server {server} imagegen {output} to output struct: {e}",
-                            server = server.id
-                        );
-                        continue;
-                    }
-                };
+    if deployment.status != DeploymentStatus::Deploying {
+        if let Some(error) = error {
+            log::warn!(
+                "Rollout of {status} deployment {id} failed: {error}",
+                status = deployment.status,
+                id = deployment.id
+            );
+        }
+        return;
+    }
 
-                let imagegen_finished_at = get_time_i64();
-                if let Err(e) = deployment
-                    .update_imagegen_finished_at(&database, Some(imagegen_finished_at))
-                    .await
-                {
-                    log::error!(
-                        "Could not set imagegen finished at to {imagegen_finished_at} for deployment {id}: {e}",
-                        id = deployment.id
-                    );
-                };
-                log::info!(
-                    "Finished processing deployment {id} imagegen at {imagegen_finished_at}",
-                    id = deployment.id
-                );
+    match error {
+        None => {
+            if let Err(e) = deployment
+                .update_status(database, DeploymentStatus::Live, None)
+                .await
+            {
+                log::error!(
+                    "Could not mark deployment {id} as live: {e}",
+                    id = deployment.id
+                );
+                return;
+            }
+            log::info!(
+                "Deployment {id} of project {project} is live",
+                id = deployment.id,
+                project = deployment.project
+            );
 
-                if let Err(e) = deployment
-                    .update_imagegen_git_hash(&database, Some(output.git_hash.clone()))
+            if let Err(e) =
+                DatabaseDeployment::supersede_all_live(database, &deployment.project, deployment.id)
                     .await
-                {
-                    log::error!(
-                        "Could not set imagegen git hash to {git_hash} for deployment {id}: {e}",
-                        git_hash = output.git_hash,
-                        id = deployment.id
-                    );
-                };
+            {
+                log::error!(
+                    "Could not supersede previous deployments of {project}: {e}",
+                    project = deployment.project
+                );
+            }
+        }
+        Some(error) => {
+            fail_deployment(
+                database,
+                deployment,
+                format!("Mini app host rollout failed: {error}"),
+            )
+            .await;
+        }
+    }
+}
 
-                // Clear RAM
-                if let Err(e) = xnode_manager_sdk::process::execute(ExecuteInput {
-                    session: &session,
-                    path: ExecutePath {
-                        process: "comfyui.service".to_string(),
-                        scope: "container:miniapp-factory-imagegen".to_string(),
-                    },
-                    data: ProcessCommand::Restart,
-                })
-                .await
-                {
-                    log::error!(
-                        "Could not restart imagegen process comfyui on server {server}: {e:?}",
-                        server = server.id
-                    );
-                }
+/// Check whether the assignment of a server finished and move its deployment to the next phase
+async fn finish_server(database: &Database, mut server: DatabaseWorkerServer) {
+    let deployment_id = match server.assignment {
+        Some(deployment_id) => deployment_id,
+        None => {
+            log::error!(
+                "Server {server} has no deployment anymore.",
+                server = server.id
+            );
+            return;
+        }
+    };
+    let deployment = match DatabaseDeployment::get_by_id(database, deployment_id).await {
+        Ok(deployment) => deployment,
+        Err(e) => {
+            log::error!("Error getting deployment by id {deployment_id}: {e}");
+            return;
+        }
+    };
+    let mut deployment = match deployment {
+        Some(deployment) => deployment,
+        None => {
+            log::error!("Deployment with id {deployment_id} not found");
+            return;
+        }
+    };
 
-                if let Err(e) = server.update_assignment(&database, None).await {
-                    log::error!(
-                        "Couldn't unassign {deployment_id} from server {server}: {e}",
-                        server = server.id
-                    );
-                }
+    if !matches!(
+        deployment.status,
+        DeploymentStatus::Coding | DeploymentStatus::Imagegen
+    ) {
+        // Deployment is not running on this server anymore (e.g. cancelled), stop what it left running before the server is claimed again
+        let session = coding_server_session(&server).await;
+        stop_and_release_server(database, &mut server, session.as_deref()).await;
+        return;
+    }
 
-                let mut project =
-                    match DatabaseProject::get_by_name(&database, &deployment.project).await {
-                        Ok(project) => match project {
-                            Some(project) => project,
-                            None => {
-                                log::error!(
-                                    "Project {project} of deployment {id} does not exist",
-                                    project = deployment.project,
-                                    id = deployment.id
-                                );
-                                continue;
-                            }
-                        },
-                        Err(e) => {
-                            log::error!(
-                                "Could not get project {project} from the database: {e}",
-                                project = deployment.project
-                            );
-                            continue;
-                        }
-                    };
+    let started_at = match deployment.status {
+        DeploymentStatus::Coding => deployment.coding_started_at,
+        _ => deployment.imagegen_started_at,
+    };
+    if started_at.is_none() {
+        // Assignment is still being started
+        return;
+    }
 
-                if let Err(e) = project.update_version(&database, None).await {
-                    log::error!(
-                        "Could not reset {project} version: {e}",
-                        project = deployment.project
-                    );
-                }
+    let session = match coding_server_session(&server).await {
+        Some(session) => session,
+        None => {
+            return;
+        }
+    };
 
-                let deployment_request = match get_session(
-                    "https://miniapp-host.xnode-manager.openxai.org",
-                    "miniapp-host.xnode-manager.openxai.org",
+    if deployment.status == DeploymentStatus::Coding {
+        let processes = match xnode_manager_sdk::process::list(ListInput::new_with_path(
+            &session,
+            ListPath {
+                scope: "container:miniapp-factory-coder".to_string(),
+            },
+        ))
+        .await
+        {
+            Ok(processes) => processes,
+            Err(e) => {
+                log::error!(
+                    "Could not get coder process list of coding server {server}: {e:?}",
+                    server = server.id
+                );
+                return;
+            }
+        };
+        if processes
+            .iter()
+            .any(|process| process.name == "miniapp-factory-coder.service")
+        {
+            // Still running
+            return;
+        }
+        capture_output(
+            database,
+            &session,
+            &server,
+            &deployment,
+            DeploymentStatus::Coding,
+        )
+        .await;
+
+        let output = match xnode_manager_sdk::file::read_file(ReadFileInput {
+            session: &session,
+            path: ReadFilePath {
+                scope: "container:miniapp-factory-coder".to_string(),
+            },
+            query: ReadFile {
+                path: "/var/lib/miniapp-factory-coder/assignment.json".to_string(),
+            },
+        })
+        .await
+        {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!(
+                    "Could not get coding server {server} coder output file content: {e:?}",
+                    server = server.id
+                );
+                return;
+            }
+        };
+        let output = match output.content {
+            xnode_manager_sdk::utils::Output::UTF8 { output } => output,
+            xnode_manager_sdk::utils::Output::Bytes { output: _ } => {
+                fail_deployment(
+                    database,
+                    &mut deployment,
+                    "Coder output file content is not in UTF8".to_string(),
                 )
-                .await
-                {
-                    Ok(session) => {
-                        match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
-                            session: &session,
-                            path: xnode_manager_sdk::config::SetPath {
-                                container: project.name.clone(),
-                            },
-                            data: xnode_manager_sdk::config::ContainerChange {
-                                settings: {
-                                    xnode_manager_sdk::config::ContainerSettings {
-                                        flake: project.get_flake(),
-                                        network: Some("containernet".to_string()),
-                                        nvidia_gpus: None,
-                                    }
-                                },
-                                update_inputs: Some(vec![]),
-                            },
-                        })
-                        .await
-                        {
-                            Ok(request_response) => request_response.request_id.into(),
-                            Err(e) => {
-                                log::error!(
-                                    "Could not update mini app host project {project}: {e:?}",
-                                    project = project.name
-                                );
-                                continue;
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Could not get xnode session with miniapp-host: {e:?}");
-                        continue;
-                    }
-                };
+                .await;
+                release_server(database, &mut server).await;
+                return;
+            }
+        };
+        let output = match serde_json::from_str::<CoderOutput>(&output) {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!(
+                    "Could not convert coding server {server} coder {output} to output struct: {e}",
+                    server = server.id
+                );
+                fail_deployment(
+                    database,
+                    &mut deployment,
+                    format!("Coder did not produce a valid output: {e}"),
+                )
+                .await;
+                release_server(database, &mut server).await;
+                return;
+            }
+        };
 
-                if let Err(e) = deployment
-                    .update_deployment_request(&database, Some(deployment_request))
-                    .await
-                {
-                    log::error!(
-                        "Could not set deployment request to {deployment_request} for deployment {id}: {e}",
-                        id = deployment.id
-                    );
-                };
+        let coding_finished_at = get_time_i64();
+        if let Err(e) = deployment
+            .update_coding_finished_at(database, Some(coding_finished_at))
+            .await
+        {
+            log::error!(
+                "Could not set coding finished at to {coding_finished_at} for deployment {id}: {e}",
+                id = deployment.id
+            );
+        };
+        log::info!(
+            "Finished processing deployment {id} coding at {coding_finished_at}",
+            id = deployment.id
+        );
+
+        if let Err(e) = deployment
+            .update_coding_git_hash(database, Some(output.git_hash.clone()))
+            .await
+        {
+            log::error!(
+                "Could not set coding git hash to {git_hash} for deployment {id}: {e}",
+                git_hash = output.git_hash,
+                id = deployment.id
+            );
+        };
+
+        // Clear RAM
+        if let Err(e) = xnode_manager_sdk::process::execute(ExecuteInput {
+            session: &session,
+            path: ExecutePath {
+                process: "ollama.service".to_string(),
+                scope: "container:miniapp-factory-coder".to_string(),
+            },
+            data: ProcessCommand::Restart,
+        })
+        .await
+        {
+            log::error!(
+                "Could not restart coder process ollama on server {server}: {e:?}",
+                server = server.id
+            );
+        }
+
+        let imagegen = match deployment.imagegen_mode {
+            ImagegenMode::Auto => output.images_changed.unwrap_or(true),
+            ImagegenMode::Force => true,
+            ImagegenMode::Skip => false,
+        };
+        if !imagegen {
+            log::info!(
+                "Skipping imagegen of deployment {id} ({mode})",
+                id = deployment.id,
+                mode = deployment.imagegen_mode
+            );
+            release_server(database, &mut server).await;
+            host_deployment(database, &mut deployment).await;
+            return;
+        }
+
+        // The coder is free for the next deployment while images are generated
+        if let Err(e) = deployment
+            .update_status(database, DeploymentStatus::ImagegenQueued, None)
+            .await
+        {
+            log::error!(
+                "Could not mark deployment {id} as imagegen queued: {e}",
+                id = deployment.id
+            );
+        }
+        release_server(database, &mut server).await;
+        return;
+    }
+
+    if deployment.status == DeploymentStatus::Imagegen {
+        let processes = match xnode_manager_sdk::process::list(ListInput::new_with_path(
+            &session,
+            ListPath {
+                scope: "container:miniapp-factory-imagegen".to_string(),
+            },
+        ))
+        .await
+        {
+            Ok(processes) => processes,
+            Err(e) => {
+                log::error!(
+                    "Could not get imagegen process list of coding server {server}: {e:?}",
+                    server = server.id
+                );
+                return;
+            }
+        };
+        if processes
+            .iter()
+            .any(|process| process.name == "miniapp-factory-imagegen.service")
+        {
+            // Still running
+            return;
+        }
+        capture_output(
+            database,
+            &session,
+            &server,
+            &deployment,
+            DeploymentStatus::Imagegen,
+        )
+        .await;
+
+        let output = match xnode_manager_sdk::file::read_file(ReadFileInput {
+            session: &session,
+            path: ReadFilePath {
+                scope: "container:miniapp-factory-imagegen".to_string(),
+            },
+            query: ReadFile {
+                path: "/var/lib/miniapp-factory-imagegen/assignment.json".to_string(),
+            },
+        })
+        .await
+        {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!(
+                    "Could not get coding server {server} imagegen output file content: {e:?}",
+                    server = server.id
+                );
+                return;
             }
+        };
+        let output = match output.content {
+            xnode_manager_sdk::utils::Output::UTF8 { output } => output,
+            xnode_manager_sdk::utils::Output::Bytes { output: _ } => {
+                fail_deployment(
+                    database,
+                    &mut deployment,
+                    "Imagegen output file content is not in UTF8".to_string(),
+                )
+                .await;
+                release_server(database, &mut server).await;
+                return;
+            }
+        };
+        let output = match serde_json::from_str::<ImagegenOutput>(&output) {
+            Ok(output) => output,
+            Err(e) => {
+                log::error!(
+                    "Could not convert coding server {server} imagegen {output} to output struct: {e}",
+                    server = server.id
+                );
+                fail_deployment(
+                    database,
+                    &mut deployment,
+                    format!("Imagegen did not produce a valid output: {e}"),
+                )
+                .await;
+                release_server(database, &mut server).await;
+                return;
+            }
+        };
+
+        let imagegen_finished_at = get_time_i64();
+        if let Err(e) = deployment
+            .update_imagegen_finished_at(database, Some(imagegen_finished_at))
+            .await
+        {
+            log::error!(
+                "Could not set imagegen finished at to {imagegen_finished_at} for deployment {id}: {e}",
+                id = deployment.id
+            );
+        };
+        log::info!(
+            "Finished processing deployment {id} imagegen at {imagegen_finished_at}",
+            id = deployment.id
+        );
+
+        if let Err(e) = deployment
+            .update_imagegen_git_hash(database, Some(output.git_hash.clone()))
+            .await
+        {
+            log::error!(
+                "Could not set imagegen git hash to {git_hash} for deployment {id}: {e}",
+                git_hash = output.git_hash,
+                id = deployment.id
+            );
+        };
+
+        // Clear RAM
+        if let Err(e) = xnode_manager_sdk::process::execute(ExecuteInput {
+            session: &session,
+            path: ExecutePath {
+                process: "comfyui.service".to_string(),
+                scope: "container:miniapp-factory-imagegen".to_string(),
+            },
+            data: ProcessCommand::Restart,
+        })
+        .await
+        {
+            log::error!(
+                "Could not restart imagegen process comfyui on server {server}: {e:?}",
+                server = server.id
+            );
         }
+
+        release_server(database, &mut server).await;
+
+        host_deployment(database, &mut deployment).await;
     }
 }
 
-pub async fn coding_server_session(
+pub async fn fail_deployment(
+    database: &Database,
+    deployment: &mut DatabaseDeployment,
+    reason: String,
+) {
+    log::warn!(
+        "Deployment {id} of project {project} failed: {reason}",
+        id = deployment.id,
+        project = deployment.project
+    );
+    if let Err(e) = deployment
+        .update_status(database, DeploymentStatus::Failed, Some(reason))
+        .await
+    {
+        log::error!(
+            "Could not mark deployment {id} as failed: {e}",
+            id = deployment.id
+        );
+        return;
+    }
+
+    refund_deployment(database, deployment).await;
+}
+
+/// Returns whether the server was released, false when it was released (and possibly claimed again) by someone else in the meantime
+pub async fn release_server(database: &Database, server: &mut DatabaseWorkerServer) -> bool {
+    let assignment = server.assignment;
+    let result = server.release(database).await;
+    released(server, assignment, result)
+}
+
+/// Stop the worker processes of the current assignment and release the server
+///
+/// Servers that were released in the meantime are left alone, so the processes of a newer assignment are not stopped. Returns whether the server was released.
+pub async fn stop_and_release_server(
+    database: &Database,
+    server: &mut DatabaseWorkerServer,
+    session: Option<&xnode_manager_sdk::utils::Session>,
+) -> bool {
+    let assignment = server.assignment;
+    let result = stop_and_release(database, server, session).await;
+    released(server, assignment, result)
+}
+
+fn released(
     server: &DatabaseWorkerServer,
-) -> Option<xnode_manager_sdk::utils::Session> {
-    match new_deployer().ipv4(&server.hardware).await {
-        Ok(ip) => match ip {
-            OptionalSupport::Supported(ip) => match ip {
-                Some(ip) => {
-                    match get_session(
-                        &format!("https://xnode.openmesh.network/api/xnode-forward/{ip}"),
-                        "manager.xnode.local",
-                    )
-                    .await
-                    {
-                        Ok(session) => {
-                            return Some(session);
-                        }
-                        Err(e) => {
-                            log::error!("Could not establish session with {ip}: {e:?}",);
-                        }
-                    }
-                }
+    assignment: Option<i32>,
+    result: Result<(), Error>,
+) -> bool {
+    match result {
+        Ok(()) => true,
+        Err(Error::RowNotFound) => {
+            log::info!(
+                "Server {server} was already released from {assignment:?}",
+                server = server.id
+            );
+            false
+        }
+        Err(e) => {
+            log::error!(
+                "Couldn't unassign {assignment:?} from server {server}: {e}",
+                server = server.id
+            );
+            false
+        }
+    }
+}
+
+async fn stop_and_release(
+    database: &Database,
+    server: &mut DatabaseWorkerServer,
+    session: Option<&xnode_manager_sdk::utils::Session>,
+) -> Result<(), Error> {
+    if !server.is_current(database).await? {
+        return Err(Error::RowNotFound);
+    }
+
+    // Outside of any transaction, a hung worker should not hold on to a database connection
+    if let Some(session) = session
+        && time::timeout(STOP_TIMEOUT, stop_worker_processes(server, session))
+            .await
+            .is_err()
+    {
+        log::warn!(
+            "Stopping processes on server {server} timed out",
+            server = server.id
+        );
+    }
+
+    server.release(database).await
+}
+
+async fn stop_worker_processes(
+    server: &DatabaseWorkerServer,
+    session: &xnode_manager_sdk::utils::Session,
+) {
+    for container in server.role.containers() {
+        let process = format!("{container}.service");
+        if let Err(e) = xnode_manager_sdk::process::execute(ExecuteInput {
+            session,
+            path: ExecutePath {
+                process: process.clone(),
+                scope: format!("container:{container}"),
+            },
+            data: ProcessCommand::Stop,
+        })
+        .await
+        {
+            log::warn!(
+                "Couldn't stop {process} on server {server}: {e:?}",
+                server = server.id
+            );
+        }
+    }
+}
+
+async fn host_deployment(database: &Database, deployment: &mut DatabaseDeployment) {
+    if let Err(e) = deployment
+        .update_status(database, DeploymentStatus::Deploying, None)
+        .await
+    {
+        log::error!(
+            "Could not mark deployment {id} as deploying: {e}",
+            id = deployment.id
+        );
+        return;
+    }
+
+    // A host migration assigning the project in the meantime has either seen it deploying or moved it before this read
+    let mut project =
+        match DatabaseProject::get_by_name_for_share(database, &deployment.project).await {
+            Ok(project) => match project {
+                Some(project) => project,
                 None => {
-                    log::error!(
-                        "Coding server {hardware:?} has no ip",
-                        hardware = server.hardware
-                    );
+                    fail_deployment(
+                        database,
+                        deployment,
+                        format!(
+                            "Project {project} does not exist",
+                            project = deployment.project
+                        ),
+                    )
+                    .await;
+                    return;
                 }
             },
-            OptionalSupport::NotSupported => {
+            Err(e) => {
                 log::error!(
-                    "Get ip of coding server {hardware:?} is not supported",
-                    hardware = server.hardware
+                    "Could not get project {project} from the database: {e}",
+                    project = deployment.project
                 );
+                fail_deployment(
+                    database,
+                    deployment,
+                    "Could not get project from the database".to_string(),
+                )
+                .await;
+                return;
+            }
+        };
+
+    if let Err(e) = project.update_version(database, None).await {
+        log::error!(
+            "Could not reset {project} version: {e}",
+            project = deployment.project
+        );
+    }
+
+    let deployment_request = match project_host_session(database, &project).await {
+        Ok((host, session)) => {
+            match xnode_manager_sdk::config::set(xnode_manager_sdk::config::SetInput {
+                session: &session,
+                path: xnode_manager_sdk::config::SetPath {
+                    container: project.name.clone(),
+                },
+                data: xnode_manager_sdk::config::ContainerChange {
+                    settings: {
+                        xnode_manager_sdk::config::ContainerSettings {
+                            flake: project.get_flake(),
+                            network: project.get_network(),
+                            nvidia_gpus: None,
+                        }
+                    },
+                    update_inputs: Some(vec![]),
+                },
+            })
+            .await
+            {
+                Ok(request_response) => request_response.request_id.into(),
+                Err(e) => {
+                    invalidate_host_session(host.id, &session).await;
+                    log::error!(
+                        "Could not update mini app host project {project}: {e:?}",
+                        project = project.name
+                    );
+                    fail_deployment(
+                        database,
+                        deployment,
+                        "Could not update mini app host project".to_string(),
+                    )
+                    .await;
+                    return;
+                }
             }
-        },
+        }
         Err(e) => {
-            log::error!(
-                "Could not get ip of coding server {hardware:?}: {e:?}",
-                hardware = server.hardware
-            );
+            log::error!("{e}");
+            fail_deployment(
+                database,
+                deployment,
+                "Could not connect to mini app host".to_string(),
+            )
+            .await;
+            return;
         }
-    }
+    };
+
+    if let Err(e) = deployment
+        .update_deployment_request(database, Some(deployment_request))
+        .await
+    {
+        log::error!(
+            "Could not set deployment request to {deployment_request} for deployment {id}: {e}",
+            id = deployment.id
+        );
+        // Without the request id the rollout can not be followed
+        fail_deployment(
+            database,
+            deployment,
+            "Could not store mini app host request".to_string(),
+        )
+        .await;
+    };
+}
 
-    None
+/// Cached session with the xnode manager of a worker server
+pub async fn coding_server_session(server: &DatabaseWorkerServer) -> Option<Arc<Session>> {
+    worker_session(server, false).await
 }
 
 async fn coding_assignment(
@@ -667,6 +1124,7 @@ async fn coding_assignment(
                     project = deployment.project,
                     id = deployment.id
                 );
+                unclaim(database, deployment, server).await;
                 return;
             }
         },
@@ -675,6 +1133,7 @@ async fn coding_assignment(
                 "Could not get project {project} from the database: {e}",
                 project = deployment.project
             );
+            unclaim(database, deployment, server).await;
             return;
         }
     };
@@ -688,6 +1147,7 @@ async fn coding_assignment(
         Ok(assignment) => assignment,
         Err(e) => {
             log::error!("Could not convert {assignment:?} to string: {e}");
+            unclaim(database, deployment, server).await;
             return;
         }
     };
@@ -695,6 +1155,7 @@ async fn coding_assignment(
     let session = match coding_server_session(server).await {
         Some(session) => session,
         None => {
+            unclaim(database, deployment, server).await;
             return;
         }
     };
@@ -710,10 +1171,12 @@ async fn coding_assignment(
     })
     .await
     {
+        invalidate_worker_session(server.id, &session).await;
         log::error!(
             "Could not create coder data dir on server {server}: {e:?}",
             server = server.id
         );
+        unclaim(database, deployment, server).await;
         return;
     }
 
@@ -733,6 +1196,7 @@ async fn coding_assignment(
             "Could not write assignment {assignment} on coder server {server}: {e:?}",
             server = server.id
         );
+        unclaim(database, deployment, server).await;
         return;
     }
 
@@ -827,17 +1291,8 @@ async fn coding_assignment(
             "Could not start miniapp factory coder process on server {server}: {e:?}",
             server = server.id
         );
-    }
-
-    if let Err(e) = server
-        .update_assignment(database, Some(deployment.id))
-        .await
-    {
-        log::error!(
-            "Could not set coding server {server} assignment to deployment {deployment}: {e}",
-            server = server.id,
-            deployment = deployment.id
-        );
+        unclaim(database, deployment, server).await;
+        return;
     }
 
     let coding_started_at = get_time_i64();
@@ -870,6 +1325,7 @@ async fn imagegen_assignment(
         Ok(assignment) => assignment,
         Err(e) => {
             log::error!("Could not convert {assignment:?} to string: {e}");
+            unclaim(database, deployment, server).await;
             return;
         }
     };
@@ -877,6 +1333,7 @@ async fn imagegen_assignment(
     let session = match coding_server_session(server).await {
         Some(session) => session,
         None => {
+            unclaim(database, deployment, server).await;
             return;
         }
     };
@@ -892,10 +1349,12 @@ async fn imagegen_assignment(
     })
     .await
     {
+        invalidate_worker_session(server.id, &session).await;
         log::error!(
             "Could not create imagegen data dir on server {server}: {e:?}",
             server = server.id
         );
+        unclaim(database, deployment, server).await;
         return;
     }
 
@@ -915,6 +1374,7 @@ async fn imagegen_assignment(
             "Could not write assignment {assignment} on imagegen server {server}: {e:?}",
             server = server.id
         );
+        unclaim(database, deployment, server).await;
         return;
     }
 
@@ -1009,6 +1469,8 @@ async fn imagegen_assignment(
             "Could not start miniapp factory imagegen process on server {server}: {e:?}",
             server = server.id
         );
+        unclaim(database, deployment, server).await;
+        return;
     }
 
     let imagegen_started_at = get_time_i64();
@@ -1022,62 +1484,79 @@ async fn imagegen_assignment(
         );
     };
     log::info!(
-        "Started processing deployment {id} imagegen at {imagegen_started_at}",
-        id = deployment.id
+        "Started processing deployment {id} (project {project}) imagegen at {imagegen_started_at} on server {server}",
+        id = deployment.id,
+        project = deployment.project,
+        server = server.id
     );
 }
 
-async fn deploy_server(database: &Database, xnode_owner: String) {
-    let deployer = new_deployer();
-    let hardware = match deployer
-        .deploy(DeployInput {
-            acme_email: None,
-            domain: None,
-            encrypted: None,
-            initial_config: Some(
-                "\
-nixpkgs.config.allowUnfree = true;
-hardware.graphics = { enable = true; extraPackages = [ pkgs.nvidia-vaapi-driver ]; };
-hardware.nvidia.open = true;
-services.xserver.videoDrivers = [ \"nvidia\" ];
-networking.firewall.allowedTCPPorts = [53];
-networking.firewall.allowedUDPPorts = [53];\
-"
-                .to_string()
-                .replace("\"", "\\\"")
-                .replace("\n", "\\n")
-                .replace("\\", "\\\\\\"),
-            ),
-            user_passwd: None,
-            xnode_owner: Some(xnode_owner),
-        })
-        .await
-    {
-        Ok(hardware) => hardware,
+pub async fn deploy_server(database: &Database, xnode_owner: String, role: WorkerRole) {
+    let provider = worker_provider();
+    let handle = match provider.deploy(xnode_owner).await {
+        Ok(handle) => handle,
         Err(e) => {
-            log::error!("Could not deploy new coding server: {e:?}");
+            log::error!(
+                "Could not deploy new {provider} coding server: {e}",
+                provider = provider.kind()
+            );
             return;
         }
     };
 
+    let flavor = provider.flavor();
     let mut server = DatabaseWorkerServer {
         id: 0,
-        hardware: Json::from(hardware.clone()),
+        provider: provider.kind(),
+        handle: Json::from(handle.clone()),
         coder_deployment: None,
         imagegen_deployment: None,
         setup_finished: false,
         assignment: None,
         dynamic: true,
+        unreachable_since: None,
+        draining: false,
+        idle_since: Some(get_time_i64()),
+        hourly_price: flavorprices().get(&flavor).copied().unwrap_or_default(),
+        flavor,
+        deleted_at: None,
+        role,
+        assigned_at: None,
+        claim_backoff_until: None,
     };
     if let Err(e) = server.insert(database).await {
-        log::error!("Could not insert new coding server {hardware:?} into database: {e:?}");
+        log::error!("Could not insert new coding server {handle} into database: {e:?}");
 
-        if let Err(e) = deployer.undeploy(hardware).await {
-            log::error!("Error undeploying coding server after database insertion failure: {e:?}",);
+        if let Err(e) = provider.undeploy(&handle).await {
+            log::error!("Error undeploying coding server after database insertion failure: {e}",);
         }
     }
 }
 
+/// Returns whether the server was undeployed and removed
+pub async fn undeploy_server(database: &Database, server: &mut DatabaseWorkerServer) -> bool {
+    if let Err(e) = provider_of(server.provider)
+        .undeploy(server.handle.as_ref())
+        .await
+    {
+        log::error!(
+            "Error undeploying coding server {server}: {e}",
+            server = server.id
+        );
+        return false;
+    }
+
+    forget_worker(server.id);
+    if let Err(e) = server.delete(database).await {
+        log::error!(
+            "Could not remove coding server {server} from database: {e}",
+            server = server.id
+        );
+    }
+
+    true
+}
+
 async fn deploy_coder_app(
     database: &Database,
     server: &mut DatabaseWorkerServer,
@@ -1350,265 +1829,41 @@ async fn finalize_setup(
     server: &mut DatabaseWorkerServer,
     session: &xnode_manager_sdk::utils::Session,
 ) {
-    match xnode_manager_sdk::process::list(ListInput::new_with_path(
-        session,
-        ListPath {
-            scope: "container:miniapp-factory-coder".to_string(),
-        },
-    ))
-    .await
-    {
-        Ok(processes) => {
-            if processes
-                .iter()
-                .any(|process| process.name == "ollama-model-loader.service")
-            {
-                // wait for ollama download to finish
-                return;
-            }
-        }
-        Err(e) => {
-            log::error!(
-                "Could not get processes of server {server}: {e:?}",
-                server = server.id
-            );
-            return;
-        }
-    };
-
-    log::info!("Finishing setup on server {server}", server = server.id);
-    match read(datadir().join(".ssh").join("id_ed25519")) {
-        Ok(ssh_key) => {
-            if let Err(e) = xnode_manager_sdk::file::create_directory(CreateDirectoryInput {
-                session,
-                path: CreateDirectoryPath {
-                    scope: "container:miniapp-factory-coder".to_string(),
-                },
-                data: CreateDirectory {
-                    make_parent: true,
-                    path: "/var/lib/miniapp-factory-coder/.ssh".to_string(),
-                },
-            })
-            .await
-            {
-                log::error!(
-                    "Could not create coder ssh dir on server {server}: {e:?}",
-                    server = server.id
-                );
-                return;
-            }
-
-            if let Err(e) = xnode_manager_sdk::file::write_file(WriteFileInput {
-                session,
-                path: WriteFilePath {
-                    scope: "container:miniapp-factory-coder".to_string(),
-                },
-                data: WriteFile {
-                    path: "/var/lib/miniapp-factory-coder/.ssh/id_ed25519".to_string(),
-                    content: ssh_key.clone(),
-                },
-            })
-            .await
-            {
-                log::warn!(
-                    "Couldn't write ssh key on coder server {server}: {e:?}",
-                    server = server.id
-                );
-            }
-
-            let user = match xnode_manager_sdk::info::users(UsersInput::new_with_path(
-                session,
-                UsersPath {
-                    scope: "container:miniapp-factory-coder".to_string(),
-                },
-            ))
-            .await
-            {
-                Ok(users) => users
-                    .into_iter()
-                    .find(|user| user.name == "miniapp-factory-coder"),
-                Err(e) => {
-                    log::warn!(
-                        "Couldn't get users of coder server {server}: {e:?}",
-                        server = server.id
-                    );
-                    None
-                }
-            };
-
-            let group = match xnode_manager_sdk::info::groups(GroupsInput::new_with_path(
-                session,
-                GroupsPath {
-                    scope: "container:miniapp-factory-coder".to_string(),
-                },
-            ))
-            .await
-            {
-                Ok(groups) => groups
-                    .into_iter()
-                    .find(|group| group.name == "miniapp-factory-coder"),
-                Err(e) => {
-                    log::warn!(
-                        "Couldn't get groups of coder server {server}: {e:?}",
-                        server = server.id
-                    );
-                    None
+    if server.role.is_coder() {
+        match xnode_manager_sdk::process::list(ListInput::new_with_path(
+            session,
+            ListPath {
+                scope: "container:miniapp-factory-coder".to_string(),
+            },
+        ))
+        .await
+        {
+            Ok(processes) => {
+                if processes
+                    .iter()
+                    .any(|process| process.name == "ollama-model-loader.service")
+                {
+                    // wait for ollama download to finish
+                    return;
                 }
-            };
-
-            if let Err(e) = xnode_manager_sdk::file::set_permissions(SetPermissionsInput {
-                session,
-                path: SetPermissionsPath {
-                    scope: "container:miniapp-factory-coder".to_string(),
-                },
-                data: SetPermissions {
-                    path: "/var/lib/miniapp-factory-coder/.ssh/id_ed25519".to_string(),
-                    permissions: vec![
-                        Permission {
-                            granted_to: Entity::User(user.map(|user| user.id).unwrap_or_default()),
-                            read: true,
-                            write: false,
-                            execute: false,
-                        },
-                        Permission {
-                            granted_to: Entity::Group(
-                                group.map(|group| group.id).unwrap_or_default(),
-                            ),
-                            read: false,
-                            write: false,
-                            execute: false,
-                        },
-                        Permission {
-                            granted_to: Entity::Any,
-                            read: false,
-                            write: false,
-                            execute: false,
-                        },
-                    ],
-                },
-            })
-            .await
-            {
-                log::warn!(
-                    "Couldn't set ssh key permissions on coder server {server}: {e:?}",
-                    server = server.id
-                );
             }
-
-            if let Err(e) = xnode_manager_sdk::file::create_directory(CreateDirectoryInput {
-                session,
-                path: CreateDirectoryPath {
-                    scope: "container:miniapp-factory-imagegen".to_string(),
-                },
-                data: CreateDirectory {
-                    make_parent: true,
-                    path: "/var/lib/miniapp-factory-imagegen/.ssh".to_string(),
-                },
-            })
-            .await
-            {
+            Err(e) => {
                 log::error!(
-                    "Could not create imagegen ssh dir on server {server}: {e:?}",
+                    "Could not get processes of server {server}: {e:?}",
                     server = server.id
                 );
                 return;
             }
+        };
+    }
 
-            if let Err(e) = xnode_manager_sdk::file::write_file(WriteFileInput {
-                session,
-                path: WriteFilePath {
-                    scope: "container:miniapp-factory-imagegen".to_string(),
-                },
-                data: WriteFile {
-                    path: "/var/lib/miniapp-factory-imagegen/.ssh/id_ed25519".to_string(),
-                    content: ssh_key.clone(),
-                },
-            })
-            .await
-            {
-                log::warn!(
-                    "Couldn't write ssh key on imagegen server {server}: {e:?}",
-                    server = server.id
-                );
-            }
-
-            let user = match xnode_manager_sdk::info::users(UsersInput::new_with_path(
-                session,
-                UsersPath {
-                    scope: "container:miniapp-factory-imagegen".to_string(),
-                },
-            ))
-            .await
-            {
-                Ok(users) => users
-                    .into_iter()
-                    .find(|user| user.name == "miniapp-factory-imagegen"),
-                Err(e) => {
-                    log::warn!(
-                        "Couldn't get users of imagegen server {server}: {e:?}",
-                        server = server.id
-                    );
-                    None
-                }
-            };
-
-            let group = match xnode_manager_sdk::info::groups(GroupsInput::new_with_path(
-                session,
-                GroupsPath {
-                    scope: "container:miniapp-factory-imagegen".to_string(),
-                },
-            ))
-            .await
-            {
-                Ok(groups) => groups
-                    .into_iter()
-                    .find(|group| group.name == "miniapp-factory-imagegen"),
-                Err(e) => {
-                    log::warn!(
-                        "Couldn't get groups of imagegen server {server}: {e:?}",
-                        server = server.id
-                    );
-                    None
+    log::info!("Finishing setup on server {server}", server = server.id);
+    match read(datadir().join(".ssh").join("id_ed25519")) {
+        Ok(ssh_key) => {
+            for container in server.role.containers() {
+                if !install_ssh_key(server, session, container, &ssh_key).await {
+                    return;
                 }
-            };
-
-            if let Err(e) = xnode_manager_sdk::file::set_permissions(SetPermissionsInput {
-                session,
-                path: SetPermissionsPath {
-                    scope: "container:miniapp-factory-imagegen".to_string(),
-                },
-                data: SetPermissions {
-                    path: "/var/lib/miniapp-factory-imagegen/.ssh/id_ed25519".to_string(),
-                    permissions: vec![
-                        Permission {
-                            granted_to: Entity::User(user.map(|user| user.id).unwrap_or_default()),
-                            read: true,
-                            write: false,
-                            execute: false,
-                        },
-                        Permission {
-                            granted_to: Entity::Group(
-                                group.map(|group| group.id).unwrap_or_default(),
-                            ),
-                            read: false,
-                            write: false,
-                            execute: false,
-                        },
-                        Permission {
-                            granted_to: Entity::Any,
-                            read: false,
-                            write: false,
-                            execute: false,
-                        },
-                    ],
-                },
-            })
-            .await
-            {
-                log::warn!(
-                    "Couldn't set ssh key imagegen permissions on server {server}: {e:?}",
-                    server = server.id
-                );
             }
         }
         Err(e) => {
@@ -1626,3 +1881,124 @@ async fn finalize_setup(
         );
     }
 }
+
+/// Gives the service user of `container` the ssh key to push to the miniapp repositories, returns false if the key directory could not be created
+async fn install_ssh_key(
+    server: &DatabaseWorkerServer,
+    session: &xnode_manager_sdk::utils::Session,
+    container: &str,
+    ssh_key: &[u8],
+) -> bool {
+    let scope = format!("container:{container}");
+    let key_path = format!("/var/lib/{container}/.ssh/id_ed25519");
+
+    if let Err(e) = xnode_manager_sdk::file::create_directory(CreateDirectoryInput {
+        session,
+        path: CreateDirectoryPath {
+            scope: scope.clone(),
+        },
+        data: CreateDirectory {
+            make_parent: true,
+            path: format!("/var/lib/{container}/.ssh"),
+        },
+    })
+    .await
+    {
+        log::error!(
+            "Could not create {container} ssh dir on server {server}: {e:?}",
+            server = server.id
+        );
+        return false;
+    }
+
+    if let Err(e) = xnode_manager_sdk::file::write_file(WriteFileInput {
+        session,
+        path: WriteFilePath {
+            scope: scope.clone(),
+        },
+        data: WriteFile {
+            path: key_path.clone(),
+            content: ssh_key.to_vec(),
+        },
+    })
+    .await
+    {
+        log::warn!(
+            "Couldn't write ssh key on {container} server {server}: {e:?}",
+            server = server.id
+        );
+    }
+
+    let user = match xnode_manager_sdk::info::users(UsersInput::new_with_path(
+        session,
+        UsersPath {
+            scope: scope.clone(),
+        },
+    ))
+    .await
+    {
+        Ok(users) => users.into_iter().find(|user| user.name == container),
+        Err(e) => {
+            log::warn!(
+                "Couldn't get users of {container} server {server}: {e:?}",
+                server = server.id
+            );
+            None
+        }
+    };
+
+    let group = match xnode_manager_sdk::info::groups(GroupsInput::new_with_path(
+        session,
+        GroupsPath {
+            scope: scope.clone(),
+        },
+    ))
+    .await
+    {
+        Ok(groups) => groups.into_iter().find(|group| group.name == container),
+        Err(e) => {
+            log::warn!(
+                "Couldn't get groups of {container} server {server}: {e:?}",
+                server = server.id
+            );
+            None
+        }
+    };
+
+    if let Err(e) = xnode_manager_sdk::file::set_permissions(SetPermissionsInput {
+        session,
+        path: SetPermissionsPath { scope },
+        data: SetPermissions {
+            path: key_path,
+            permissions: vec![
+                Permission {
+                    granted_to: Entity::User(user.map(|user| user.id).unwrap_or_default()),
+                    read: true,
+                    write: false,
+                    execute: false,
+                },
+                Permission {
+                    granted_to: Entity::Group(group.map(|group| group.id).unwrap_or_default()),
+                    read: false,
+                    write: false,
+                    execute: false,
+                },
+                Permission {
+                    granted_to: Entity::Any,
+                    read: false,
+                    write: false,
+                    execute: false,
+                },
+            ],
+        },
+    })
+    .await
+    {
+        log::warn!(
+            "Couldn't set ssh key permissions on {container} server {server}: {e:?}",
+            server = server.id
+        );
+    }
+
+    true
+}
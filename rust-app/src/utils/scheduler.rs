@@ -0,0 +1,206 @@
+use std::{cmp::Reverse, collections::HashMap};
+
+use serde::{Deserialize, Serialize};
+use sqlx::Error;
+
+use crate::{
+    database::{
+        Database,
+        deployments::{DatabaseDeployment, DeploymentPriority},
+    },
+    utils::{
+        env::maxaccountdeployments,
+        eta::{PhaseStatistics, queue_starts},
+        time::get_time_i64,
+    },
+};
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct QueueEstimate {
+    /// Queued deployments that will be started before this one
+    pub position: i64,
+    /// Unix timestamp, none when there is no history to base it on
+    pub estimated_start: Option<i64>,
+    /// Unix timestamp the worker phases are expected to be finished
+    pub estimated_finish: Option<i64>,
+}
+
+/// Deployments without account (from before accounts were recorded) share by project
+fn account_of(deployment: &DatabaseDeployment) -> String {
+    deployment
+        .account
+        .clone()
+        .unwrap_or_else(|| deployment.project.clone())
+}
+
+/// Express deployments go first, then paid changes, then free ones
+fn tier(deployment: &DatabaseDeployment) -> u8 {
+    match deployment.priority {
+        DeploymentPriority::Express => 2,
+        DeploymentPriority::Standard if deployment.credits > 0 => 1,
+        DeploymentPriority::Standard => 0,
+    }
+}
+
+/// Order queued deployments by tier, within a tier accounts take turns (fewest deployments running or ahead first), ties go to the oldest
+pub fn schedule(
+    queued: Vec<DatabaseDeployment>,
+    running: &HashMap<String, i64>,
+) -> Vec<DatabaseDeployment> {
+    let mut ahead: HashMap<String, i64> = HashMap::new();
+    let mut ranked: Vec<(u8, i64, DatabaseDeployment)> = queued
+        .into_iter()
+        .map(|deployment| {
+            let account = account_of(&deployment);
+            let count = ahead
+                .entry(account.clone())
+                .or_insert(running.get(&account).copied().unwrap_or_default());
+            let share = *count;
+            *count += 1;
+            (tier(&deployment), share, deployment)
+        })
+        .collect();
+    ranked.sort_by_key(|(tier, share, deployment)| (Reverse(*tier), *share, deployment.id));
+
+    ranked
+        .into_iter()
+        .map(|(_, _, deployment)| deployment)
+        .collect()
+}
+
+async fn scheduled_queue(
+    database: &Database,
+) -> Result<(Vec<DatabaseDeployment>, HashMap<String, i64>), Error> {
+    let queued = DatabaseDeployment::get_all_queued(database).await?;
+    let running: HashMap<String, i64> = DatabaseDeployment::get_running_count_by_account(database)
+        .await?
+        .into_iter()
+        .collect();
+
+    Ok((schedule(queued, &running), running))
+}
+
+/// Next queued deployment to start, skipping accounts that reached their concurrent deployments limit
+pub async fn next_deployment(database: &Database) -> Result<Option<DatabaseDeployment>, Error> {
+    let (queue, running) = scheduled_queue(database).await?;
+    let max = maxaccountdeployments();
+
+    Ok(queue.into_iter().find(|deployment| {
+        running
+            .get(&account_of(deployment))
+            .copied()
+            .unwrap_or_default()
+            < max
+    }))
+}
+
+/// Position of a queued deployment under the scheduling order, none when it is not queued
+/// Queued deployments that will be started before this one, none when it is not queued
+pub async fn queue_position(database: &Database, deployment: i32) -> Result<Option<i64>, Error> {
+    let (queue, _) = scheduled_queue(database).await?;
+
+    Ok(queue
+        .iter()
+        .position(|queued| queued.id == deployment)
+        .map(|position| position as i64))
+}
+
+pub async fn queue_estimate(
+    database: &Database,
+    deployment: i32,
+) -> Result<Option<QueueEstimate>, Error> {
+    let position = match queue_position(database, deployment).await? {
+        Some(position) => position,
+        None => {
+            return Ok(None);
+        }
+    };
+
+    let statistics = PhaseStatistics::recent(database).await?;
+    let estimated_start =
+        queue_starts(database, &statistics, position as usize + 1, get_time_i64())
+            .await?
+            .and_then(|starts| starts.last().copied());
+    let estimated_finish = estimated_start
+        .zip(statistics.processing())
+        .map(|(start, processing)| start + processing as i64);
+
+    Ok(Some(QueueEstimate {
+        position,
+        estimated_start,
+        estimated_finish,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::database::deployments::{DatabaseDeployment, DeploymentPriority};
+
+    use super::schedule;
+
+    fn queued(id: i32, account: &str) -> DatabaseDeployment {
+        DatabaseDeployment {
+            account: Some(account.to_string()),
+            ..DatabaseDeployment::queued(id, "project")
+        }
+    }
+
+    fn ids(deployments: Vec<DatabaseDeployment>) -> Vec<i32> {
+        deployments
+            .into_iter()
+            .map(|deployment| deployment.id)
+            .collect()
+    }
+
+    #[test]
+    fn express_then_paid_then_free() {
+        let queue = vec![
+            queued(1, "a"),
+            DatabaseDeployment {
+                credits: 10,
+                ..queued(2, "b")
+            },
+            DatabaseDeployment {
+                credits: 10,
+                priority: DeploymentPriority::Express,
+                ..queued(3, "c")
+            },
+        ];
+
+        assert_eq!(ids(schedule(queue, &HashMap::new())), vec![3, 2, 1]);
+    }
+
+    #[test]
+    fn accounts_take_turns_within_a_tier() {
+        let queue = vec![
+            queued(1, "a"),
+            queued(2, "a"),
+            queued(3, "a"),
+            queued(4, "b"),
+            queued(5, "b"),
+        ];
+
+        assert_eq!(ids(schedule(queue, &HashMap::new())), vec![1, 4, 2, 5, 3]);
+    }
+
+    #[test]
+    fn running_deployments_count_towards_the_share() {
+        let queue = vec![queued(1, "a"), queued(2, "b"), queued(3, "b")];
+        let running = HashMap::from([("a".to_string(), 2)]);
+
+        assert_eq!(ids(schedule(queue, &running)), vec![2, 3, 1]);
+    }
+
+    #[test]
+    fn deployments_without_account_share_by_project() {
+        let queue = vec![
+            DatabaseDeployment::queued(1, "shared"),
+            DatabaseDeployment::queued(2, "shared"),
+            queued(3, "a"),
+        ];
+
+        assert_eq!(ids(schedule(queue, &HashMap::new())), vec![1, 3, 2]);
+    }
+}
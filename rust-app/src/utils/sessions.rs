@@ -0,0 +1,222 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, LazyLock, Mutex, PoisonError},
+};
+
+use xnode_manager_sdk::utils::Session;
+
+use crate::{
+    database::{
+        Database, miniapp_hosts::DatabaseMiniappHost, projects::DatabaseProject,
+        worker_servers::DatabaseWorkerServer,
+    },
+    utils::{auth::get_session, env::sessionlifetime, time::get_time_i64},
+    worker_provider::{WorkerManager, WorkerProvider, provider_of},
+};
+
+struct CachedSession {
+    session: Arc<Session>,
+    created_at: i64,
+}
+
+/// Resolved manager (e.g. from the worker IPv4) and logged in session of a worker
+#[derive(Default)]
+struct WorkerConnection {
+    manager: Option<WorkerManager>,
+    session: Option<CachedSession>,
+}
+
+/// Shared by all loops and handlers, the per worker lock makes sure only one of them logs in at the same time
+static WORKERS: LazyLock<Mutex<HashMap<i32, Arc<tokio::sync::Mutex<WorkerConnection>>>>> =
+    LazyLock::new(Default::default);
+
+/// Logged in sessions with the mini app hosts, by host id
+static HOSTS: LazyLock<Mutex<HashMap<i32, Arc<tokio::sync::Mutex<Option<CachedSession>>>>>> =
+    LazyLock::new(Default::default);
+
+fn worker_connection(worker: i32) -> Arc<tokio::sync::Mutex<WorkerConnection>> {
+    WORKERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(worker)
+        .or_default()
+        .clone()
+}
+
+/// Drop the cached manager and session of a worker that is undeployed or removed
+pub fn forget_worker(worker: i32) {
+    WORKERS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .remove(&worker);
+}
+
+/// Whether `session` is the cached one, copies handed out earlier may have been replaced already
+fn is_cached(cached: &Option<CachedSession>, session: &Session) -> bool {
+    cached
+        .as_ref()
+        .is_some_and(|cached| std::ptr::eq(Arc::as_ptr(&cached.session), session))
+}
+
+/// Drop the cached session of a worker after a call with it failed (e.g. it expired on the manager), the next caller logs in again
+pub async fn invalidate_worker_session(worker: i32, session: &Session) {
+    let connection = worker_connection(worker);
+    let mut connection = connection.lock().await;
+
+    if is_cached(&connection.session, session) {
+        connection.session = None;
+    }
+}
+
+/// Drop the cached session of a mini app host after a call with it failed, the next caller logs in again
+pub async fn invalidate_host_session(host: i32, session: &Session) {
+    let cached = HOSTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .get(&host)
+        .cloned();
+    let Some(cached) = cached else {
+        return;
+    };
+    let mut cached = cached.lock().await;
+
+    if is_cached(&cached, session) {
+        *cached = None;
+    }
+}
+
+async fn resolve_manager(server: &DatabaseWorkerServer) -> Option<WorkerManager> {
+    match provider_of(server.provider)
+        .manager(server.handle.as_ref())
+        .await
+    {
+        Ok(Some(manager)) => Some(manager),
+        Ok(None) => {
+            log::error!(
+                "{provider} coding server {server} has no manager",
+                provider = server.provider,
+                server = server.id
+            );
+            None
+        }
+        Err(e) => {
+            log::error!(
+                "Could not get manager of {provider} coding server {server}: {e}",
+                provider = server.provider,
+                server = server.id
+            );
+            None
+        }
+    }
+}
+
+async fn login(manager: &WorkerManager) -> Option<CachedSession> {
+    match get_session(&manager.url, &manager.domain).await {
+        Ok(session) => Some(CachedSession {
+            session: Arc::new(session),
+            created_at: get_time_i64(),
+        }),
+        Err(e) => {
+            log::error!(
+                "Could not establish session with {url}: {e:?}",
+                url = manager.url
+            );
+            None
+        }
+    }
+}
+
+/// Session with the xnode manager of a worker, reused until it is older than the session lifetime or invalidated after a failed call
+///
+/// With `fresh` a new login is forced, which doubles as reachability check. The manager is only resolved again (e.g. a new IPv4 from Hyperstack) when logging in with the cached one fails.
+pub async fn worker_session(server: &DatabaseWorkerServer, fresh: bool) -> Option<Arc<Session>> {
+    let connection = worker_connection(server.id);
+    let mut connection = connection.lock().await;
+
+    if !fresh
+        && let Some(cached) = &connection.session
+        && get_time_i64() - cached.created_at < sessionlifetime()
+    {
+        return Some(cached.session.clone());
+    }
+    connection.session = None;
+
+    if let Some(manager) = &connection.manager
+        && let Some(cached) = login(manager).await
+    {
+        let session = cached.session.clone();
+        connection.session = Some(cached);
+        return Some(session);
+    }
+
+    connection.manager = None;
+    let manager = resolve_manager(server).await?;
+    let cached = login(&manager).await;
+    connection.manager = Some(manager);
+    let cached = cached?;
+    let session = cached.session.clone();
+    connection.session = Some(cached);
+
+    Some(session)
+}
+
+/// Session with the xnode manager of a mini app host, reused until it is older than the session lifetime or invalidated after a failed call
+pub async fn host_session(host: &DatabaseMiniappHost) -> Result<Arc<Session>, String> {
+    let cached = HOSTS
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .entry(host.id)
+        .or_default()
+        .clone();
+    let mut cached = cached.lock().await;
+
+    if let Some(cached) = cached.as_ref()
+        && get_time_i64() - cached.created_at < sessionlifetime()
+    {
+        return Ok(cached.session.clone());
+    }
+
+    let session = Arc::new(get_session(&host.url, &host.domain).await.map_err(|e| {
+        format!(
+            "Could not get xnode session with mini app host {url}: {e:?}",
+            url = host.url
+        )
+    })?);
+    *cached = Some(CachedSession {
+        session: session.clone(),
+        created_at: get_time_i64(),
+    });
+
+    Ok(session)
+}
+
+pub async fn miniapp_host(database: &Database, id: i32) -> Result<DatabaseMiniappHost, String> {
+    DatabaseMiniappHost::get_by_id(database, id)
+        .await
+        .map_err(|e| format!("Could not get mini app host {id} from the database: {e}"))?
+        .ok_or(format!("Mini app host {id} does not exist"))
+}
+
+/// Mini app host with a session to reach it
+pub async fn miniapp_host_session(
+    database: &Database,
+    id: i32,
+) -> Result<(DatabaseMiniappHost, Arc<Session>), String> {
+    let host = miniapp_host(database, id).await?;
+    let session = host_session(&host).await?;
+
+    Ok((host, session))
+}
+
+/// Mini app host a project is placed on, with a session to reach it
+pub async fn project_host_session(
+    database: &Database,
+    project: &DatabaseProject,
+) -> Result<(DatabaseMiniappHost, Arc<Session>), String> {
+    let id = project.miniapp_host.ok_or(format!(
+        "Project {project} is not placed on a mini app host",
+        project = project.name
+    ))?;
+
+    miniapp_host_session(database, id).await
+}
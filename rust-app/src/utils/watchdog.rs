@@ -0,0 +1,317 @@
+use std::{sync::Arc, time::Duration};
+
+use sqlx::Error;
+use tokio::time;
+use xnode_manager_sdk::{config::ContainersInput, utils::Session};
+
+use crate::{
+    database::{
+        Database,
+        deployments::{DatabaseDeployment, DeploymentStatus},
+        worker_events::DatabaseWorkerEvent,
+        worker_servers::DatabaseWorkerServer,
+    },
+    utils::{
+        env::{
+            codingtimeout, imagegenqueuetimeout, imagegentimeout, maxretries, rollouttimeout,
+            setuptimeout, unreachablegrace,
+        },
+        output::capture_output,
+        runner::{fail_deployment, stop_and_release_server, undeploy_server},
+        sessions::worker_session,
+        time::get_time_i64,
+    },
+};
+
+pub async fn watchdog(database: Database) {
+    let mut interval = time::interval(Duration::from_secs(30));
+
+    loop {
+        interval.tick().await;
+
+        match DatabaseWorkerServer::get_all(&database).await {
+            Ok(servers) => {
+                for mut server in servers {
+                    if !server.setup_finished {
+                        // Setup failures are expected until the OS is installed
+                        check_setup(&database, &mut server).await;
+                        continue;
+                    }
+
+                    check_server(&database, &mut server).await;
+                }
+            }
+            Err(e) => {
+                log::error!("Could not get all coding servers: {e}");
+            }
+        }
+
+        match DatabaseDeployment::get_all_by_status(&database, DeploymentStatus::ImagegenQueued)
+            .await
+        {
+            Ok(deployments) => {
+                let now = get_time_i64();
+                let timeout = imagegenqueuetimeout();
+                for mut deployment in deployments {
+                    // E.g. no imagegen worker exists and none can be deployed
+                    if deployment
+                        .coding_finished_at
+                        .is_some_and(|coding_finished_at| now - coding_finished_at > timeout)
+                    {
+                        fail_deployment(
+                            &database,
+                            &mut deployment,
+                            format!("No imagegen worker became available within {timeout} seconds"),
+                        )
+                        .await;
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Could not get all imagegen queued deployments: {e}");
+            }
+        }
+
+        match DatabaseDeployment::get_all_by_status(&database, DeploymentStatus::Deploying).await {
+            Ok(deployments) => {
+                let now = get_time_i64();
+                let timeout = rollouttimeout();
+                for mut deployment in deployments {
+                    if deployment
+                        .imagegen_finished_at
+                        .or(deployment.coding_finished_at)
+                        .is_some_and(|started_at| now - started_at > timeout)
+                    {
+                        fail_deployment(
+                            &database,
+                            &mut deployment,
+                            format!(
+                                "Mini app host rollout did not finish within {timeout} seconds"
+                            ),
+                        )
+                        .await;
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Could not get all deploying deployments: {e}");
+            }
+        }
+    }
+}
+
+/// Undeploy dynamic servers stuck in setup, they are billed without ever taking work
+async fn check_setup(database: &Database, server: &mut DatabaseWorkerServer) {
+    if !server.dynamic {
+        return;
+    }
+
+    let deployed_at = match DatabaseWorkerEvent::get_deployed_at(database, server.id).await {
+        Ok(Some(deployed_at)) => deployed_at,
+        Ok(None) => {
+            return;
+        }
+        Err(e) => {
+            log::error!(
+                "Could not get deployment time of coding server {server}: {e}",
+                server = server.id
+            );
+            return;
+        }
+    };
+
+    let timeout = setuptimeout();
+    if get_time_i64() - deployed_at <= timeout {
+        return;
+    }
+
+    log::warn!(
+        "Coding server {server} did not finish setup within {timeout} seconds",
+        server = server.id
+    );
+    if undeploy_server(database, server).await {
+        log::info!(
+            "Reclaimed coding server {server} by undeploying it",
+            server = server.id
+        );
+    }
+}
+
+/// Cached session that still works, only logging in again when a call with it fails
+async fn reachable_session(server: &DatabaseWorkerServer) -> Option<Arc<Session>> {
+    if let Some(session) = worker_session(server, false).await
+        && xnode_manager_sdk::config::containers(ContainersInput::new(&session))
+            .await
+            .is_ok()
+    {
+        return Some(session);
+    }
+
+    // E.g. the session expired on the server or the server became unreachable
+    worker_session(server, true).await
+}
+
+async fn check_server(database: &Database, server: &mut DatabaseWorkerServer) {
+    let now = get_time_i64();
+    let session = reachable_session(server).await;
+
+    let deployment = match server.assignment {
+        Some(deployment_id) => match DatabaseDeployment::get_by_id(database, deployment_id).await {
+            Ok(deployment) => deployment,
+            Err(e) => {
+                log::error!("Error getting deployment by id {deployment_id}: {e}");
+                return;
+            }
+        },
+        None => None,
+    };
+
+    if session.is_none() {
+        let unreachable_since = match server.unreachable_since {
+            Some(unreachable_since) => unreachable_since,
+            None => {
+                if let Err(e) = server.update_unreachable_since(database, Some(now)).await {
+                    log::error!(
+                        "Could not mark coding server {server} as unreachable: {e}",
+                        server = server.id
+                    );
+                }
+                now
+            }
+        };
+
+        let grace = unreachablegrace();
+        if now - unreachable_since <= grace {
+            return;
+        }
+
+        log::warn!(
+            "Coding server {server} has been unreachable for more than {grace} seconds",
+            server = server.id
+        );
+        if let Some(mut deployment) = deployment {
+            retry_deployment(
+                database,
+                &mut deployment,
+                format!("Worker server unreachable for more than {grace} seconds"),
+            )
+            .await;
+        }
+        if server.dynamic || server.assignment.is_some() {
+            reclaim_server(database, server, None).await;
+        }
+        return;
+    }
+
+    if server.unreachable_since.is_some()
+        && let Err(e) = server.update_unreachable_since(database, None).await
+    {
+        log::error!(
+            "Could not mark coding server {server} as reachable: {e}",
+            server = server.id
+        );
+    }
+
+    let mut deployment = match deployment {
+        Some(deployment) => deployment,
+        None => {
+            return;
+        }
+    };
+    let (started_at, timeout) = match deployment.status {
+        DeploymentStatus::Coding => (deployment.coding_started_at, codingtimeout()),
+        DeploymentStatus::Imagegen => (deployment.imagegen_started_at, imagegentimeout()),
+        _ => {
+            // Released by finish_deployment
+            return;
+        }
+    };
+
+    // Claims that never started (e.g. the claiming instance stopped) time out as well
+    if started_at
+        .or(server.assigned_at)
+        .is_some_and(|started_at| now - started_at > timeout)
+    {
+        if let Some(session) = &session {
+            capture_output(database, session, server, &deployment, deployment.status).await;
+        }
+        retry_deployment(
+            database,
+            &mut deployment,
+            format!(
+                "{status} did not finish within {timeout} seconds",
+                status = deployment.status
+            ),
+        )
+        .await;
+        reclaim_server(database, server, session.as_deref()).await;
+    }
+}
+
+async fn retry_deployment(
+    database: &Database,
+    deployment: &mut DatabaseDeployment,
+    reason: String,
+) {
+    if !matches!(
+        deployment.status,
+        DeploymentStatus::Coding | DeploymentStatus::Imagegen
+    ) {
+        // Finished or moved on (e.g. cancelled) in the meantime, nothing to retry
+        return;
+    }
+
+    let max_retries = maxretries();
+    if deployment.retries >= max_retries {
+        fail_deployment(
+            database,
+            deployment,
+            format!("{reason} (after {max_retries} retries)"),
+        )
+        .await;
+        return;
+    }
+
+    log::warn!(
+        "Requeueing deployment {id} of project {project}: {reason}",
+        id = deployment.id,
+        project = deployment.project
+    );
+    // Coding results are kept when only image generation failed
+    let result = if deployment.status == DeploymentStatus::Imagegen {
+        deployment.requeue_imagegen(database).await
+    } else {
+        deployment.requeue(database).await
+    };
+    match result {
+        Ok(()) => {}
+        Err(Error::RowNotFound) => {
+            // The status changed since it was read, whoever changed it owns the deployment now
+            log::info!(
+                "Deployment {id} changed status before it could be requeued",
+                id = deployment.id
+            );
+        }
+        Err(e) => {
+            log::error!("Could not requeue deployment {id}: {e}", id = deployment.id);
+        }
+    }
+}
+
+async fn reclaim_server(
+    database: &Database,
+    server: &mut DatabaseWorkerServer,
+    session: Option<&xnode_manager_sdk::utils::Session>,
+) {
+    if server.dynamic && undeploy_server(database, server).await {
+        log::info!(
+            "Reclaimed coding server {server} by undeploying it",
+            server = server.id
+        );
+        return;
+    }
+
+    if stop_and_release_server(database, server, session).await {
+        log::info!("Reclaimed coding server {server}", server = server.id);
+    }
+}
@@ -0,0 +1,129 @@
+use rand::{Rng, distr::Alphanumeric};
+use serde_json::Value;
+use xnode_deployer::{
+    DeployInput, OptionalSupport, XnodeDeployer,
+    hyperstack::{HyperstackDeployer, HyperstackHardware, HyperstackOutput},
+};
+
+use crate::{
+    database::worker_servers::WorkerProviderKind,
+    utils::env::{hyperstackapikey, hyperstackenvironment, hyperstackflavor},
+    worker_provider::{WorkerManager, WorkerProvider, WorkerProviderError, WorkerStatus},
+};
+
+/// GPU virtual machines rented from Hyperstack, the handle is the HyperstackOutput of the deployment
+pub struct Hyperstack {
+    api_key: String,
+    environment: String,
+    flavor: String,
+}
+
+impl Hyperstack {
+    pub fn from_env() -> Self {
+        Self {
+            api_key: hyperstackapikey(),
+            environment: hyperstackenvironment(),
+            flavor: hyperstackflavor(),
+        }
+    }
+
+    fn deployer(&self) -> HyperstackDeployer {
+        HyperstackDeployer::new(
+            self.api_key.clone(),
+            HyperstackHardware::VirtualMachine {
+                name: format!(
+                    "miniapp-factory-coder-{random}",
+                    random = rand::rng()
+                        .sample_iter(&Alphanumeric)
+                        .take(10)
+                        .map(char::from)
+                        .collect::<String>()
+                ),
+                environment_name: self.environment.clone(),
+                flavor_name: self.flavor.clone(),
+                key_name: "NixOS".to_string(),
+            },
+        )
+    }
+}
+
+fn hardware(handle: &Value) -> Result<HyperstackOutput, WorkerProviderError> {
+    Ok(serde_json::from_value(handle.clone())?)
+}
+
+impl WorkerProvider for Hyperstack {
+    fn kind(&self) -> WorkerProviderKind {
+        WorkerProviderKind::Hyperstack
+    }
+
+    fn flavor(&self) -> String {
+        self.flavor.clone()
+    }
+
+    async fn deploy(&self, xnode_owner: String) -> Result<Value, WorkerProviderError> {
+        let hardware = self
+            .deployer()
+            .deploy(DeployInput {
+                acme_email: None,
+                domain: None,
+                encrypted: None,
+                initial_config: Some(
+                    "\
+nixpkgs.config.allowUnfree = true;
+hardware.graphics = { enable = true; extraPackages = [ pkgs.nvidia-vaapi-driver ]; };
+hardware.nvidia.open = true;
+services.xserver.videoDrivers = [ \"nvidia\" ];
+networking.firewall.allowedTCPPorts = [53];
+networking.firewall.allowedUDPPorts = [53];\
+"
+                    .to_string()
+                    .replace("\"", "\\\"")
+                    .replace("\n", "\\n")
+                    .replace("\\", "\\\\\\"),
+                ),
+                user_passwd: None,
+                xnode_owner: Some(xnode_owner),
+            })
+            .await
+            .map_err(|e| WorkerProviderError::Deployer(format!("{e:?}")))?;
+
+        Ok(serde_json::to_value(hardware)?)
+    }
+
+    async fn undeploy(&self, handle: &Value) -> Result<(), WorkerProviderError> {
+        self.deployer()
+            .undeploy(hardware(handle)?)
+            .await
+            .map_err(|e| WorkerProviderError::Deployer(format!("{e:?}")))?;
+
+        Ok(())
+    }
+
+    async fn ipv4(&self, handle: &Value) -> Result<Option<String>, WorkerProviderError> {
+        match self
+            .deployer()
+            .ipv4(&hardware(handle)?)
+            .await
+            .map_err(|e| WorkerProviderError::Deployer(format!("{e:?}")))?
+        {
+            OptionalSupport::Supported(ip) => Ok(ip.map(|ip| ip.to_string())),
+            OptionalSupport::NotSupported => Err(WorkerProviderError::NotSupported),
+        }
+    }
+
+    async fn manager(&self, handle: &Value) -> Result<Option<WorkerManager>, WorkerProviderError> {
+        Ok(self
+            .ipv4(handle)
+            .await?
+            .map(|ip| WorkerManager::forwarded(&ip)))
+    }
+
+    async fn status(&self, handle: &Value) -> Result<WorkerStatus, WorkerProviderError> {
+        match self.ipv4(handle).await {
+            Ok(Some(_)) => Ok(WorkerStatus::Running),
+            Ok(None) => Ok(WorkerStatus::Starting),
+            Err(WorkerProviderError::Deployer(_)) => Ok(WorkerStatus::Unreachable),
+            Err(e) => Err(e),
+        }
+    }
+}
@@ -0,0 +1,105 @@
+use rand::{Rng, distr::Alphanumeric};
+use serde_json::{Value, json};
+
+use crate::{
+    database::worker_servers::WorkerProviderKind,
+    utils::env::mockworkerurl,
+    worker_provider::{WorkerManager, WorkerProvider, WorkerProviderError, WorkerStatus},
+};
+
+/// Pretends to deploy workers, all of them connect to a single (local) xnode manager, meant for development and testing
+pub struct Mock {
+    url: String,
+}
+
+impl Mock {
+    pub fn from_env() -> Self {
+        Self {
+            url: mockworkerurl(),
+        }
+    }
+}
+
+impl WorkerProvider for Mock {
+    fn kind(&self) -> WorkerProviderKind {
+        WorkerProviderKind::Mock
+    }
+
+    fn flavor(&self) -> String {
+        "mock".to_string()
+    }
+
+    async fn deploy(&self, _xnode_owner: String) -> Result<Value, WorkerProviderError> {
+        Ok(json!({
+            "name": format!(
+                "miniapp-factory-mock-{random}",
+                random = rand::rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(10)
+                    .map(char::from)
+                    .collect::<String>()
+            ),
+        }))
+    }
+
+    async fn undeploy(&self, _handle: &Value) -> Result<(), WorkerProviderError> {
+        Ok(())
+    }
+
+    async fn ipv4(&self, _handle: &Value) -> Result<Option<String>, WorkerProviderError> {
+        Ok(Some("127.0.0.1".to_string()))
+    }
+
+    async fn manager(&self, _handle: &Value) -> Result<Option<WorkerManager>, WorkerProviderError> {
+        Ok(Some(WorkerManager {
+            url: self.url.clone(),
+            domain: "manager.xnode.local".to_string(),
+        }))
+    }
+
+    async fn status(&self, _handle: &Value) -> Result<WorkerStatus, WorkerProviderError> {
+        Ok(WorkerStatus::Running)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::worker_provider::{WorkerProvider, WorkerStatus};
+
+    use super::Mock;
+
+    fn mock() -> Mock {
+        Mock {
+            url: "http://127.0.0.1:1234".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn deploys_unique_workers() {
+        let mock = mock();
+        let first = mock.deploy("owner".to_string()).await.unwrap();
+        let second = mock.deploy("owner".to_string()).await.unwrap();
+
+        assert!(
+            first["name"]
+                .as_str()
+                .is_some_and(|name| name.starts_with("miniapp-factory-mock-"))
+        );
+        assert_ne!(first, second);
+    }
+
+    #[tokio::test]
+    async fn every_worker_connects_to_the_same_manager() {
+        let mock = mock();
+        let handle = mock.deploy("owner".to_string()).await.unwrap();
+
+        let manager = mock.manager(&handle).await.unwrap().unwrap();
+        assert_eq!(manager.url, "http://127.0.0.1:1234");
+        assert_eq!(
+            mock.ipv4(&handle).await.unwrap().as_deref(),
+            Some("127.0.0.1")
+        );
+        assert_eq!(mock.status(&handle).await.unwrap(), WorkerStatus::Running);
+        assert!(mock.undeploy(&handle).await.is_ok());
+    }
+}
@@ -0,0 +1,186 @@
+use std::fmt::Display;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    database::worker_servers::WorkerProviderKind,
+    utils::env::workerprovider,
+    worker_provider::{hyperstack::Hyperstack, mock::Mock, static_pool::StaticPool},
+};
+
+pub mod hyperstack;
+pub mod mock;
+pub mod static_pool;
+
+#[derive(Debug)]
+pub enum WorkerProviderError {
+    Deployer(String),
+    Handle(serde_json::Error),
+    NotSupported,
+}
+
+impl Display for WorkerProviderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WorkerProviderError::Deployer(e) => write!(f, "deployer error: {e}"),
+            WorkerProviderError::Handle(e) => write!(f, "invalid worker handle: {e}"),
+            WorkerProviderError::NotSupported => write!(f, "not supported by this provider"),
+        }
+    }
+}
+
+impl From<serde_json::Error> for WorkerProviderError {
+    fn from(value: serde_json::Error) -> Self {
+        WorkerProviderError::Handle(value)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WorkerStatus {
+    /// Deployed, but not reachable yet
+    Starting,
+    Running,
+    Unreachable,
+}
+
+/// Xnode manager of a worker server
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct WorkerManager {
+    pub url: String,
+    pub domain: String,
+}
+
+impl WorkerManager {
+    /// Manager reached through the Openmesh xnode forwarding service
+    pub fn forwarded(ip: &str) -> Self {
+        Self {
+            url: format!("https://xnode.openmesh.network/api/xnode-forward/{ip}"),
+            domain: "manager.xnode.local".to_string(),
+        }
+    }
+}
+
+/// Supplies the servers running the coder and imagegen workers, each worker is identified by an opaque handle
+pub trait WorkerProvider {
+    fn kind(&self) -> WorkerProviderKind;
+
+    /// Hardware of newly deployed workers, used to look up their price
+    fn flavor(&self) -> String;
+
+    /// Deploy a new worker owned by `xnode_owner`, returns its handle
+    fn deploy(
+        &self,
+        xnode_owner: String,
+    ) -> impl Future<Output = Result<Value, WorkerProviderError>> + Send;
+
+    fn undeploy(
+        &self,
+        handle: &Value,
+    ) -> impl Future<Output = Result<(), WorkerProviderError>> + Send;
+
+    /// Returns None in case the worker has no ip (yet)
+    #[allow(dead_code)]
+    fn ipv4(
+        &self,
+        handle: &Value,
+    ) -> impl Future<Output = Result<Option<String>, WorkerProviderError>> + Send;
+
+    /// Returns None in case the manager of the worker can not be reached (yet)
+    fn manager(
+        &self,
+        handle: &Value,
+    ) -> impl Future<Output = Result<Option<WorkerManager>, WorkerProviderError>> + Send;
+
+    #[allow(dead_code)]
+    fn status(
+        &self,
+        handle: &Value,
+    ) -> impl Future<Output = Result<WorkerStatus, WorkerProviderError>> + Send;
+}
+
+pub enum WorkerProviderBackend {
+    Hyperstack(Hyperstack),
+    Static(StaticPool),
+    Mock(Mock),
+}
+
+/// Provider new workers are deployed with, selected by the WORKERPROVIDER env var (hyperstack, static or mock)
+pub fn worker_provider() -> WorkerProviderBackend {
+    match workerprovider().as_str() {
+        "hyperstack" => provider_of(WorkerProviderKind::Hyperstack),
+        "static" => provider_of(WorkerProviderKind::Static),
+        "mock" => provider_of(WorkerProviderKind::Mock),
+        other => {
+            panic!("Unknown worker provider {other}, expected hyperstack, static or mock.")
+        }
+    }
+}
+
+/// Provider managing existing workers of `kind`
+pub fn provider_of(kind: WorkerProviderKind) -> WorkerProviderBackend {
+    match kind {
+        WorkerProviderKind::Hyperstack => WorkerProviderBackend::Hyperstack(Hyperstack::from_env()),
+        WorkerProviderKind::Static => WorkerProviderBackend::Static(StaticPool),
+        WorkerProviderKind::Mock => WorkerProviderBackend::Mock(Mock::from_env()),
+    }
+}
+
+impl WorkerProvider for WorkerProviderBackend {
+    fn kind(&self) -> WorkerProviderKind {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.kind(),
+            WorkerProviderBackend::Static(provider) => provider.kind(),
+            WorkerProviderBackend::Mock(provider) => provider.kind(),
+        }
+    }
+
+    fn flavor(&self) -> String {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.flavor(),
+            WorkerProviderBackend::Static(provider) => provider.flavor(),
+            WorkerProviderBackend::Mock(provider) => provider.flavor(),
+        }
+    }
+
+    async fn deploy(&self, xnode_owner: String) -> Result<Value, WorkerProviderError> {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.deploy(xnode_owner).await,
+            WorkerProviderBackend::Static(provider) => provider.deploy(xnode_owner).await,
+            WorkerProviderBackend::Mock(provider) => provider.deploy(xnode_owner).await,
+        }
+    }
+
+    async fn undeploy(&self, handle: &Value) -> Result<(), WorkerProviderError> {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.undeploy(handle).await,
+            WorkerProviderBackend::Static(provider) => provider.undeploy(handle).await,
+            WorkerProviderBackend::Mock(provider) => provider.undeploy(handle).await,
+        }
+    }
+
+    async fn ipv4(&self, handle: &Value) -> Result<Option<String>, WorkerProviderError> {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.ipv4(handle).await,
+            WorkerProviderBackend::Static(provider) => provider.ipv4(handle).await,
+            WorkerProviderBackend::Mock(provider) => provider.ipv4(handle).await,
+        }
+    }
+
+    async fn manager(&self, handle: &Value) -> Result<Option<WorkerManager>, WorkerProviderError> {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.manager(handle).await,
+            WorkerProviderBackend::Static(provider) => provider.manager(handle).await,
+            WorkerProviderBackend::Mock(provider) => provider.manager(handle).await,
+        }
+    }
+
+    async fn status(&self, handle: &Value) -> Result<WorkerStatus, WorkerProviderError> {
+        match self {
+            WorkerProviderBackend::Hyperstack(provider) => provider.status(handle).await,
+            WorkerProviderBackend::Static(provider) => provider.status(handle).await,
+            WorkerProviderBackend::Mock(provider) => provider.status(handle).await,
+        }
+    }
+}
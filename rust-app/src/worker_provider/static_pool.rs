@@ -0,0 +1,69 @@
+use std::net::Ipv4Addr;
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::{
+    database::worker_servers::WorkerProviderKind,
+    utils::auth::get_session,
+    worker_provider::{WorkerManager, WorkerProvider, WorkerProviderError, WorkerStatus},
+};
+
+/// Handle of a self-owned xnode, registered by the url of its manager
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct StaticHandle {
+    pub url: String,
+    pub domain: String,
+}
+
+/// Self-owned xnodes that are always running, they can not be deployed or undeployed by the factory
+pub struct StaticPool;
+
+fn static_handle(handle: &Value) -> Result<StaticHandle, WorkerProviderError> {
+    Ok(serde_json::from_value(handle.clone())?)
+}
+
+impl WorkerProvider for StaticPool {
+    fn kind(&self) -> WorkerProviderKind {
+        WorkerProviderKind::Static
+    }
+
+    fn flavor(&self) -> String {
+        "static".to_string()
+    }
+
+    async fn deploy(&self, _xnode_owner: String) -> Result<Value, WorkerProviderError> {
+        Err(WorkerProviderError::NotSupported)
+    }
+
+    async fn undeploy(&self, _handle: &Value) -> Result<(), WorkerProviderError> {
+        Err(WorkerProviderError::NotSupported)
+    }
+
+    async fn ipv4(&self, handle: &Value) -> Result<Option<String>, WorkerProviderError> {
+        let handle = static_handle(handle)?;
+
+        Ok(reqwest::Url::parse(&handle.url)
+            .ok()
+            .and_then(|url| url.host_str().map(|host| host.to_string()))
+            .filter(|host| host.parse::<Ipv4Addr>().is_ok()))
+    }
+
+    async fn manager(&self, handle: &Value) -> Result<Option<WorkerManager>, WorkerProviderError> {
+        let handle = static_handle(handle)?;
+
+        Ok(Some(WorkerManager {
+            url: handle.url,
+            domain: handle.domain,
+        }))
+    }
+
+    async fn status(&self, handle: &Value) -> Result<WorkerStatus, WorkerProviderError> {
+        let handle = static_handle(handle)?;
+
+        match get_session(&handle.url, &handle.domain).await {
+            Ok(_) => Ok(WorkerStatus::Running),
+            Err(_) => Ok(WorkerStatus::Unreachable),
+        }
+    }
+}